@@ -0,0 +1,11 @@
+CREATE TABLE threads (
+    id         BIGINT PRIMARY KEY,
+    group_id   INT NOT NULL REFERENCES groups(id)   ON DELETE CASCADE,
+    channel_id INT NOT NULL REFERENCES channels(id) ON DELETE CASCADE,
+    name       TEXT NOT NULL,
+    created_by INT NOT NULL REFERENCES users(id)    ON DELETE CASCADE,
+    archived   BOOLEAN NOT NULL DEFAULT false,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX idx_threads_group_id ON threads(group_id);
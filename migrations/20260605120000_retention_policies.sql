@@ -0,0 +1,16 @@
+ALTER TABLE groups
+    ADD COLUMN retention_max_age   INT CHECK (retention_max_age > 0),
+    ADD COLUMN retention_max_count INT CHECK (retention_max_count > 0);
+
+ALTER TABLE channels
+    ADD COLUMN retention_max_age   INT CHECK (retention_max_age > 0),
+    ADD COLUMN retention_max_count INT CHECK (retention_max_count > 0);
+
+CREATE TABLE dm_retention (
+    user1     INT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    user2     INT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    max_age   INT CHECK (max_age > 0),
+    max_count INT CHECK (max_count > 0),
+    PRIMARY KEY (user1, user2),
+    CHECK (user1 < user2)
+);
@@ -0,0 +1,2 @@
+ALTER TABLE channels
+    ADD COLUMN slow_mode INT NOT NULL DEFAULT 0 CHECK (slow_mode >= 0);
@@ -0,0 +1,14 @@
+CREATE TABLE polls (
+    message_id BIGINT PRIMARY KEY,
+    expires_at TIMESTAMPTZ,
+    closed     BOOLEAN NOT NULL DEFAULT false
+);
+CREATE INDEX idx_polls_expires_at ON polls(expires_at) WHERE NOT closed;
+
+CREATE TABLE poll_votes (
+    message_id BIGINT   NOT NULL REFERENCES polls(message_id) ON DELETE CASCADE,
+    user_id    INT      NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    option     SMALLINT NOT NULL CHECK (option >= 0),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (message_id, user_id, option)
+);
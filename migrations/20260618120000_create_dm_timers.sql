@@ -0,0 +1,7 @@
+CREATE TABLE dm_timers (
+    user1 INT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    user2 INT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    ttl   INT NOT NULL CHECK (ttl > 0),
+    PRIMARY KEY (user1, user2),
+    CHECK (user1 < user2)
+);
@@ -0,0 +1 @@
+ALTER TABLE reactions ALTER COLUMN reaction TYPE VARCHAR(64);
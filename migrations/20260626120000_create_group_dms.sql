@@ -0,0 +1,22 @@
+-- Group DM ids count down from -1 so they never collide with user ids in DM lists
+CREATE SEQUENCE group_dm_ids AS INT INCREMENT BY -1 MINVALUE -2147483648 MAXVALUE -1 START WITH -1;
+
+CREATE TABLE group_dms (
+    id         INT PRIMARY KEY DEFAULT nextval('group_dm_ids'),
+    name       TEXT,
+    icon       TEXT,
+    owner_id   INT NOT NULL REFERENCES users(id),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    CHECK (id < 0)
+);
+
+ALTER SEQUENCE group_dm_ids OWNED BY group_dms.id;
+
+CREATE TABLE group_dm_members (
+    group_dm_id INT NOT NULL REFERENCES group_dms(id) ON DELETE CASCADE,
+    user_id     INT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    joined_at   TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (group_dm_id, user_id)
+);
+
+CREATE INDEX idx_group_dm_members_user_id ON group_dm_members(user_id);
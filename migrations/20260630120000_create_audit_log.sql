@@ -0,0 +1,12 @@
+CREATE TABLE audit_log (
+    id         BIGSERIAL PRIMARY KEY,
+    group_id   INT NOT NULL REFERENCES groups(id) ON DELETE CASCADE,
+    actor_id   INT     NULL REFERENCES users(id)  ON DELETE SET NULL,
+    action     SMALLINT NOT NULL,
+    target_id  INT     NULL,
+    changes    JSONB NOT NULL DEFAULT '[]',
+    reason     TEXT    NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX idx_audit_log_group_id ON audit_log(group_id, id DESC);
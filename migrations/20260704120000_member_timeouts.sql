@@ -0,0 +1,4 @@
+ALTER TABLE group_users ADD COLUMN timeout_until TIMESTAMPTZ;
+
+CREATE INDEX idx_group_users_timeout_until ON group_users(timeout_until)
+    WHERE timeout_until IS NOT NULL;
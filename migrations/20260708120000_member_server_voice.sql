@@ -0,0 +1,3 @@
+ALTER TABLE group_users
+    ADD COLUMN server_mute BOOLEAN NOT NULL DEFAULT false,
+    ADD COLUMN server_deafen BOOLEAN NOT NULL DEFAULT false;
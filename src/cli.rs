@@ -0,0 +1,54 @@
+use crate::db::backup::BACKUP_DIR;
+use crate::db::message::{MESSAGE_STORE_PATH, MessageStore};
+use anyhow::{Context, Result};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+
+const USAGE: &str = "Usage:
+    ThisCrow                        run the server
+    ThisCrow backup                 back up the message store
+    ThisCrow restore [backup_id]    restore the latest or the given backup
+    ThisCrow export <file>          export all messages as msgpack
+    ThisCrow import <file>          import messages from an export";
+
+/// Runs a maintenance command instead of the server. The server must be stopped,
+/// except for `export` which reads the store without locking it.
+pub async fn run(command: &str, args: &[String]) -> Result<()> {
+    match (command, args) {
+        ("backup", []) => {
+            let store = MessageStore::open(MESSAGE_STORE_PATH)?;
+            store.backup(BACKUP_DIR.clone(), usize::MAX).await?;
+            println!("Backed up to {}", BACKUP_DIR.display());
+        }
+        ("restore", [] | [_]) => {
+            let backup_id = args
+                .first()
+                .map(|backup_id| backup_id.parse())
+                .transpose()
+                .context("Invalid backup id")?;
+
+            MessageStore::restore(MESSAGE_STORE_PATH, BACKUP_DIR.as_path(), backup_id)?;
+            println!("Restored {}", MESSAGE_STORE_PATH);
+        }
+        ("export", [path]) => {
+            let store = MessageStore::open_read_only(MESSAGE_STORE_PATH)?;
+            let mut writer = BufWriter::new(File::create(path).context("Failed to create file")?);
+
+            let count = store.export(&mut writer)?;
+            writer.flush().context("Failed to flush export")?;
+
+            println!("Exported {} messages to {}", count, path);
+        }
+        ("import", [path]) => {
+            let store = MessageStore::open(MESSAGE_STORE_PATH)?;
+            let reader = BufReader::new(File::open(path).context("Failed to open file")?);
+
+            let count = store.import(reader)?;
+
+            println!("Imported {} messages from {}", count, path);
+        }
+        _ => anyhow::bail!("{}", USAGE),
+    }
+
+    Ok(())
+}
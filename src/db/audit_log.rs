@@ -0,0 +1,180 @@
+use crate::id::id;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::types::Json;
+use sqlx::{Pool, Postgres};
+
+pub const DEFAULT_PAGE_LEN: i64 = 50;
+pub const MAX_PAGE_LEN: i64 = 100;
+
+/// Stored as SMALLINT, so existing values must never be renumbered.
+#[derive(Serialize, Deserialize, sqlx::Type, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+#[repr(i16)]
+pub enum AuditAction {
+    UpdateGroup = 1,
+    CreateChannel = 2,
+    UpdateChannel = 3,
+    DeleteChannel = 4,
+    SetPermissionOverride = 5,
+    DeletePermissionOverride = 6,
+    CreateRole = 7,
+    UpdateRole = 8,
+    DeleteRole = 9,
+    AssignRole = 10,
+    RemoveRole = 11,
+    KickUser = 12,
+    BanUser = 13,
+    UnbanUser = 14,
+    CreateInvitation = 15,
+    DeleteInvitation = 16,
+    JoinInvitation = 17,
+    TimeoutUser = 18,
+    MuteMember = 19,
+    DeafenMember = 20,
+    MoveMember = 21,
+    DisconnectMember = 22,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AuditChange {
+    pub key: String,
+    pub before: Value,
+    pub after: Value,
+}
+
+impl AuditChange {
+    /// Pass `()` for a side that does not exist, e.g. the before of a created role.
+    pub fn new(key: &str, before: impl Serialize, after: impl Serialize) -> Self {
+        Self {
+            key: key.to_string(),
+            before: serde_json::to_value(before).unwrap_or(Value::Null),
+            after: serde_json::to_value(after).unwrap_or(Value::Null),
+        }
+    }
+}
+
+/// Like `AuditChange::new`, but None when both sides are equal.
+pub fn change(key: &str, before: impl Serialize, after: impl Serialize) -> Option<AuditChange> {
+    let change = AuditChange::new(key, before, after);
+    (change.before != change.after).then_some(change)
+}
+
+#[derive(Serialize, Debug)]
+pub struct AuditLogEntry {
+    pub id: i64,
+    pub actor: Option<id>,
+    pub action: AuditAction,
+    pub target: Option<id>,
+    pub changes: Vec<AuditChange>,
+    pub reason: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Deserialize, Default)]
+pub struct AuditLogFilter {
+    pub actor: Option<id>,
+    pub target: Option<id>,
+    pub action: Option<AuditAction>,
+    pub before: Option<i64>,
+    pub len: Option<i64>,
+}
+
+/// Entries are written after the change they describe, so a failed insert is only
+/// logged and never fails the change itself.
+pub async fn record(
+    pool: &Pool<Postgres>,
+    group_id: id,
+    actor: id,
+    action: AuditAction,
+    target: Option<id>,
+    changes: Vec<AuditChange>,
+    reason: Option<String>,
+) {
+    if let Err(e) = insert(pool, group_id, actor, action, target, changes, reason).await {
+        log::error!("Error while recording audit log: {:?}", e);
+    }
+}
+
+async fn insert(
+    pool: &Pool<Postgres>,
+    group_id: id,
+    actor: id,
+    action: AuditAction,
+    target: Option<id>,
+    changes: Vec<AuditChange>,
+    reason: Option<String>,
+) -> Result<()> {
+    sqlx::query!(
+        r#"
+        INSERT INTO audit_log (group_id, actor_id, action, target_id, changes, reason)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        "#,
+        *group_id,
+        *actor,
+        action as i16,
+        target.map(|t| *t),
+        Json(changes) as _,
+        reason
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Newest entries first, `before` is the id of the last entry of the previous page.
+pub async fn get(
+    pool: &Pool<Postgres>,
+    group_id: id,
+    filter: AuditLogFilter,
+) -> Result<Vec<AuditLogEntry>> {
+    let len = filter
+        .len
+        .unwrap_or(DEFAULT_PAGE_LEN)
+        .clamp(1, MAX_PAGE_LEN);
+
+    let entries = sqlx::query!(
+        r#"
+        SELECT
+            id,
+            actor_id AS "actor: id",
+            action AS "action: AuditAction",
+            target_id AS "target: id",
+            changes AS "changes: Json<Vec<AuditChange>>",
+            reason,
+            created_at
+        FROM audit_log
+        WHERE group_id = $1
+            AND ($2::BIGINT IS NULL OR id < $2)
+            AND ($3::INT IS NULL OR actor_id = $3)
+            AND ($4::INT IS NULL OR target_id = $4)
+            AND ($5::SMALLINT IS NULL OR action = $5)
+        ORDER BY id DESC
+        LIMIT $6
+        "#,
+        *group_id,
+        filter.before,
+        filter.actor.map(|a| *a),
+        filter.target.map(|t| *t),
+        filter.action.map(|a| a as i16),
+        len
+    )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|row| AuditLogEntry {
+        id: row.id,
+        actor: row.actor,
+        action: row.action,
+        target: row.target,
+        changes: row.changes.0,
+        reason: row.reason,
+        created_at: row.created_at,
+    })
+    .collect();
+
+    Ok(entries)
+}
@@ -0,0 +1,64 @@
+use crate::State;
+use crate::db::message::{MESSAGE_STORE_PATH, MessageStore};
+use anyhow::Result;
+use once_cell::sync::Lazy;
+use std::env;
+use std::path::{Path, PathBuf};
+use tokio::time::{self, Duration, Instant};
+
+pub static BACKUP_DIR: Lazy<PathBuf> = Lazy::new(|| {
+    env::var("BACKUP_DIR")
+        .unwrap_or_else(|_| "data/backups".to_string())
+        .into()
+});
+
+static BACKUP_INTERVAL: Lazy<Duration> = Lazy::new(|| {
+    let hours = env::var("BACKUP_INTERVAL_HOURS")
+        .ok()
+        .and_then(|hours| hours.parse::<u64>().ok())
+        .unwrap_or(24)
+        .max(1);
+
+    Duration::from_secs(hours * 60 * 60)
+});
+
+static BACKUP_KEEP: Lazy<usize> = Lazy::new(|| {
+    env::var("BACKUP_KEEP")
+        .ok()
+        .and_then(|keep| keep.parse().ok())
+        .unwrap_or(7)
+});
+
+/// Restores the latest backup when the message store directory is missing.
+pub fn restore_if_missing() -> Result<()> {
+    if Path::new(MESSAGE_STORE_PATH).exists() || !BACKUP_DIR.exists() {
+        return Ok(());
+    }
+
+    log::warn!(
+        "Message store not found, restoring the latest backup from {}",
+        BACKUP_DIR.display()
+    );
+
+    MessageStore::restore(MESSAGE_STORE_PATH, BACKUP_DIR.as_path(), None)
+}
+
+pub async fn backup_schedular(state: State) {
+    let mut interval = time::interval_at(Instant::now() + *BACKUP_INTERVAL, *BACKUP_INTERVAL);
+
+    loop {
+        tokio::select! {
+            _ = state.shutdown.cancelled() => break,
+            _ = interval.tick() => {}
+        }
+
+        match state
+            .messages
+            .backup(BACKUP_DIR.clone(), *BACKUP_KEEP)
+            .await
+        {
+            Ok(()) => log::info!("Message store backed up to {}", BACKUP_DIR.display()),
+            Err(e) => log::error!("Error while backing up the message store: {:?}", e),
+        }
+    }
+}
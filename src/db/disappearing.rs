@@ -0,0 +1,120 @@
+use crate::id::id;
+use crate::message::snowflake::snowflake_id;
+use crate::message::{Ack, Message};
+use crate::{State, db};
+use anyhow::Result;
+use chrono::Utc;
+use sqlx::PgPool;
+use tokio::time::{self, Duration};
+
+const REAP_INTERVAL: Duration = Duration::from_secs(5);
+const MAX_REAP_BATCH: usize = 1000;
+
+pub const MIN_TTL: u32 = 5;
+pub const MAX_TTL: u32 = 7 * 24 * 60 * 60;
+
+pub fn validate_ttl(ttl: u32) -> Result<()> {
+    if !(MIN_TTL..=MAX_TTL).contains(&ttl) {
+        anyhow::bail!("Disappearing timer must be {}-{} seconds", MIN_TTL, MAX_TTL);
+    }
+
+    Ok(())
+}
+
+/// Sets the timer of a conversation, `None` turns disappearing messages off.
+pub async fn set_timer(pool: &PgPool, user1: id, user2: id, ttl: Option<u32>) -> Result<()> {
+    let (user1, user2) = user1.sort_pair(user2);
+
+    match ttl {
+        Some(ttl) => {
+            sqlx::query!(
+                "INSERT INTO dm_timers (user1, user2, ttl) VALUES ($1, $2, $3) \
+                 ON CONFLICT (user1, user2) DO UPDATE SET ttl = $3",
+                *user1,
+                *user2,
+                ttl as i32,
+            )
+            .execute(pool)
+            .await?;
+        }
+        None => {
+            sqlx::query!(
+                "DELETE FROM dm_timers WHERE user1 = $1 AND user2 = $2",
+                *user1,
+                *user2,
+            )
+            .execute(pool)
+            .await?;
+        }
+    }
+
+    Ok(())
+}
+
+pub async fn get_timer(pool: &PgPool, user1: id, user2: id) -> Result<Option<u32>> {
+    let (user1, user2) = user1.sort_pair(user2);
+
+    let ttl = sqlx::query_scalar!(
+        "SELECT ttl FROM dm_timers WHERE user1 = $1 AND user2 = $2",
+        *user1,
+        *user2,
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(ttl.map(|ttl| ttl as u32))
+}
+
+async fn reap_expired(state: &State) -> Result<()> {
+    loop {
+        let expired = state
+            .messages
+            .delete_expired(Utc::now(), MAX_REAP_BATCH)
+            .await?;
+
+        if expired.is_empty() {
+            return Ok(());
+        }
+
+        let message_ids: Vec<snowflake_id> = expired.iter().map(|message| message.id).collect();
+
+        db::reaction::delete_for_messages(&state.pool, &message_ids).await?;
+        db::poll::delete_for_messages(&state.pool, &message_ids).await?;
+
+        for message in &expired {
+            let ack = Message {
+                id: state.snowflake.generate(),
+                data: Ack::Deleted(message.id),
+                ..Default::default()
+            };
+
+            if let Some(u) = state.users.get(&message.from) {
+                u.send_message(ack.clone());
+            }
+            if message.to != message.from
+                && let Some(u) = state.users.get(&message.to)
+            {
+                u.send_message(ack);
+            }
+        }
+
+        if expired.len() < MAX_REAP_BATCH {
+            return Ok(());
+        }
+    }
+}
+
+pub async fn reap_schedular(state: State) {
+    let mut interval = time::interval(REAP_INTERVAL);
+
+    loop {
+        tokio::select! {
+            _ = state.shutdown.cancelled() => break,
+            _ = interval.tick() => {}
+        }
+
+        if let Err(e) = reap_expired(&state).await {
+            log::error!("Error while deleting expired messages: {:?}", e);
+        }
+    }
+}
@@ -1,8 +1,12 @@
 use std::collections::{HashMap, HashSet};
 
+use crate::db::retention::Retention;
+use crate::message::snowflake::snowflake_id;
 use crate::state::{
     self,
-    group::{Channel, ChannelType, Member, OverrideTarget, PermissionOverride, Permissions, Role},
+    group::{
+        Channel, ChannelType, Member, OverrideTarget, PermissionOverride, Permissions, Role, Thread,
+    },
 };
 
 fn override_target_ids(target: &OverrideTarget) -> (Option<i32>, Option<i32>) {
@@ -30,7 +34,10 @@ pub struct Group {
 
 pub async fn init_group(pool: &Pool<Postgres>, group_id: id) -> Result<state::Group, sqlx::Error> {
     let group = sqlx::query!(
-        r#"SELECT id, icon, name, created_by, everyone_permissions FROM groups WHERE id = $1"#,
+        r#"
+            SELECT id, icon, name, created_by, everyone_permissions, retention_max_age, retention_max_count
+            FROM groups WHERE id = $1
+        "#,
         *group_id
     )
     .fetch_one(pool)
@@ -123,6 +130,9 @@ pub async fn init_group(pool: &Pool<Postgres>, group_id: id) -> Result<state::Gr
                         row.position as usize,
                         c_type,
                         channel_overrides.remove(&row.id).unwrap_or_default(),
+                        Retention::from_row(row.retention_max_age, row.retention_max_count),
+                        row.slow_mode as u32,
+                        HashMap::new(),
                     ),
                 )
             })
@@ -130,7 +140,9 @@ pub async fn init_group(pool: &Pool<Postgres>, group_id: id) -> Result<state::Gr
 
     let members: HashMap<id, Member> = sqlx::query!(
         r#"
-            SELECT gu.user_id, gu.name, gur.role_id AS "role_id?"
+            SELECT
+                gu.user_id, gu.name, gu.timeout_until, gu.server_mute, gu.server_deafen,
+                gur.role_id AS "role_id?"
             FROM group_users gu
             LEFT JOIN group_user_roles gur 
             ON gu.group_id = gur.group_id AND gu.user_id = gur.user_id
@@ -141,21 +153,24 @@ pub async fn init_group(pool: &Pool<Postgres>, group_id: id) -> Result<state::Gr
     .fetch_all(pool)
     .await?
     .into_iter()
-    .fold(
-        HashMap::<id, (Option<String>, Vec<id>)>::new(),
-        |mut acc, row| {
-            let uid = id::from(row.user_id);
-            let entry = acc.entry(uid).or_insert((row.name, Vec::new()));
-
-            if let Some(rid) = row.role_id {
-                entry.1.push(id::from(rid));
-            }
+    .fold(HashMap::new(), |mut acc: HashMap<id, (_, Vec<id>)>, row| {
+        let uid = id::from(row.user_id);
+        let voice = (row.server_mute, row.server_deafen);
+        let entry = acc
+            .entry(uid)
+            .or_insert(((row.name, row.timeout_until, voice), Vec::new()));
+
+        if let Some(rid) = row.role_id {
+            entry.1.push(id::from(rid));
+        }
 
-            acc
-        },
-    )
+        acc
+    })
     .into_iter()
-    .map(|(uid, (name, roles))| (uid, Member::new(uid, name, roles)))
+    .map(|(uid, ((name, timeout_until, (mute, deafen)), roles))| {
+        let member = Member::new(uid, name, roles, timeout_until, mute, deafen);
+        (uid, member)
+    })
     .collect();
 
     let bans: HashSet<id> = sqlx::query_scalar!(
@@ -168,6 +183,29 @@ pub async fn init_group(pool: &Pool<Postgres>, group_id: id) -> Result<state::Gr
     .map(id::from)
     .collect();
 
+    let threads: HashMap<snowflake_id, Thread> = sqlx::query!(
+        r#"SELECT id, channel_id, name, created_by, archived FROM threads WHERE group_id = $1"#,
+        *group_id,
+    )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|row| {
+        let thread_id = snowflake_id(row.id as u64);
+        (
+            thread_id,
+            Thread::new(
+                thread_id,
+                id::from(row.channel_id),
+                row.name,
+                id::from(row.created_by),
+                row.archived,
+                HashSet::new(),
+            ),
+        )
+    })
+    .collect();
+
     Ok(state::Group::new(
         group_id,
         group.icon,
@@ -179,6 +217,8 @@ pub async fn init_group(pool: &Pool<Postgres>, group_id: id) -> Result<state::Gr
         Permissions::from_bits_truncate(group.everyone_permissions as u64),
         HashSet::new(),
         bans,
+        threads,
+        Retention::from_row(group.retention_max_age, group.retention_max_count),
     ))
 }
 
@@ -340,6 +380,7 @@ pub async fn update_channel(
     name: Option<String>,
     title: Option<String>,
     position: Option<usize>,
+    slow_mode: Option<u32>,
 ) -> Result<(), sqlx::Error> {
     let mut tx = pool.begin().await?;
 
@@ -391,13 +432,15 @@ pub async fn update_channel(
     sqlx::query!(
         r#"
         UPDATE channels 
-        SET name = COALESCE($2, name), title = $3, position = COALESCE($4, position)
+        SET name = COALESCE($2, name), title = $3, position = COALESCE($4, position),
+            slow_mode = COALESCE($5, slow_mode)
         WHERE id = $1
         "#,
         *channel_id,
         name,
         title,
         position.map(|p| p as i16),
+        slow_mode.map(|s| s as i32),
     )
     .execute(&mut *tx)
     .await?;
@@ -750,6 +793,42 @@ pub async fn update_group_user_position(
     Ok(())
 }
 
+pub async fn set_server_mute(
+    pool: &Pool<Postgres>,
+    group_id: id,
+    user_id: id,
+    mute: bool,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"UPDATE group_users SET server_mute = $3 WHERE group_id = $1 AND user_id = $2"#,
+        *group_id,
+        *user_id,
+        mute,
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn set_server_deafen(
+    pool: &Pool<Postgres>,
+    group_id: id,
+    user_id: id,
+    deafen: bool,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"UPDATE group_users SET server_deafen = $3 WHERE group_id = $1 AND user_id = $2"#,
+        *group_id,
+        *user_id,
+        deafen,
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
 pub async fn remove_member(
     pool: &Pool<Postgres>,
     group_id: id,
@@ -792,6 +871,49 @@ pub async fn is_member(
     Ok(exists)
 }
 
+/* ===== THREAD ===== */
+
+pub async fn create_thread(
+    pool: &Pool<Postgres>,
+    thread_id: snowflake_id,
+    group_id: id,
+    channel_id: id,
+    name: &str,
+    created_by: id,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO threads (id, group_id, channel_id, name, created_by)
+        VALUES ($1, $2, $3, $4, $5)
+        "#,
+        *thread_id as i64,
+        *group_id,
+        *channel_id,
+        name,
+        *created_by,
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn set_thread_archived(
+    pool: &Pool<Postgres>,
+    thread_id: snowflake_id,
+    archived: bool,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"UPDATE threads SET archived = $2 WHERE id = $1"#,
+        *thread_id as i64,
+        archived,
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
 /* ===== BANS ===== */
 
 #[derive(sqlx::FromRow, serde::Serialize)]
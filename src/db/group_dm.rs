@@ -0,0 +1,527 @@
+use crate::id::id;
+use crate::message::data::{Data, SystemEntry};
+use crate::message::snowflake::snowflake_id;
+use crate::message::{Ack, Message, MessageType};
+use crate::{State, msgpack};
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use std::collections::HashSet;
+
+pub const MAX_GROUP_DM_MEMBERS: usize = 10;
+const MAX_NAME_CHARS: usize = 100;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GroupDm {
+    pub id: id,
+    pub name: Option<String>,
+    pub icon: Option<String>,
+    pub owner: id,
+    pub members: HashSet<id>,
+}
+
+/// Trims the name, an empty one clears it.
+fn validate_name(name: Option<String>) -> Result<Option<String>> {
+    let name = name
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty());
+
+    if name
+        .as_ref()
+        .is_some_and(|name| name.chars().count() > MAX_NAME_CHARS)
+    {
+        anyhow::bail!(
+            "Group DM name must be at most {} characters",
+            MAX_NAME_CHARS
+        );
+    }
+
+    Ok(name)
+}
+
+async fn insert(pool: &PgPool, owner: id, name: Option<&str>, members: &[id]) -> Result<id> {
+    let mut tx = pool.begin().await?;
+
+    let group_dm_id = sqlx::query_scalar!(
+        r#"INSERT INTO group_dms (name, owner_id) VALUES ($1, $2) RETURNING id AS "id: id""#,
+        name,
+        *owner,
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    let members: Vec<i32> = members.iter().map(|member| member.0).collect();
+
+    sqlx::query!(
+        "INSERT INTO group_dm_members (group_dm_id, user_id) SELECT $1, UNNEST($2::INT[])",
+        *group_dm_id,
+        &members,
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+    Ok(group_dm_id)
+}
+
+async fn fetch(pool: &PgPool, group_dm_id: id) -> Result<GroupDm> {
+    let row = sqlx::query!(
+        r#"SELECT name, icon, owner_id AS "owner_id: id" FROM group_dms WHERE id = $1"#,
+        *group_dm_id,
+    )
+    .fetch_optional(pool)
+    .await?
+    .context("Group DM not found")?;
+
+    let members = sqlx::query_scalar!(
+        r#"SELECT user_id AS "user_id: id" FROM group_dm_members WHERE group_dm_id = $1"#,
+        *group_dm_id,
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(GroupDm {
+        id: group_dm_id,
+        name: row.name,
+        icon: row.icon,
+        owner: row.owner_id,
+        members: members.into_iter().collect(),
+    })
+}
+
+async fn add_member(pool: &PgPool, group_dm_id: id, user_id: id) -> Result<()> {
+    sqlx::query!(
+        "INSERT INTO group_dm_members (group_dm_id, user_id) VALUES ($1, $2) ON CONFLICT DO NOTHING",
+        *group_dm_id,
+        *user_id,
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Removes a member and hands the ownership to the longest standing member when
+/// the owner leaves. Returns the owner afterwards, none once nobody is left.
+async fn remove_member(pool: &PgPool, group_dm_id: id, user_id: id) -> Result<Option<id>> {
+    let mut tx = pool.begin().await?;
+
+    sqlx::query!(
+        "DELETE FROM group_dm_members WHERE group_dm_id = $1 AND user_id = $2",
+        *group_dm_id,
+        *user_id,
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    let owner = sqlx::query_scalar!(
+        r#"SELECT m.user_id AS "user_id: id" FROM group_dm_members m
+           JOIN group_dms g ON g.id = m.group_dm_id
+           WHERE m.group_dm_id = $1
+           ORDER BY m.user_id = g.owner_id DESC, m.joined_at, m.user_id
+           LIMIT 1"#,
+        *group_dm_id,
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    match owner {
+        Some(owner) => {
+            sqlx::query!(
+                "UPDATE group_dms SET owner_id = $2 WHERE id = $1",
+                *group_dm_id,
+                *owner,
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+        None => {
+            sqlx::query!("DELETE FROM group_dms WHERE id = $1", *group_dm_id)
+                .execute(&mut *tx)
+                .await?;
+        }
+    }
+
+    tx.commit().await?;
+    Ok(owner)
+}
+
+async fn update(
+    pool: &PgPool,
+    group_dm_id: id,
+    name: Option<&str>,
+    icon: Option<&str>,
+) -> Result<()> {
+    sqlx::query!(
+        "UPDATE group_dms SET name = $2, icon = $3 WHERE id = $1",
+        *group_dm_id,
+        name,
+        icon,
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Loads a group DM into the cache if needed and returns it.
+pub async fn get(state: &State, group_dm_id: id) -> Result<GroupDm> {
+    if let Some(group_dm) = state.group_dms.get(&group_dm_id) {
+        return Ok(group_dm.clone());
+    }
+
+    let _write_guard = state.group_locks.write(group_dm_id).await;
+
+    if let Some(group_dm) = state.group_dms.get(&group_dm_id) {
+        return Ok(group_dm.clone());
+    }
+
+    let group_dm = fetch(&state.pool, group_dm_id).await?;
+    state.group_dms.insert(group_dm_id, group_dm.clone());
+
+    Ok(group_dm)
+}
+
+/// Group DMs the user is a member of.
+pub async fn for_user(state: &State, user_id: id) -> Result<Vec<GroupDm>> {
+    let group_dm_ids = sqlx::query_scalar!(
+        r#"SELECT group_dm_id AS "group_dm_id: id" FROM group_dm_members WHERE user_id = $1"#,
+        *user_id,
+    )
+    .fetch_all(&state.pool)
+    .await?;
+
+    let mut group_dms = Vec::with_capacity(group_dm_ids.len());
+
+    for group_dm_id in group_dm_ids {
+        group_dms.push(get(state, group_dm_id).await?);
+    }
+
+    Ok(group_dms)
+}
+
+/// Users a direct message is shared with, the pair or the members of a group DM.
+pub async fn participants(state: &State, from: id, to: id) -> Result<Vec<id>> {
+    if !to.is_group_dm() {
+        return Ok(if from == to {
+            vec![from]
+        } else {
+            vec![from, to]
+        });
+    }
+
+    Ok(get(state, to).await?.members.into_iter().collect())
+}
+
+/// Members of a cached group DM, empty when it isn't loaded.
+pub fn members(state: &State, group_dm_id: id) -> Vec<id> {
+    state
+        .group_dms
+        .get(&group_dm_id)
+        .map(|group_dm| group_dm.members.iter().copied().collect())
+        .unwrap_or_default()
+}
+
+/// Sends to every member of a cached group DM but `skip`.
+pub fn send_to_members<T: Serialize>(
+    state: &State,
+    group_dm_id: id,
+    message: Message<T>,
+    skip: Option<id>,
+) {
+    let is_message = matches!(message.r#type, MessageType::Direct);
+    let bytes = Bytes::from(msgpack!(message));
+
+    for member in members(state, group_dm_id) {
+        if Some(member) == skip {
+            continue;
+        }
+
+        if let Some(mut user) = state.users.get_mut(&member) {
+            if is_message {
+                user.state.dms.insert(group_dm_id);
+            }
+            user.send_bytes(bytes.clone());
+        }
+    }
+}
+
+/// Writes a system entry into the conversation and sends it to the members.
+async fn write_entry(state: &State, from: id, group_dm_id: id, entry: SystemEntry) -> Result<()> {
+    let entry = Message {
+        id: state.snowflake.generate(),
+        from,
+        to: group_dm_id,
+        data: Data::System(entry),
+        r#type: MessageType::Direct,
+        ..Message::default()
+    };
+
+    state.messages.write(entry.clone().try_into()?).await?;
+    state
+        .messages
+        .touch_dms(members(state, group_dm_id), group_dm_id, entry.id)
+        .await?;
+
+    send_to_members(state, group_dm_id, entry, None);
+
+    Ok(())
+}
+
+fn is_friend(state: &State, user_id: id, other: id) -> bool {
+    state
+        .users
+        .get(&user_id)
+        .is_some_and(|user| user.state.friends.contains(&other))
+}
+
+pub async fn handle_create(
+    state: &State,
+    event_id: snowflake_id,
+    user_id: id,
+    name: Option<String>,
+    members: Vec<id>,
+) -> Result<()> {
+    let name = validate_name(name)?;
+
+    let mut members: HashSet<id> = members.into_iter().collect();
+    members.remove(&user_id);
+
+    if members.is_empty() {
+        anyhow::bail!("Group DM needs at least one other member");
+    }
+
+    if members.len() + 1 > MAX_GROUP_DM_MEMBERS {
+        anyhow::bail!("Group DM can have at most {} members", MAX_GROUP_DM_MEMBERS);
+    }
+
+    if members
+        .iter()
+        .any(|&member| !is_friend(state, user_id, member))
+    {
+        anyhow::bail!("You can only add friends to a group DM");
+    }
+
+    members.insert(user_id);
+    let member_ids: Vec<id> = members.iter().copied().collect();
+
+    let group_dm_id = insert(&state.pool, user_id, name.as_deref(), &member_ids).await?;
+
+    let group_dm = GroupDm {
+        id: group_dm_id,
+        name,
+        icon: None,
+        owner: user_id,
+        members,
+    };
+
+    state.group_dms.insert(group_dm_id, group_dm.clone());
+
+    let ack = Message {
+        id: event_id,
+        from: user_id,
+        to: group_dm_id,
+        data: Ack::JoinedGroupDm(group_dm),
+        ..Message::default()
+    };
+
+    send_to_members(state, group_dm_id, ack, None);
+
+    write_entry(state, user_id, group_dm_id, SystemEntry::GroupDmCreated).await
+}
+
+pub async fn handle_add(
+    state: &State,
+    event_id: snowflake_id,
+    user_id: id,
+    group_dm_id: id,
+    new_member: id,
+) -> Result<()> {
+    get(state, group_dm_id).await?;
+
+    let _write_guard = state.group_locks.write(group_dm_id).await;
+
+    let group_dm = state
+        .group_dms
+        .get(&group_dm_id)
+        .map(|group_dm| group_dm.clone())
+        .context("Group DM not found")?;
+
+    if !group_dm.members.contains(&user_id) {
+        anyhow::bail!("You are not a member of this group DM");
+    }
+
+    if group_dm.members.contains(&new_member) {
+        anyhow::bail!("User is already a member of this group DM");
+    }
+
+    if group_dm.members.len() >= MAX_GROUP_DM_MEMBERS {
+        anyhow::bail!("Group DM can have at most {} members", MAX_GROUP_DM_MEMBERS);
+    }
+
+    if !is_friend(state, user_id, new_member) {
+        anyhow::bail!("You can only add friends to a group DM");
+    }
+
+    add_member(&state.pool, group_dm_id, new_member).await?;
+
+    let group_dm = {
+        let Some(mut group_dm) = state.group_dms.get_mut(&group_dm_id) else {
+            anyhow::bail!("Group DM not found");
+        };
+        group_dm.members.insert(new_member);
+        group_dm.clone()
+    };
+
+    let ack = Message {
+        id: event_id,
+        from: user_id,
+        to: group_dm_id,
+        data: Ack::AddedGroupDmMember(new_member),
+        ..Message::default()
+    };
+
+    send_to_members(state, group_dm_id, ack, Some(new_member));
+
+    if let Some(mut user) = state.users.get_mut(&new_member) {
+        user.state.dms.insert(group_dm_id);
+        user.send_message(Message {
+            id: event_id,
+            from: user_id,
+            to: group_dm_id,
+            data: Ack::JoinedGroupDm(group_dm),
+            ..Message::default()
+        });
+    }
+
+    write_entry(
+        state,
+        user_id,
+        group_dm_id,
+        SystemEntry::MemberAdded { user: new_member },
+    )
+    .await
+}
+
+/// Kicks `member` when sent by the owner, or leaves when `member` is the sender.
+pub async fn handle_remove(
+    state: &State,
+    event_id: snowflake_id,
+    user_id: id,
+    group_dm_id: id,
+    member: id,
+) -> Result<()> {
+    get(state, group_dm_id).await?;
+
+    let _write_guard = state.group_locks.write(group_dm_id).await;
+
+    let group_dm = state
+        .group_dms
+        .get(&group_dm_id)
+        .map(|group_dm| group_dm.clone())
+        .context("Group DM not found")?;
+
+    if !group_dm.members.contains(&member) {
+        anyhow::bail!("User is not a member of this group DM");
+    }
+
+    if member != user_id && group_dm.owner != user_id {
+        anyhow::bail!("Only the owner can remove members");
+    }
+
+    let owner = remove_member(&state.pool, group_dm_id, member).await?;
+
+    match owner {
+        Some(owner) => {
+            if let Some(mut group_dm) = state.group_dms.get_mut(&group_dm_id) {
+                group_dm.members.remove(&member);
+                group_dm.owner = owner;
+            }
+        }
+        None => {
+            state.group_dms.remove(&group_dm_id);
+        }
+    }
+
+    state.messages.remove_dm(member, group_dm_id).await?;
+
+    let ack = Message {
+        id: event_id,
+        from: user_id,
+        to: group_dm_id,
+        data: Ack::RemovedGroupDmMember {
+            user: member,
+            owner,
+        },
+        ..Message::default()
+    };
+
+    if let Some(mut user) = state.users.get_mut(&member) {
+        user.state.dms.remove(&group_dm_id);
+        user.send_message(ack.clone());
+    }
+
+    if owner.is_none() {
+        return Ok(());
+    }
+
+    send_to_members(state, group_dm_id, ack, None);
+
+    write_entry(
+        state,
+        user_id,
+        group_dm_id,
+        SystemEntry::MemberRemoved { user: member },
+    )
+    .await
+}
+
+pub async fn handle_update(
+    state: &State,
+    event_id: snowflake_id,
+    user_id: id,
+    group_dm_id: id,
+    name: Option<String>,
+    icon: Option<String>,
+) -> Result<()> {
+    get(state, group_dm_id).await?;
+
+    let _write_guard = state.group_locks.write(group_dm_id).await;
+
+    let group_dm = state
+        .group_dms
+        .get(&group_dm_id)
+        .map(|group_dm| group_dm.clone())
+        .context("Group DM not found")?;
+
+    if !group_dm.members.contains(&user_id) {
+        anyhow::bail!("You are not a member of this group DM");
+    }
+
+    let name = match name {
+        Some(name) => validate_name(Some(name))?,
+        None => group_dm.name,
+    };
+    let icon = match icon {
+        Some(icon) => Some(icon).filter(|icon| !icon.is_empty()),
+        None => group_dm.icon,
+    };
+
+    update(&state.pool, group_dm_id, name.as_deref(), icon.as_deref()).await?;
+
+    if let Some(mut group_dm) = state.group_dms.get_mut(&group_dm_id) {
+        group_dm.name = name.clone();
+        group_dm.icon = icon.clone();
+    }
+
+    let ack = Message {
+        id: event_id,
+        from: user_id,
+        to: group_dm_id,
+        data: Ack::UpdatedGroupDm { name, icon },
+        ..Message::default()
+    };
+
+    send_to_members(state, group_dm_id, ack, None);
+
+    Ok(())
+}
@@ -1,18 +1,53 @@
+use crate::db::poll::PollResults;
+use crate::db::reaction::ReactionSummary;
 use crate::id::id;
 use crate::message::snowflake::snowflake_id;
 use crate::message::{Data, Message, MessageType};
 use anyhow::{Context, Result};
-use rocksdb::{ColumnFamilyDescriptor, DBWithThreadMode, MultiThreaded, Options};
+use chrono::{DateTime, Utc};
+use parking_lot::Mutex;
+use rocksdb::backup::{BackupEngine, BackupEngineOptions, RestoreOptions};
+use rocksdb::{ColumnFamilyDescriptor, DBWithThreadMode, Env, MultiThreaded, Options};
 use serde::{Deserialize, Serialize};
-use std::path::Path;
+use std::collections::{HashMap, HashSet};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 pub type RocksDB = DBWithThreadMode<MultiThreaded>;
 
+pub const MESSAGE_STORE_PATH: &str = "data/messages";
+
 const CF_MESSAGES: &str = "messages";
 const CF_DM_INDEX: &str = "dm_index";
 const CF_CHANNEL_INDEX: &str = "channel_index";
 const CF_USER_DMS: &str = "user_dms";
+const CF_SEARCH_INDEX: &str = "search_index";
+const CF_REVISIONS: &str = "revisions";
+const CF_THREAD_INDEX: &str = "thread_index";
+const CF_CHANNEL_PINS: &str = "channel_pins";
+const CF_DM_PINS: &str = "dm_pins";
+const CF_MENTION_INDEX: &str = "mention_index";
+const CF_READ_STATES: &str = "read_states";
+const CF_SCHEDULED: &str = "scheduled";
+const CF_EXPIRY_INDEX: &str = "expiry_index";
+const CF_USER_SCHEDULED: &str = "user_scheduled";
+
+pub const MAX_PINS: usize = 50;
+
+const MIN_SEARCH_TERM_CHARS: usize = 2;
+const MAX_SEARCH_TERM_BYTES: usize = 64;
+const MAX_SEARCH_SCAN: usize = 10_000;
+const MAX_PURGE_BATCH: usize = 10_000;
+
+pub const MAX_BULK_DELETE: usize = 1000;
+const MAX_BULK_SCAN: usize = MAX_BULK_DELETE * 10;
+
+const DEFAULT_PAGE_LEN: i64 = 50;
+const MAX_PAGE_LEN: i64 = 100;
+
+/// Unread and mention counts stop here; clients show it as "N+".
+pub const MAX_UNREAD_COUNT: usize = 1000;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct StoredMessage {
@@ -25,6 +60,56 @@ pub struct StoredMessage {
     pub overwrited: Option<bool>,
     #[serde(skip_serializing_if = "Option::is_none", default)]
     pub reacted: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub thread: Option<snowflake_id>,
+    /// Current tally of a poll, only filled in when history is fetched.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub poll: Option<PollResults>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub expires_at: Option<DateTime<Utc>>,
+    /// Aggregated reactions, only filled in for hydrated fetches.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub reactions: Option<Vec<ReactionSummary>>,
+    /// Preview of the replied-to message, only filled in for hydrated fetches.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub reply_preview: Option<ReplyPreview>,
+}
+
+const MAX_PREVIEW_CHARS: usize = 100;
+
+/// Compact view of a replied-to message.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ReplyPreview {
+    pub id: snowflake_id,
+    pub from: id,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub text: Option<String>,
+    pub attachment: bool,
+}
+
+impl From<&StoredMessage> for ReplyPreview {
+    fn from(message: &StoredMessage) -> Self {
+        let text = match &message.data {
+            Data::Text(text) => Some(text.as_str()),
+            Data::MultiData(data) | Data::Reply { data, .. } => data.text(),
+            Data::Poll(poll) => Some(poll.question.as_str()),
+            Data::Encrypted { .. } | Data::Call { .. } | Data::System(_) => None,
+        };
+
+        Self {
+            id: message.id,
+            from: message.from,
+            text: text.map(|text| text.chars().take(MAX_PREVIEW_CHARS).collect()),
+            attachment: message.data.has_attachment(),
+        }
+    }
+}
+
+/// Content a message had before an edit replaced it.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StoredRevision {
+    pub data: Data,
+    pub edited_at: DateTime<Utc>,
 }
 
 impl From<StoredMessage> for Message<Data> {
@@ -38,6 +123,8 @@ impl From<StoredMessage> for Message<Data> {
                 Some(gid) => MessageType::Group(gid),
                 None => MessageType::Direct,
             },
+            thread: stored.thread,
+            ttl: None,
         }
     }
 }
@@ -63,12 +150,256 @@ impl<T: Serialize> TryFrom<Message<T>> for StoredMessage {
             },
             overwrited: None,
             reacted: None,
+            thread: message.thread,
+            poll: None,
+            expires_at: None,
+            reactions: None,
+            reply_preview: None,
         })
     }
 }
 
+#[derive(Debug, Clone, Copy)]
+pub enum SearchScope {
+    Channel(id),
+    Direct(id, id),
+    GroupDirect(id),
+}
+
+impl SearchScope {
+    fn of(message: &StoredMessage) -> Self {
+        match message.group_id {
+            Some(_) => SearchScope::Channel(message.to),
+            None if message.to.is_group_dm() => SearchScope::GroupDirect(message.to),
+            None => {
+                let (user1, user2) = message.from.sort_pair(message.to);
+                SearchScope::Direct(user1, user2)
+            }
+        }
+    }
+
+    /// Prefix of the scope inside `CF_CHANNEL_INDEX` / `CF_DM_INDEX`.
+    fn index_prefix(&self) -> Vec<u8> {
+        match *self {
+            SearchScope::Channel(channel_id) | SearchScope::GroupDirect(channel_id) => {
+                channel_id.to_be_bytes().to_vec()
+            }
+            SearchScope::Direct(user1, user2) => {
+                let mut prefix = Vec::with_capacity(8);
+                prefix.extend_from_slice(&user1.to_be_bytes());
+                prefix.extend_from_slice(&user2.to_be_bytes());
+                prefix
+            }
+        }
+    }
+
+    /// Prefix of the scope inside `CF_SEARCH_INDEX`.
+    fn search_prefix(&self) -> Vec<u8> {
+        let tag = match self {
+            SearchScope::Channel(_) => b'c',
+            SearchScope::Direct(..) => b'd',
+            SearchScope::GroupDirect(_) => b'g',
+        };
+
+        let mut prefix = vec![tag];
+        prefix.extend_from_slice(&self.index_prefix());
+        prefix
+    }
+
+    fn term_prefix(&self, term: &str) -> Vec<u8> {
+        let mut prefix = self.search_prefix();
+        prefix.extend_from_slice(term.as_bytes());
+        prefix.push(0);
+        prefix
+    }
+}
+
+/// A conversation a user keeps a read position in, seen from that user.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum ReadTarget {
+    Channel(id),
+    Direct(id),
+}
+
+impl ReadTarget {
+    fn to_bytes(self) -> [u8; 5] {
+        let (tag, target) = match self {
+            ReadTarget::Channel(channel_id) => (b'c', channel_id),
+            ReadTarget::Direct(user_id) => (b'd', user_id),
+        };
+
+        let mut bytes = [0u8; 5];
+        bytes[0] = tag;
+        bytes[1..5].copy_from_slice(&target.to_be_bytes());
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let target = id(i32::from_be_bytes(bytes.get(1..5)?.try_into().ok()?));
+
+        match bytes.first()? {
+            b'c' => Some(ReadTarget::Channel(target)),
+            b'd' => Some(ReadTarget::Direct(target)),
+            _ => None,
+        }
+    }
+
+    fn key(self, user_id: id) -> [u8; 9] {
+        let mut key = [0u8; 9];
+        key[0..4].copy_from_slice(&user_id.to_be_bytes());
+        key[4..9].copy_from_slice(&self.to_bytes());
+        key
+    }
+
+    fn scope(self, user_id: id) -> SearchScope {
+        match self {
+            ReadTarget::Channel(channel_id) => SearchScope::Channel(channel_id),
+            ReadTarget::Direct(group_dm_id) if group_dm_id.is_group_dm() => {
+                SearchScope::GroupDirect(group_dm_id)
+            }
+            ReadTarget::Direct(peer) => {
+                let (user1, user2) = user_id.sort_pair(peer);
+                SearchScope::Direct(user1, user2)
+            }
+        }
+    }
+}
+
+/// A message waiting to be sent at `due_at`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ScheduledMessage {
+    pub id: snowflake_id,
+    pub from: id,
+    pub to: id,
+    pub data: Data,
+    pub group_id: Option<id>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thread: Option<snowflake_id>,
+    pub due_at: DateTime<Utc>,
+}
+
+impl From<ScheduledMessage> for Message<Data> {
+    fn from(scheduled: ScheduledMessage) -> Self {
+        Message {
+            id: scheduled.id,
+            from: scheduled.from,
+            to: scheduled.to,
+            data: scheduled.data,
+            r#type: match scheduled.group_id {
+                Some(gid) => MessageType::Group(gid),
+                None => MessageType::Direct,
+            },
+            thread: scheduled.thread,
+            ttl: None,
+        }
+    }
+}
+
+/// Where a page of history starts. Pages are always returned newest first.
+#[derive(Debug, Clone, Copy)]
+pub enum Page {
+    Before(Option<snowflake_id>),
+    After(snowflake_id),
+    Around(snowflake_id),
+}
+
+impl Default for Page {
+    fn default() -> Self {
+        Page::Before(None)
+    }
+}
+
+fn page_len(len: Option<i64>) -> usize {
+    len.unwrap_or(DEFAULT_PAGE_LEN).clamp(1, MAX_PAGE_LEN) as usize
+}
+
+/// Messages of a channel removed by a bulk delete. Range bounds are exclusive.
+#[derive(Debug, Clone)]
+pub enum BulkSelection {
+    Ids(Vec<snowflake_id>),
+    Range {
+        after: Option<snowflake_id>,
+        before: Option<snowflake_id>,
+        author: Option<id>,
+    },
+}
+
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct ReadState {
+    pub last_read: Option<snowflake_id>,
+    pub unread: usize,
+    pub mentions: usize,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SearchFilter {
+    pub terms: Vec<String>,
+    pub author: Option<id>,
+    pub before: Option<snowflake_id>,
+    pub after: Option<snowflake_id>,
+    pub has_attachment: Option<bool>,
+    pub limit: usize,
+}
+
+impl SearchFilter {
+    fn matches(&self, message: &StoredMessage) -> bool {
+        if matches!(message.data, Data::Encrypted { .. }) {
+            return false;
+        }
+
+        if self.author.is_some_and(|author| author != message.from) {
+            return false;
+        }
+
+        if self
+            .has_attachment
+            .is_some_and(|has| has != message.data.has_attachment())
+        {
+            return false;
+        }
+
+        true
+    }
+}
+
+/// Splits text into lowercase alphanumeric terms for the search index.
+pub fn search_terms(text: &str) -> HashSet<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|term| term.chars().count() >= MIN_SEARCH_TERM_CHARS)
+        .map(str::to_lowercase)
+        .filter(|term| term.len() <= MAX_SEARCH_TERM_BYTES)
+        .collect()
+}
+
+fn message_terms(data: &Data) -> HashSet<String> {
+    match data {
+        Data::Text(text) => search_terms(text),
+        Data::MultiData(data) | Data::Reply { data, .. } => data
+            .text()
+            .into_iter()
+            .chain(data.file_names())
+            .flat_map(search_terms)
+            .collect(),
+        Data::Poll(poll) => std::iter::once(&poll.question)
+            .chain(&poll.options)
+            .flat_map(|text| search_terms(text))
+            .collect(),
+        Data::Encrypted { .. } | Data::Call { .. } | Data::System(_) => HashSet::new(),
+    }
+}
+
+fn open_backup_engine(backup_dir: &Path) -> Result<BackupEngine> {
+    let opts = BackupEngineOptions::new(backup_dir).context("Invalid backup directory")?;
+    let env = Env::new().context("Failed to create RocksDB env")?;
+
+    BackupEngine::open(&opts, &env).context("Failed to open backup engine")
+}
+
 pub struct MessageStore {
     db: Arc<RocksDB>,
+    /// Serializes edits of scheduled messages with the worker taking them.
+    schedule_lock: Arc<Mutex<()>>,
 }
 
 impl MessageStore {
@@ -85,42 +416,193 @@ impl MessageStore {
             ColumnFamilyDescriptor::new(CF_DM_INDEX, Options::default()),
             ColumnFamilyDescriptor::new(CF_CHANNEL_INDEX, Options::default()),
             ColumnFamilyDescriptor::new(CF_USER_DMS, Options::default()),
+            ColumnFamilyDescriptor::new(CF_SEARCH_INDEX, Options::default()),
+            ColumnFamilyDescriptor::new(CF_REVISIONS, Options::default()),
+            ColumnFamilyDescriptor::new(CF_THREAD_INDEX, Options::default()),
+            ColumnFamilyDescriptor::new(CF_CHANNEL_PINS, Options::default()),
+            ColumnFamilyDescriptor::new(CF_DM_PINS, Options::default()),
+            ColumnFamilyDescriptor::new(CF_MENTION_INDEX, Options::default()),
+            ColumnFamilyDescriptor::new(CF_READ_STATES, Options::default()),
+            ColumnFamilyDescriptor::new(CF_SCHEDULED, Options::default()),
+            ColumnFamilyDescriptor::new(CF_EXPIRY_INDEX, Options::default()),
+            ColumnFamilyDescriptor::new(CF_USER_SCHEDULED, Options::default()),
         ];
 
+        let existing_cfs = RocksDB::list_cf(&opts, &path).unwrap_or_default();
+
         let db =
             RocksDB::open_cf_descriptors(&opts, path, cfs).context("Failed to open RocksDB")?;
 
-        Ok(Self { db: Arc::new(db) })
+        let db = Arc::new(db);
+
+        if !existing_cfs.is_empty() && !existing_cfs.iter().any(|cf| cf == CF_SEARCH_INDEX) {
+            log::info!("Building message search index");
+            Self::rebuild_search_index(&db)?;
+        }
+
+        Ok(Self {
+            db,
+            schedule_lock: Arc::new(Mutex::new(())),
+        })
     }
 
-    pub async fn write(&self, message: StoredMessage) -> Result<()> {
-        let db = self.db.clone();
+    /// Opens the store without taking its lock, for reading next to a running server.
+    pub fn open_read_only<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let opts = Options::default();
+        let cfs = RocksDB::list_cf(&opts, &path).context("Failed to list column families")?;
+
+        let db = RocksDB::open_cf_for_read_only(&opts, path, cfs, false)
+            .context("Failed to open RocksDB read only")?;
 
+        Ok(Self {
+            db: Arc::new(db),
+            schedule_lock: Arc::new(Mutex::new(())),
+        })
+    }
+
+    /// Takes a consistent backup of the live store and keeps the newest `keep` backups.
+    pub async fn backup(&self, backup_dir: PathBuf, keep: usize) -> Result<()> {
+        let db = self.db.clone();
         tokio::task::spawn_blocking(move || {
-            let cf_messages = db
-                .cf_handle(CF_MESSAGES)
-                .context("CF_MESSAGES cf not found")?;
+            let mut engine = open_backup_engine(&backup_dir)?;
+
+            engine
+                .create_new_backup_flush(&*db, true)
+                .context("Failed to create backup")?;
+            engine
+                .purge_old_backups(keep)
+                .context("Failed to purge old backups")?;
+
+            Ok(())
+        })
+        .await
+        .context("Failed to spawn blocking task for backup")?
+    }
+
+    /// Replaces the store at `path` with a backup, the latest one when `backup_id` is none.
+    /// The store must not be open.
+    pub fn restore<P: AsRef<Path>, B: AsRef<Path>>(
+        path: P,
+        backup_dir: B,
+        backup_id: Option<u32>,
+    ) -> Result<()> {
+        let mut engine = open_backup_engine(backup_dir.as_ref())?;
+        let opts = RestoreOptions::default();
+
+        match backup_id {
+            Some(backup_id) => engine.restore_from_backup(&path, &path, &opts, backup_id),
+            None => engine.restore_from_latest_backup(&path, &path, &opts),
+        }
+        .context("Failed to restore backup")?;
+
+        Ok(())
+    }
+
+    /// Writes every message as a stream of msgpack records. Returns the number of messages.
+    pub fn export<W: Write>(&self, writer: &mut W) -> Result<usize> {
+        let cf_messages = self
+            .db
+            .cf_handle(CF_MESSAGES)
+            .context("CF_MESSAGES cf not found")?;
+
+        let mut count = 0;
+
+        for item in self
+            .db
+            .iterator_cf(&cf_messages, rocksdb::IteratorMode::Start)
+        {
+            let (_, value) = item.context("Failed to read message while exporting")?;
+
+            let message: StoredMessage =
+                rmp_serde::from_slice(&value).context("Failed to deserialize message")?;
+
+            rmp_serde::encode::write_named(writer, &message)
+                .context("Failed to write exported message")?;
+
+            count += 1;
+        }
+
+        Ok(count)
+    }
+
+    /// Reads a stream written by `export` and indexes every message again.
+    /// Returns the number of messages.
+    pub fn import<R: Read>(&self, reader: R) -> Result<usize> {
+        let mut deserializer = rmp_serde::Deserializer::new(reader);
+        let mut count = 0;
+
+        loop {
+            let message = match StoredMessage::deserialize(&mut deserializer) {
+                Ok(message) => message,
+                Err(rmp_serde::decode::Error::InvalidMarkerRead(e))
+                    if e.kind() == std::io::ErrorKind::UnexpectedEof =>
+                {
+                    break;
+                }
+                Err(e) => return Err(e).context("Failed to read imported message"),
+            };
+
+            Self::write_message(&self.db, &message)?;
+            count += 1;
+        }
+
+        Ok(count)
+    }
+
+    fn write_message(db: &Arc<RocksDB>, message: &StoredMessage) -> Result<()> {
+        let cf_messages = db
+            .cf_handle(CF_MESSAGES)
+            .context("CF_MESSAGES cf not found")?;
+
+        /*
+            Messages:
+                message_id -> message
+        */
+
+        let message_bytes =
+            rmp_serde::to_vec_named(message).context("Failed to serialize message")?;
+
+        db.put_cf(&cf_messages, message.id.to_be_bytes(), &message_bytes)
+            .context("Failed to put message in RocksDB")?;
+
+        match (message.group_id, message.thread) {
+            (Some(_), Some(thread_id)) => Self::index_thread_message(db, message, thread_id)?,
+            (Some(_), None) => Self::index_channel_message(db, message)?,
+            (None, _) => Self::index_dm_message(db, message)?,
+        }
+
+        Self::index_search_terms(db, message)?;
+
+        if let Some(expires_at) = message.expires_at {
+            let cf_expiry = db
+                .cf_handle(CF_EXPIRY_INDEX)
+                .context("CF_EXPIRY_INDEX cf not found")?;
 
             /*
-                Messages:
-                    message_id -> message
+                Expiry Index:
+                    expires_at + message_id -> null
             */
 
-            let message_bytes =
-                rmp_serde::to_vec_named(&message).context("Failed to serialize message")?;
+            db.put_cf(&cf_expiry, Self::expiry_key(expires_at, message.id), [])
+                .context("Failed to put expiry index")?;
+        }
 
-            db.put_cf(&cf_messages, message.id.to_be_bytes(), &message_bytes)
-                .context("Failed to put message in RocksDB")?;
+        Ok(())
+    }
 
-            match message.group_id {
-                Some(_) => Self::index_channel_message(&db, &message)?,
-                None => Self::index_dm_message(&db, &message)?,
-            }
+    fn expiry_key(expires_at: DateTime<Utc>, message_id: snowflake_id) -> [u8; 16] {
+        let mut key = [0u8; 16];
+        key[0..8].copy_from_slice(&(expires_at.timestamp_millis().max(0) as u64).to_be_bytes());
+        key[8..16].copy_from_slice(&message_id.to_be_bytes());
+        key
+    }
 
-            Ok(())
-        })
-        .await
-        .context("Failed to spawn blocking task for write")?
+    pub async fn write(&self, message: StoredMessage) -> Result<()> {
+        let db = self.db.clone();
+
+        tokio::task::spawn_blocking(move || Self::write_message(&db, &message))
+            .await
+            .context("Failed to spawn blocking task for write")?
     }
 
     pub async fn get(&self, message_id: snowflake_id) -> Result<StoredMessage> {
@@ -154,6 +636,22 @@ impl MessageStore {
             .cf_handle(CF_USER_DMS)
             .context("CF_USER_DMS cf not found")?;
 
+        if message.to.is_group_dm() {
+            /*
+                DM Index:
+                    group_dm_id + message_id -> null
+            */
+
+            let mut index_key = [0u8; 12];
+            index_key[0..4].copy_from_slice(&message.to.to_be_bytes());
+            index_key[4..12].copy_from_slice(&message.id.to_be_bytes());
+
+            db.put_cf(&cf_dm, index_key, [])
+                .context("Failed to put dm index")?;
+
+            return Ok(());
+        }
+
         let (user1, user2) = message.from.sort_pair(message.to);
 
         /*
@@ -213,138 +711,510 @@ impl MessageStore {
         Ok(())
     }
 
-    pub async fn get_direct_messages(
-        &self,
-        from: id,
-        to: id,
-        len: Option<i64>,
-        before: Option<snowflake_id>,
-    ) -> Result<Vec<StoredMessage>> {
-        let db = self.db.clone();
-        tokio::task::spawn_blocking(move || {
-            let limit = len.unwrap_or(50) as usize;
-            let cf_dm = db
-                .cf_handle(CF_DM_INDEX)
-                .context("CF_DM_INDEX cf not found")?;
-            let cf_messages = db
-                .cf_handle(CF_MESSAGES)
-                .context("CF_MESSAGES cf not found")?;
+    fn thread_index_key(
+        channel_id: id,
+        thread_id: snowflake_id,
+        message_id: snowflake_id,
+    ) -> [u8; 20] {
+        let mut key = [0u8; 20];
+        key[0..4].copy_from_slice(&channel_id.to_be_bytes());
+        key[4..12].copy_from_slice(&thread_id.to_be_bytes());
+        key[12..20].copy_from_slice(&message_id.to_be_bytes());
+        key
+    }
 
-            let (user1, user2) = from.sort_pair(to);
+    fn index_thread_message(
+        db: &Arc<RocksDB>,
+        message: &StoredMessage,
+        thread_id: snowflake_id,
+    ) -> Result<()> {
+        let cf_thread = db
+            .cf_handle(CF_THREAD_INDEX)
+            .context("CF_THREAD_INDEX cf not found")?;
 
-            let mut prefix = [0u8; 8];
-            prefix[0..4].copy_from_slice(&user1.to_be_bytes());
-            prefix[4..8].copy_from_slice(&user2.to_be_bytes());
+        /*
+            Thread Index:
+                channel_id + thread_id + message_id -> null
+        */
 
-            let before_id = before.map(|s| s.0).unwrap_or(u64::MAX);
-            let seek_id = before_id.saturating_sub(1);
+        let index_key = Self::thread_index_key(message.to, thread_id, message.id);
 
-            let mut seek_key = [0u8; 16];
-            seek_key[0..8].copy_from_slice(&prefix);
-            seek_key[8..16].copy_from_slice(&seek_id.to_be_bytes());
+        db.put_cf(&cf_thread, index_key, [])
+            .context("Failed to put thread index")?;
 
-            let iter = db.iterator_cf(
-                &cf_dm,
-                rocksdb::IteratorMode::From(&seek_key, rocksdb::Direction::Reverse),
-            );
+        Ok(())
+    }
 
-            let mut messages = Vec::with_capacity(limit);
+    /*
+        Channel Pins:
+            channel_id + message_id -> pinned_by
 
-            for item in iter {
-                let (key, _) = item.context("Failed to get item from iterator")?;
+        DM Pins:
+            user1 + user2 + message_id -> pinned_by
+            group_dm_id + message_id -> pinned_by
+    */
 
-                if !key.starts_with(&prefix) {
-                    break;
-                }
+    /// Returns the pin column family and the key prefix of the conversation.
+    fn pin_prefix(message: &StoredMessage) -> (&'static str, Vec<u8>) {
+        match message.group_id {
+            Some(_) => (CF_CHANNEL_PINS, message.to.to_be_bytes().to_vec()),
+            None => (CF_DM_PINS, SearchScope::of(message).index_prefix()),
+        }
+    }
 
-                if key.len() == 16 {
-                    let message_id_bytes: [u8; 8] = key[8..16].try_into().unwrap();
+    fn delete_pin(
+        db: &Arc<RocksDB>,
+        batch: &mut rocksdb::WriteBatch,
+        message: &StoredMessage,
+    ) -> Result<()> {
+        let (cf_name, mut key) = Self::pin_prefix(message);
+        key.extend_from_slice(&message.id.to_be_bytes());
 
-                    if let Some(message_bytes) = db
-                        .get_cf(&cf_messages, message_id_bytes)
-                        .context("Failed to get message bytes")?
-                    {
-                        let stored: StoredMessage = rmp_serde::from_slice(&message_bytes)
-                            .context("Failed to deserialize message")?;
-                        messages.push(stored);
-                    }
-                }
+        let cf_pins = db.cf_handle(cf_name).context("Pins cf not found")?;
 
-                if messages.len() >= limit {
-                    break;
-                }
-            }
+        batch.delete_cf(&cf_pins, key);
 
-            Ok(messages)
-        })
-        .await
-        .context("Failed to spawn blocking task for get_direct_messages")?
+        Ok(())
     }
 
-    pub async fn get_channel_messages(
-        &self,
-        channel_id: id,
-        len: Option<i64>,
-        before: Option<snowflake_id>,
-    ) -> Result<Vec<StoredMessage>> {
-        let db = self.db.clone();
-        tokio::task::spawn_blocking(move || {
-            let limit = len.unwrap_or(50) as usize;
-            let cf_channel = db
-                .cf_handle(CF_CHANNEL_INDEX)
-                .context("CF_CHANNEL_INDEX cf not found")?;
-            let cf_messages = db
-                .cf_handle(CF_MESSAGES)
-                .context("CF_MESSAGES cf not found")?;
+    fn get_pins(db: &Arc<RocksDB>, cf_name: &str, prefix: &[u8]) -> Result<Vec<StoredMessage>> {
+        let cf_pins = db.cf_handle(cf_name).context("Pins cf not found")?;
+        let cf_messages = db
+            .cf_handle(CF_MESSAGES)
+            .context("CF_MESSAGES cf not found")?;
 
-            let mut prefix = [0u8; 4];
-            prefix[0..4].copy_from_slice(&channel_id.to_be_bytes());
+        let mut messages = Vec::new();
 
-            let before_id = before.map(|s| s.0).unwrap_or(u64::MAX);
-            let seek_id = before_id.saturating_sub(1);
+        for item in db.prefix_iterator_cf(&cf_pins, prefix) {
+            let (key, _) = item.context("Failed to get item from iterator")?;
 
-            let mut seek_key = [0u8; 12];
-            seek_key[0..4].copy_from_slice(&prefix);
-            seek_key[4..12].copy_from_slice(&seek_id.to_be_bytes());
+            if !key.starts_with(prefix) {
+                break;
+            }
 
-            let iter = db.iterator_cf(
-                &cf_channel,
-                rocksdb::IteratorMode::From(&seek_key, rocksdb::Direction::Reverse),
-            );
+            if key.len() != prefix.len() + 8 {
+                continue;
+            }
 
-            let mut messages = Vec::with_capacity(limit);
+            if let Some(message_bytes) = db
+                .get_cf(&cf_messages, &key[prefix.len()..])
+                .context("Failed to get message bytes")?
+            {
+                let stored: StoredMessage = rmp_serde::from_slice(&message_bytes)
+                    .context("Failed to deserialize message")?;
+                messages.push(stored);
+            }
+        }
 
-            for item in iter {
-                let (key, _) = item.context("Failed to get item from iterator")?;
+        messages.reverse();
 
-                if !key.starts_with(&prefix) {
-                    break;
-                }
+        Ok(messages)
+    }
+
+    fn index_search_terms(db: &Arc<RocksDB>, message: &StoredMessage) -> Result<()> {
+        let cf_search = db
+            .cf_handle(CF_SEARCH_INDEX)
+            .context("CF_SEARCH_INDEX cf not found")?;
+
+        let scope = SearchScope::of(message);
+
+        /*
+            Search Index:
+                'c' + channel_id + term + 0 + message_id -> null
+                'd' + user1 + user2 + term + 0 + message_id -> null
+        */
+
+        for term in message_terms(&message.data) {
+            let mut key = scope.term_prefix(&term);
+            key.extend_from_slice(&message.id.to_be_bytes());
+
+            db.put_cf(&cf_search, &key, [])
+                .context("Failed to put search index")?;
+        }
+
+        Ok(())
+    }
+
+    fn unindex_search_terms(
+        db: &Arc<RocksDB>,
+        batch: &mut rocksdb::WriteBatch,
+        message: &StoredMessage,
+    ) -> Result<()> {
+        let cf_search = db
+            .cf_handle(CF_SEARCH_INDEX)
+            .context("CF_SEARCH_INDEX cf not found")?;
+
+        let scope = SearchScope::of(message);
+
+        for term in message_terms(&message.data) {
+            let mut key = scope.term_prefix(&term);
+            key.extend_from_slice(&message.id.to_be_bytes());
+
+            batch.delete_cf(&cf_search, &key);
+        }
+
+        Ok(())
+    }
+
+    fn rebuild_search_index(db: &Arc<RocksDB>) -> Result<()> {
+        let cf_messages = db
+            .cf_handle(CF_MESSAGES)
+            .context("CF_MESSAGES cf not found")?;
+
+        for item in db.iterator_cf(&cf_messages, rocksdb::IteratorMode::Start) {
+            let (_, value) = item.context("Failed to read message while indexing")?;
+
+            let message: StoredMessage =
+                rmp_serde::from_slice(&value).context("Failed to deserialize message")?;
+
+            Self::index_search_terms(db, &message)?;
+        }
+
+        Ok(())
+    }
+
+    /// Keeps the previous content when an edit changed it. Overwrites that only
+    /// touch flags (reactions, call end time) are not revisions.
+    fn store_revision(
+        db: &Arc<RocksDB>,
+        previous: &StoredMessage,
+        message: &StoredMessage,
+    ) -> Result<()> {
+        if message.overwrited != Some(true) || previous.data == message.data {
+            return Ok(());
+        }
+
+        let cf_revisions = db
+            .cf_handle(CF_REVISIONS)
+            .context("CF_REVISIONS cf not found")?;
+
+        /*
+            Revisions:
+                message_id + edited_at (millis) -> StoredRevision
+        */
+
+        let revision = StoredRevision {
+            data: previous.data.clone(),
+            edited_at: Utc::now(),
+        };
+
+        let mut revision_key = [0u8; 16];
+        revision_key[0..8].copy_from_slice(&message.id.to_be_bytes());
+        revision_key[8..16]
+            .copy_from_slice(&(revision.edited_at.timestamp_millis() as u64).to_be_bytes());
+
+        let revision_bytes =
+            rmp_serde::to_vec_named(&revision).context("Failed to serialize revision")?;
+
+        db.put_cf(&cf_revisions, revision_key, revision_bytes)
+            .context("Failed to put revision in RocksDB")?;
+
+        Ok(())
+    }
+
+    fn delete_revisions(
+        db: &Arc<RocksDB>,
+        batch: &mut rocksdb::WriteBatch,
+        message_id: snowflake_id,
+    ) -> Result<()> {
+        let cf_revisions = db
+            .cf_handle(CF_REVISIONS)
+            .context("CF_REVISIONS cf not found")?;
+
+        batch.delete_range_cf(
+            &cf_revisions,
+            message_id.to_be_bytes(),
+            (message_id.0 + 1).to_be_bytes(),
+        );
+
+        Ok(())
+    }
+
+    /// Walks `prefix + message_id` keys newest first, starting below `before`
+    /// and stopping at `after`. Stops early when `visit` returns false.
+    fn scan_ids_reverse(
+        db: &Arc<RocksDB>,
+        cf: &Arc<rocksdb::BoundColumnFamily>,
+        prefix: &[u8],
+        before: Option<snowflake_id>,
+        after: Option<snowflake_id>,
+        mut visit: impl FnMut(snowflake_id) -> Result<bool>,
+    ) -> Result<()> {
+        let before_id = before.map(|s| s.0).unwrap_or(u64::MAX);
+        let seek_id = before_id.saturating_sub(1);
+
+        let mut seek_key = prefix.to_vec();
+        seek_key.extend_from_slice(&seek_id.to_be_bytes());
+
+        let iter = db.iterator_cf(
+            cf,
+            rocksdb::IteratorMode::From(&seek_key, rocksdb::Direction::Reverse),
+        );
+
+        for item in iter {
+            let (key, _) = item.context("Failed to get item from iterator")?;
+
+            if !key.starts_with(prefix) {
+                break;
+            }
+
+            if key.len() != prefix.len() + 8 {
+                continue;
+            }
+
+            let message_id_bytes: [u8; 8] = key[prefix.len()..].try_into().unwrap();
+            let message_id = snowflake_id(u64::from_be_bytes(message_id_bytes));
+
+            if after.is_some_and(|after| message_id < after) {
+                break;
+            }
+
+            if !visit(message_id)? {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Walks `prefix + message_id` keys oldest first, starting above `after`.
+    /// Stops early when `visit` returns false.
+    fn scan_ids_forward(
+        db: &Arc<RocksDB>,
+        cf: &Arc<rocksdb::BoundColumnFamily>,
+        prefix: &[u8],
+        after: snowflake_id,
+        mut visit: impl FnMut(snowflake_id) -> Result<bool>,
+    ) -> Result<()> {
+        let Some(seek_id) = after.0.checked_add(1) else {
+            return Ok(());
+        };
+
+        let mut seek_key = prefix.to_vec();
+        seek_key.extend_from_slice(&seek_id.to_be_bytes());
+
+        let iter = db.iterator_cf(
+            cf,
+            rocksdb::IteratorMode::From(&seek_key, rocksdb::Direction::Forward),
+        );
+
+        for item in iter {
+            let (key, _) = item.context("Failed to get item from iterator")?;
+
+            if !key.starts_with(prefix) {
+                break;
+            }
+
+            if key.len() != prefix.len() + 8 {
+                continue;
+            }
+
+            let message_id_bytes: [u8; 8] = key[prefix.len()..].try_into().unwrap();
+
+            if !visit(snowflake_id(u64::from_be_bytes(message_id_bytes)))? {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Loads up to `limit` messages of an index around the page cursor, newest first.
+    fn read_page(
+        db: &Arc<RocksDB>,
+        cf: &Arc<rocksdb::BoundColumnFamily>,
+        prefix: &[u8],
+        page: Page,
+        limit: usize,
+    ) -> Result<Vec<StoredMessage>> {
+        let mut ids = Vec::with_capacity(limit);
+
+        match page {
+            Page::Before(before) => {
+                Self::scan_ids_reverse(db, cf, prefix, before, None, |message_id| {
+                    ids.push(message_id);
+                    Ok(ids.len() < limit)
+                })?;
+            }
+            Page::After(after) => {
+                Self::scan_ids_forward(db, cf, prefix, after, |message_id| {
+                    ids.push(message_id);
+                    Ok(ids.len() < limit)
+                })?;
+                ids.reverse();
+            }
+            Page::Around(target) => {
+                let newer_len = limit / 2;
+
+                if newer_len > 0 {
+                    Self::scan_ids_forward(db, cf, prefix, target, |message_id| {
+                        ids.push(message_id);
+                        Ok(ids.len() < newer_len)
+                    })?;
+                    ids.reverse();
+                }
+
+                // The target itself and older messages fill the rest of the page.
+                let before = target.0.checked_add(1).map(snowflake_id);
+                Self::scan_ids_reverse(db, cf, prefix, before, None, |message_id| {
+                    ids.push(message_id);
+                    Ok(ids.len() < limit)
+                })?;
+            }
+        }
+
+        let mut messages = Vec::with_capacity(ids.len());
+
+        for message_id in ids {
+            if let Some(message) = Self::load_message(db, message_id)? {
+                messages.push(message);
+            }
+        }
 
-                if key.len() == 12 {
-                    let message_id_bytes: [u8; 8] = key[4..12].try_into().unwrap();
+        Ok(messages)
+    }
 
-                    if let Some(message_bytes) = db
-                        .get_cf(&cf_messages, message_id_bytes)
-                        .context("Failed to get message bytes")?
+    fn search_scope(
+        db: &Arc<RocksDB>,
+        scope: SearchScope,
+        filter: &SearchFilter,
+    ) -> Result<Vec<StoredMessage>> {
+        let cf_messages = db
+            .cf_handle(CF_MESSAGES)
+            .context("CF_MESSAGES cf not found")?;
+        let cf_search = db
+            .cf_handle(CF_SEARCH_INDEX)
+            .context("CF_SEARCH_INDEX cf not found")?;
+
+        let (cf, prefix) = match (filter.terms.first(), scope) {
+            (Some(term), _) => (cf_search.clone(), scope.term_prefix(term)),
+            (None, SearchScope::Channel(_)) => (
+                db.cf_handle(CF_CHANNEL_INDEX)
+                    .context("CF_CHANNEL_INDEX cf not found")?,
+                scope.index_prefix(),
+            ),
+            (None, SearchScope::Direct(..) | SearchScope::GroupDirect(_)) => (
+                db.cf_handle(CF_DM_INDEX)
+                    .context("CF_DM_INDEX cf not found")?,
+                scope.index_prefix(),
+            ),
+        };
+
+        let other_terms: Vec<Vec<u8>> = filter
+            .terms
+            .iter()
+            .skip(1)
+            .map(|term| scope.term_prefix(term))
+            .collect();
+
+        let mut messages = Vec::new();
+        let mut scanned = 0;
+
+        Self::scan_ids_reverse(
+            db,
+            &cf,
+            &prefix,
+            filter.before,
+            filter.after,
+            |message_id| {
+                scanned += 1;
+
+                for term_prefix in &other_terms {
+                    let mut key = term_prefix.clone();
+                    key.extend_from_slice(&message_id.to_be_bytes());
+
+                    if db
+                        .get_pinned_cf(&cf_search, &key)
+                        .context("Failed to get search index")?
+                        .is_none()
                     {
-                        let stored: StoredMessage = rmp_serde::from_slice(&message_bytes)
-                            .context("Failed to deserialize message")?;
-                        messages.push(stored);
+                        return Ok(scanned < MAX_SEARCH_SCAN);
                     }
                 }
 
-                if messages.len() >= limit {
-                    break;
+                if let Some(message_bytes) = db
+                    .get_cf(&cf_messages, message_id.to_be_bytes())
+                    .context("Failed to get message bytes")?
+                {
+                    let stored: StoredMessage = rmp_serde::from_slice(&message_bytes)
+                        .context("Failed to deserialize message")?;
+
+                    if filter.matches(&stored) {
+                        messages.push(stored);
+                    }
                 }
-            }
 
-            Ok(messages)
+                Ok(messages.len() < filter.limit && scanned < MAX_SEARCH_SCAN)
+            },
+        )?;
+
+        Ok(messages)
+    }
+
+    pub async fn get_direct_messages(
+        &self,
+        from: id,
+        to: id,
+        len: Option<i64>,
+        page: Page,
+    ) -> Result<Vec<StoredMessage>> {
+        let db = self.db.clone();
+        tokio::task::spawn_blocking(move || {
+            let cf_dm = db
+                .cf_handle(CF_DM_INDEX)
+                .context("CF_DM_INDEX cf not found")?;
+
+            let prefix = ReadTarget::Direct(to).scope(from).index_prefix();
+
+            Self::read_page(&db, &cf_dm, &prefix, page, page_len(len))
+        })
+        .await
+        .context("Failed to spawn blocking task for get_direct_messages")?
+    }
+
+    pub async fn get_channel_messages(
+        &self,
+        channel_id: id,
+        len: Option<i64>,
+        page: Page,
+    ) -> Result<Vec<StoredMessage>> {
+        let db = self.db.clone();
+        tokio::task::spawn_blocking(move || {
+            let cf_channel = db
+                .cf_handle(CF_CHANNEL_INDEX)
+                .context("CF_CHANNEL_INDEX cf not found")?;
+
+            let prefix = channel_id.to_be_bytes();
+
+            Self::read_page(&db, &cf_channel, &prefix, page, page_len(len))
         })
         .await
         .context("Failed to spawn blocking task for get_channel_messages")?
     }
 
+    pub async fn get_thread_messages(
+        &self,
+        channel_id: id,
+        thread_id: snowflake_id,
+        len: Option<i64>,
+        page: Page,
+    ) -> Result<Vec<StoredMessage>> {
+        let db = self.db.clone();
+        tokio::task::spawn_blocking(move || {
+            let cf_thread = db
+                .cf_handle(CF_THREAD_INDEX)
+                .context("CF_THREAD_INDEX cf not found")?;
+
+            let mut prefix = [0u8; 12];
+            prefix[0..4].copy_from_slice(&channel_id.to_be_bytes());
+            prefix[4..12].copy_from_slice(&thread_id.to_be_bytes());
+
+            Self::read_page(&db, &cf_thread, &prefix, page, page_len(len))
+        })
+        .await
+        .context("Failed to spawn blocking task for get_thread_messages")?
+    }
+
     pub async fn get_dms(&self, user_id: id) -> Result<Vec<(id, snowflake_id)>> {
         let db = self.db.clone();
         tokio::task::spawn_blocking(move || {
@@ -391,59 +1261,185 @@ impl MessageStore {
                 .cf_handle(CF_MESSAGES)
                 .context("CF_MESSAGES cf not found")?;
 
+            if let Some(bytes) = db
+                .get_cf(&cf_messages, message.id.to_be_bytes())
+                .context("Failed to get message from RocksDB")?
+            {
+                let previous: StoredMessage =
+                    rmp_serde::from_slice(&bytes).context("Failed to deserialize message")?;
+
+                let mut batch = rocksdb::WriteBatch::default();
+                Self::unindex_search_terms(&db, &mut batch, &previous)?;
+                db.write(batch).context("Failed to delete search index")?;
+
+                Self::store_revision(&db, &previous, &message)?;
+            }
+
             let message_bytes =
                 rmp_serde::to_vec_named(&message).context("Failed to serialize message")?;
 
             db.put_cf(&cf_messages, message.id.to_be_bytes(), &message_bytes)
                 .context("Failed to put message in RocksDB")?;
 
+            Self::index_search_terms(&db, &message)?;
+
             Ok(())
         })
         .await
         .context("Failed to spawn blocking task for overwrite")?
     }
 
-    pub async fn delete(&self, message_id: snowflake_id) -> Result<()> {
+    fn load_message(db: &Arc<RocksDB>, message_id: snowflake_id) -> Result<Option<StoredMessage>> {
+        let cf_messages = db
+            .cf_handle(CF_MESSAGES)
+            .context("CF_MESSAGES cf not found")?;
+
+        db.get_cf(&cf_messages, message_id.to_be_bytes())
+            .context("Failed to get message from RocksDB")?
+            .map(|bytes| rmp_serde::from_slice(&bytes).context("Failed to deserialize message"))
+            .transpose()
+    }
+
+    /// Loads the given messages in one multi_get, missing ones are left out.
+    pub async fn get_many(
+        &self,
+        message_ids: Vec<snowflake_id>,
+    ) -> Result<HashMap<snowflake_id, StoredMessage>> {
         let db = self.db.clone();
         tokio::task::spawn_blocking(move || {
             let cf_messages = db
                 .cf_handle(CF_MESSAGES)
                 .context("CF_MESSAGES cf not found")?;
 
-            let message_bytes = db
-                .get_cf(&cf_messages, message_id.to_be_bytes())
-                .context("Failed to get message from RocksDB")?;
+            let keys = message_ids
+                .iter()
+                .map(|message_id| (&cf_messages, message_id.to_be_bytes()));
 
-            if let Some(bytes) = message_bytes {
-                let message: StoredMessage =
-                    rmp_serde::from_slice(&bytes).context("Failed to deserialize message")?;
-                match message.group_id {
-                    Some(_) => {
-                        let cf_channel = db
-                            .cf_handle(CF_CHANNEL_INDEX)
-                            .context("CF_CHANNEL_INDEX cf not found")?;
-                        let mut index_key = [0u8; 12];
-                        index_key[0..4].copy_from_slice(&message.to.to_be_bytes());
-                        index_key[4..12].copy_from_slice(&message.id.to_be_bytes());
-                        db.delete_cf(&cf_channel, &index_key)
-                            .context("Failed to delete channel index")?;
-                    }
-                    None => {
-                        let cf_dm = db
-                            .cf_handle(CF_DM_INDEX)
-                            .context("CF_DM_INDEX cf not found")?;
-                        let (user1, user2) = message.from.sort_pair(message.to);
-
-                        let mut index_key = [0u8; 16];
-                        index_key[0..4].copy_from_slice(&user1.to_be_bytes());
-                        index_key[4..8].copy_from_slice(&user2.to_be_bytes());
-                        index_key[8..16].copy_from_slice(&message.id.to_be_bytes());
-                        db.delete_cf(&cf_dm, &index_key)
-                            .context("Failed to delete dm index")?;
+            let mut messages = HashMap::with_capacity(message_ids.len());
+
+            for (message_id, value) in message_ids.iter().zip(db.multi_get_cf(keys)) {
+                if let Some(bytes) = value.context("Failed to get message from RocksDB")? {
+                    let message: StoredMessage =
+                        rmp_serde::from_slice(&bytes).context("Failed to deserialize message")?;
+                    messages.insert(*message_id, message);
+                }
+            }
+
+            Ok(messages)
+        })
+        .await
+        .context("Failed to spawn blocking task for get_many")?
+    }
+
+    /// Queues the removal of a message together with its index, search, revision and pin entries.
+    fn delete_message(
+        db: &Arc<RocksDB>,
+        batch: &mut rocksdb::WriteBatch,
+        message: &StoredMessage,
+    ) -> Result<()> {
+        let cf_messages = db
+            .cf_handle(CF_MESSAGES)
+            .context("CF_MESSAGES cf not found")?;
+
+        match message.group_id {
+            Some(_) if message.thread.is_some() => {
+                let cf_thread = db
+                    .cf_handle(CF_THREAD_INDEX)
+                    .context("CF_THREAD_INDEX cf not found")?;
+                let index_key =
+                    Self::thread_index_key(message.to, message.thread.unwrap(), message.id);
+                batch.delete_cf(&cf_thread, index_key);
+            }
+            Some(_) => {
+                let cf_channel = db
+                    .cf_handle(CF_CHANNEL_INDEX)
+                    .context("CF_CHANNEL_INDEX cf not found")?;
+                let mut index_key = [0u8; 12];
+                index_key[0..4].copy_from_slice(&message.to.to_be_bytes());
+                index_key[4..12].copy_from_slice(&message.id.to_be_bytes());
+                batch.delete_cf(&cf_channel, index_key);
+            }
+            None => {
+                let cf_dm = db
+                    .cf_handle(CF_DM_INDEX)
+                    .context("CF_DM_INDEX cf not found")?;
+                let mut index_key = SearchScope::of(message).index_prefix();
+                index_key.extend_from_slice(&message.id.to_be_bytes());
+                batch.delete_cf(&cf_dm, index_key);
+            }
+        }
+
+        Self::unindex_search_terms(db, batch, message)?;
+        Self::delete_revisions(db, batch, message.id)?;
+        Self::delete_pin(db, batch, message)?;
+
+        if let Some(expires_at) = message.expires_at {
+            let cf_expiry = db
+                .cf_handle(CF_EXPIRY_INDEX)
+                .context("CF_EXPIRY_INDEX cf not found")?;
+            batch.delete_cf(&cf_expiry, Self::expiry_key(expires_at, message.id));
+        }
+
+        batch.delete_cf(&cf_messages, message.id.to_be_bytes());
+
+        Ok(())
+    }
+
+    /// Deletes up to `limit` messages that expired by `now` and returns them.
+    pub async fn delete_expired(
+        &self,
+        now: DateTime<Utc>,
+        limit: usize,
+    ) -> Result<Vec<StoredMessage>> {
+        let db = self.db.clone();
+        tokio::task::spawn_blocking(move || {
+            let cf_expiry = db
+                .cf_handle(CF_EXPIRY_INDEX)
+                .context("CF_EXPIRY_INDEX cf not found")?;
+
+            let now_millis = now.timestamp_millis().max(0) as u64;
+            let mut batch = rocksdb::WriteBatch::default();
+            let mut expired = Vec::new();
+
+            for item in db.iterator_cf(&cf_expiry, rocksdb::IteratorMode::Start) {
+                let (key, _) = item.context("Failed to get item from iterator")?;
+
+                if key.len() != 16 {
+                    continue;
+                }
+
+                if u64::from_be_bytes(key[0..8].try_into()?) > now_millis || expired.len() >= limit
+                {
+                    break;
+                }
+
+                let message_id = snowflake_id(u64::from_be_bytes(key[8..16].try_into()?));
+
+                match Self::load_message(&db, message_id)? {
+                    Some(message) => {
+                        Self::delete_message(&db, &mut batch, &message)?;
+                        expired.push(message);
                     }
+                    None => batch.delete_cf(&cf_expiry, &key),
                 }
+            }
 
-                db.delete_cf(&cf_messages, message_id.to_be_bytes())
+            db.write(batch)
+                .context("Failed to delete expired messages")?;
+
+            Ok(expired)
+        })
+        .await
+        .context("Failed to spawn blocking task for delete_expired")?
+    }
+
+    pub async fn delete(&self, message_id: snowflake_id) -> Result<()> {
+        let db = self.db.clone();
+        tokio::task::spawn_blocking(move || {
+            if let Some(message) = Self::load_message(&db, message_id)? {
+                let mut batch = rocksdb::WriteBatch::default();
+                Self::delete_message(&db, &mut batch, &message)?;
+                db.write(batch)
                     .context("Failed to delete message from RocksDB")?;
             }
 
@@ -453,47 +1449,990 @@ impl MessageStore {
         .context("Failed to spawn blocking task for delete")?
     }
 
-    pub async fn delete_channel_messages(&self, channel_id: id) -> Result<()> {
+    /// Deletes the oldest messages of a scope that are older than `before` or
+    /// beyond the newest `keep`. Returns the purged ids, at most `MAX_PURGE_BATCH`.
+    pub async fn purge(
+        &self,
+        scope: SearchScope,
+        before: Option<snowflake_id>,
+        keep: Option<usize>,
+    ) -> Result<Vec<snowflake_id>> {
         let db = self.db.clone();
         tokio::task::spawn_blocking(move || {
-            let cf_channel = db
-                .cf_handle(CF_CHANNEL_INDEX)
-                .context("CF_CHANNEL_INDEX cf not found")?;
-            let cf_messages = db
-                .cf_handle(CF_MESSAGES)
-                .context("CF_MESSAGES cf not found")?;
+            let cf_index = match scope {
+                SearchScope::Channel(_) => db
+                    .cf_handle(CF_CHANNEL_INDEX)
+                    .context("CF_CHANNEL_INDEX cf not found")?,
+                SearchScope::Direct(..) | SearchScope::GroupDirect(_) => db
+                    .cf_handle(CF_DM_INDEX)
+                    .context("CF_DM_INDEX cf not found")?,
+            };
+
+            let prefix = scope.index_prefix();
+            let mut cutoff = before.unwrap_or_default();
+
+            if let Some(keep) = keep {
+                let mut seen = 0;
+                Self::scan_ids_reverse(&db, &cf_index, &prefix, None, None, |message_id| {
+                    seen += 1;
+                    if seen > keep {
+                        cutoff = cutoff.max(snowflake_id(message_id.0 + 1));
+                        return Ok(false);
+                    }
+                    Ok(true)
+                })?;
+            }
 
-            let prefix = channel_id.to_be_bytes();
+            let mut expired = Vec::new();
 
             let iter = db.iterator_cf(
-                &cf_channel,
+                &cf_index,
                 rocksdb::IteratorMode::From(&prefix, rocksdb::Direction::Forward),
             );
 
-            let mut batch = rocksdb::WriteBatch::default();
-
             for item in iter {
-                let (key, _) = item.context("Failed to read channel index item")?;
+                let (key, _) = item.context("Failed to get item from iterator")?;
 
-                if !key.starts_with(&prefix) {
+                if !key.starts_with(&prefix) || expired.len() >= MAX_PURGE_BATCH {
                     break;
                 }
 
-                if key.len() == 12 {
-                    let message_id_bytes: [u8; 8] = key[4..12].try_into().unwrap();
-                    batch.delete_cf(&cf_messages, message_id_bytes);
+                if key.len() != prefix.len() + 8 {
+                    continue;
+                }
+
+                let message_id = snowflake_id(u64::from_be_bytes(key[prefix.len()..].try_into()?));
+
+                if message_id >= cutoff {
+                    break;
                 }
 
-                batch.delete_cf(&cf_channel, &key);
+                expired.push(message_id);
             }
 
-            db.write(batch)
-                .context("Failed to write channel message delete batch")?;
+            // Thread messages only expire by age, they are not part of the channel count.
+            if let (SearchScope::Channel(channel_id), Some(before)) = (scope, before) {
+                let cf_thread = db
+                    .cf_handle(CF_THREAD_INDEX)
+                    .context("CF_THREAD_INDEX cf not found")?;
+                let prefix = channel_id.to_be_bytes();
 
-            Ok(())
+                let iter = db.iterator_cf(
+                    &cf_thread,
+                    rocksdb::IteratorMode::From(&prefix, rocksdb::Direction::Forward),
+                );
+
+                for item in iter {
+                    let (key, _) = item.context("Failed to get item from iterator")?;
+
+                    if !key.starts_with(&prefix) || expired.len() >= MAX_PURGE_BATCH {
+                        break;
+                    }
+
+                    if key.len() != 20 {
+                        continue;
+                    }
+
+                    let message_id = snowflake_id(u64::from_be_bytes(key[12..20].try_into()?));
+
+                    if message_id < before {
+                        expired.push(message_id);
+                    }
+                }
+            }
+
+            let mut batch = rocksdb::WriteBatch::default();
+            let mut purged = Vec::with_capacity(expired.len());
+
+            for message_id in expired {
+                if let Some(message) = Self::load_message(&db, message_id)? {
+                    Self::delete_message(&db, &mut batch, &message)?;
+                    purged.push(message_id);
+                }
+            }
+
+            db.write(batch).context("Failed to purge messages")?;
+
+            Ok(purged)
         })
         .await
-        .context("Failed to spawn blocking task for delete_channel_messages")?
+        .context("Failed to spawn blocking task for purge")?
+    }
+
+    /// Deletes up to `MAX_BULK_DELETE` messages of a channel in one write.
+    /// A range selection looks at no more than `MAX_BULK_SCAN` messages.
+    /// Returns the ids that were deleted.
+    pub async fn bulk_delete(
+        &self,
+        channel_id: id,
+        selection: BulkSelection,
+    ) -> Result<Vec<snowflake_id>> {
+        let db = self.db.clone();
+        tokio::task::spawn_blocking(move || {
+            let in_channel =
+                |message: &StoredMessage| message.group_id.is_some() && message.to == channel_id;
+
+            let mut messages = Vec::new();
+
+            match selection {
+                BulkSelection::Ids(mut ids) => {
+                    ids.sort_unstable();
+                    ids.dedup();
+                    ids.truncate(MAX_BULK_DELETE);
+
+                    for message_id in ids {
+                        if let Some(message) = Self::load_message(&db, message_id)?
+                            && in_channel(&message)
+                        {
+                            messages.push(message);
+                        }
+                    }
+                }
+                BulkSelection::Range {
+                    after,
+                    before,
+                    author,
+                } => {
+                    let cf_channel = db
+                        .cf_handle(CF_CHANNEL_INDEX)
+                        .context("CF_CHANNEL_INDEX cf not found")?;
+
+                    let after = after.map(|after| snowflake_id(after.0.saturating_add(1)));
+                    let mut scanned = 0;
+
+                    Self::scan_ids_reverse(
+                        &db,
+                        &cf_channel,
+                        &channel_id.to_be_bytes(),
+                        before,
+                        after,
+                        |message_id| {
+                            scanned += 1;
+                            if let Some(message) = Self::load_message(&db, message_id)?
+                                && author.is_none_or(|author| author == message.from)
+                            {
+                                messages.push(message);
+                            }
+                            Ok(messages.len() < MAX_BULK_DELETE && scanned < MAX_BULK_SCAN)
+                        },
+                    )?;
+                }
+            }
+
+            let mut batch = rocksdb::WriteBatch::default();
+
+            for message in &messages {
+                Self::delete_message(&db, &mut batch, message)?;
+            }
+
+            db.write(batch).context("Failed to bulk delete messages")?;
+
+            Ok(messages.into_iter().map(|message| message.id).collect())
+        })
+        .await
+        .context("Failed to spawn blocking task for bulk_delete")?
+    }
+
+    pub async fn delete_channel_messages(&self, channel_id: id) -> Result<()> {
+        let db = self.db.clone();
+        tokio::task::spawn_blocking(move || {
+            let cf_channel = db
+                .cf_handle(CF_CHANNEL_INDEX)
+                .context("CF_CHANNEL_INDEX cf not found")?;
+            let cf_messages = db
+                .cf_handle(CF_MESSAGES)
+                .context("CF_MESSAGES cf not found")?;
+            let cf_search = db
+                .cf_handle(CF_SEARCH_INDEX)
+                .context("CF_SEARCH_INDEX cf not found")?;
+            let cf_revisions = db
+                .cf_handle(CF_REVISIONS)
+                .context("CF_REVISIONS cf not found")?;
+
+            let cf_thread = db
+                .cf_handle(CF_THREAD_INDEX)
+                .context("CF_THREAD_INDEX cf not found")?;
+
+            let prefix = channel_id.to_be_bytes();
+
+            let mut batch = rocksdb::WriteBatch::default();
+
+            // Channel index keys are 12 bytes, thread index keys are 20;
+            // both end with the message id.
+            for (cf_index, key_len) in [(&cf_channel, 12), (&cf_thread, 20)] {
+                let iter = db.iterator_cf(
+                    cf_index,
+                    rocksdb::IteratorMode::From(&prefix, rocksdb::Direction::Forward),
+                );
+
+                for item in iter {
+                    let (key, _) = item.context("Failed to read channel index item")?;
+
+                    if !key.starts_with(&prefix) {
+                        break;
+                    }
+
+                    if key.len() == key_len {
+                        let message_id_bytes: [u8; 8] = key[key_len - 8..].try_into().unwrap();
+                        batch.delete_cf(&cf_messages, message_id_bytes);
+
+                        let message_id = u64::from_be_bytes(message_id_bytes);
+                        batch.delete_range_cf(
+                            &cf_revisions,
+                            message_id.to_be_bytes(),
+                            (message_id + 1).to_be_bytes(),
+                        );
+                    }
+
+                    batch.delete_cf(cf_index, &key);
+                }
+            }
+
+            let cf_pins = db
+                .cf_handle(CF_CHANNEL_PINS)
+                .context("CF_CHANNEL_PINS cf not found")?;
+            let mut pins_end = prefix.to_vec();
+            pins_end.extend_from_slice(&[0xFF; 9]);
+            batch.delete_range_cf(&cf_pins, prefix.to_vec(), pins_end);
+
+            // Search terms are UTF-8, so no key under the scope reaches 0xFF.
+            let search_start = SearchScope::Channel(channel_id).search_prefix();
+            let mut search_end = search_start.clone();
+            search_end.push(0xFF);
+            batch.delete_range_cf(&cf_search, search_start, search_end);
+
+            db.write(batch)
+                .context("Failed to write channel message delete batch")?;
+
+            Ok(())
+        })
+        .await
+        .context("Failed to spawn blocking task for delete_channel_messages")?
+    }
+
+    /// Returns false when the message was already pinned.
+    pub async fn pin(&self, message: StoredMessage, pinned_by: id) -> Result<bool> {
+        let db = self.db.clone();
+        tokio::task::spawn_blocking(move || {
+            let (cf_name, prefix) = Self::pin_prefix(&message);
+            let cf_pins = db.cf_handle(cf_name).context("Pins cf not found")?;
+
+            let mut key = prefix.clone();
+            key.extend_from_slice(&message.id.to_be_bytes());
+
+            if db
+                .get_pinned_cf(&cf_pins, &key)
+                .context("Failed to get pin")?
+                .is_some()
+            {
+                return Ok(false);
+            }
+
+            let pins = db
+                .prefix_iterator_cf(&cf_pins, &prefix)
+                .take_while(|item| item.as_ref().is_ok_and(|(k, _)| k.starts_with(&prefix)))
+                .count();
+
+            if pins >= MAX_PINS {
+                anyhow::bail!("Pin limit of {MAX_PINS} reached");
+            }
+
+            db.put_cf(&cf_pins, key, pinned_by.to_be_bytes())
+                .context("Failed to put pin")?;
+
+            Ok(true)
+        })
+        .await
+        .context("Failed to spawn blocking task for pin")?
+    }
+
+    /// Returns false when the message was not pinned.
+    pub async fn unpin(&self, message: StoredMessage) -> Result<bool> {
+        let db = self.db.clone();
+        tokio::task::spawn_blocking(move || {
+            let (cf_name, mut key) = Self::pin_prefix(&message);
+            key.extend_from_slice(&message.id.to_be_bytes());
+
+            let cf_pins = db.cf_handle(cf_name).context("Pins cf not found")?;
+
+            if db
+                .get_pinned_cf(&cf_pins, &key)
+                .context("Failed to get pin")?
+                .is_none()
+            {
+                return Ok(false);
+            }
+
+            db.delete_cf(&cf_pins, key)
+                .context("Failed to delete pin")?;
+
+            Ok(true)
+        })
+        .await
+        .context("Failed to spawn blocking task for unpin")?
+    }
+
+    pub async fn get_channel_pins(&self, channel_id: id) -> Result<Vec<StoredMessage>> {
+        let db = self.db.clone();
+        tokio::task::spawn_blocking(move || {
+            Self::get_pins(&db, CF_CHANNEL_PINS, &channel_id.to_be_bytes())
+        })
+        .await
+        .context("Failed to spawn blocking task for get_channel_pins")?
+    }
+
+    pub async fn get_dm_pins(&self, user1: id, user2: id) -> Result<Vec<StoredMessage>> {
+        let db = self.db.clone();
+        tokio::task::spawn_blocking(move || {
+            let prefix = ReadTarget::Direct(user2).scope(user1).index_prefix();
+
+            Self::get_pins(&db, CF_DM_PINS, &prefix)
+        })
+        .await
+        .context("Failed to spawn blocking task for get_dm_pins")?
+    }
+
+    pub async fn index_mentions(
+        &self,
+        message_id: snowflake_id,
+        target: ReadTarget,
+        users: Vec<id>,
+    ) -> Result<()> {
+        let db = self.db.clone();
+        tokio::task::spawn_blocking(move || {
+            let cf_mentions = db
+                .cf_handle(CF_MENTION_INDEX)
+                .context("CF_MENTION_INDEX cf not found")?;
+
+            /*
+                Mention Index:
+                    user_id + message_id -> read target
+            */
+
+            let mut batch = rocksdb::WriteBatch::default();
+
+            for user_id in users {
+                let key = Self::mention_key(user_id, message_id);
+                batch.put_cf(&cf_mentions, key, target.to_bytes());
+            }
+
+            db.write(batch).context("Failed to write mention index")?;
+
+            Ok(())
+        })
+        .await
+        .context("Failed to spawn blocking task for index_mentions")?
+    }
+
+    /// Replaces the mentions of an edited message, `before` is dropped first.
+    pub async fn reindex_mentions(
+        &self,
+        message_id: snowflake_id,
+        target: ReadTarget,
+        before: Vec<id>,
+        after: Vec<id>,
+    ) -> Result<()> {
+        let db = self.db.clone();
+        tokio::task::spawn_blocking(move || {
+            let cf_mentions = db
+                .cf_handle(CF_MENTION_INDEX)
+                .context("CF_MENTION_INDEX cf not found")?;
+
+            let mut batch = rocksdb::WriteBatch::default();
+
+            for user_id in before {
+                batch.delete_cf(&cf_mentions, Self::mention_key(user_id, message_id));
+            }
+
+            for user_id in after {
+                let key = Self::mention_key(user_id, message_id);
+                batch.put_cf(&cf_mentions, key, target.to_bytes());
+            }
+
+            db.write(batch).context("Failed to reindex mentions")?;
+
+            Ok(())
+        })
+        .await
+        .context("Failed to spawn blocking task for reindex_mentions")?
+    }
+
+    fn mention_key(user_id: id, message_id: snowflake_id) -> [u8; 12] {
+        let mut key = [0u8; 12];
+        key[0..4].copy_from_slice(&user_id.to_be_bytes());
+        key[4..12].copy_from_slice(&message_id.to_be_bytes());
+        key
+    }
+
+    /// Entries whose message was deleted are dropped from the index while reading.
+    pub async fn get_mentions(
+        &self,
+        user_id: id,
+        len: Option<i64>,
+        before: Option<snowflake_id>,
+    ) -> Result<Vec<StoredMessage>> {
+        let db = self.db.clone();
+        tokio::task::spawn_blocking(move || {
+            let limit = page_len(len);
+            let cf_mentions = db
+                .cf_handle(CF_MENTION_INDEX)
+                .context("CF_MENTION_INDEX cf not found")?;
+            let cf_messages = db
+                .cf_handle(CF_MESSAGES)
+                .context("CF_MESSAGES cf not found")?;
+
+            let prefix = user_id.to_be_bytes();
+            let mut messages = Vec::with_capacity(limit);
+            let mut stale = Vec::new();
+
+            Self::scan_ids_reverse(&db, &cf_mentions, &prefix, before, None, |message_id| {
+                match db
+                    .get_cf(&cf_messages, message_id.to_be_bytes())
+                    .context("Failed to get message bytes")?
+                {
+                    Some(message_bytes) => {
+                        let stored: StoredMessage = rmp_serde::from_slice(&message_bytes)
+                            .context("Failed to deserialize message")?;
+                        messages.push(stored);
+                    }
+                    None => stale.push(message_id),
+                }
+
+                Ok(messages.len() < limit)
+            })?;
+
+            for message_id in stale {
+                let mut key = [0u8; 12];
+                key[0..4].copy_from_slice(&prefix);
+                key[4..12].copy_from_slice(&message_id.to_be_bytes());
+                db.delete_cf(&cf_mentions, key)
+                    .context("Failed to delete stale mention")?;
+            }
+
+            Ok(messages)
+        })
+        .await
+        .context("Failed to spawn blocking task for get_mentions")?
+    }
+
+    pub async fn mark_read(
+        &self,
+        user_id: id,
+        target: ReadTarget,
+        message_id: snowflake_id,
+    ) -> Result<()> {
+        let db = self.db.clone();
+        tokio::task::spawn_blocking(move || {
+            let cf_read = db
+                .cf_handle(CF_READ_STATES)
+                .context("CF_READ_STATES cf not found")?;
+
+            /*
+                Read States:
+                    user_id + target tag + target_id -> message_id
+            */
+
+            db.put_cf(&cf_read, target.key(user_id), message_id.to_be_bytes())
+                .context("Failed to put read state")?;
+
+            Ok(())
+        })
+        .await
+        .context("Failed to spawn blocking task for mark_read")?
+    }
+
+    fn count_after(
+        db: &Arc<RocksDB>,
+        cf: &Arc<rocksdb::BoundColumnFamily>,
+        prefix: &[u8],
+        after: Option<snowflake_id>,
+    ) -> Result<usize> {
+        let mut seek_key = prefix.to_vec();
+        seek_key.extend_from_slice(&after.map_or(0, |after| after.0 + 1).to_be_bytes());
+
+        let iter = db.iterator_cf(
+            cf,
+            rocksdb::IteratorMode::From(&seek_key, rocksdb::Direction::Forward),
+        );
+
+        let mut count = 0;
+
+        for item in iter {
+            let (key, _) = item.context("Failed to get item from iterator")?;
+
+            if !key.starts_with(prefix) || count >= MAX_UNREAD_COUNT {
+                break;
+            }
+
+            if key.len() == prefix.len() + 8 {
+                count += 1;
+            }
+        }
+
+        Ok(count)
+    }
+
+    /// Read position, unread and mention counts of each target for a user.
+    pub async fn read_states(
+        &self,
+        user_id: id,
+        targets: Vec<ReadTarget>,
+    ) -> Result<HashMap<ReadTarget, ReadState>> {
+        let db = self.db.clone();
+        tokio::task::spawn_blocking(move || {
+            let cf_read = db
+                .cf_handle(CF_READ_STATES)
+                .context("CF_READ_STATES cf not found")?;
+            let cf_channel = db
+                .cf_handle(CF_CHANNEL_INDEX)
+                .context("CF_CHANNEL_INDEX cf not found")?;
+            let cf_dm = db
+                .cf_handle(CF_DM_INDEX)
+                .context("CF_DM_INDEX cf not found")?;
+            let cf_mentions = db
+                .cf_handle(CF_MENTION_INDEX)
+                .context("CF_MENTION_INDEX cf not found")?;
+            let cf_messages = db
+                .cf_handle(CF_MESSAGES)
+                .context("CF_MESSAGES cf not found")?;
+
+            let mut states = HashMap::with_capacity(targets.len());
+
+            for target in targets {
+                let last_read = db
+                    .get_pinned_cf(&cf_read, target.key(user_id))
+                    .context("Failed to get read state")?
+                    .and_then(|bytes| bytes.as_ref().try_into().ok())
+                    .map(|bytes| snowflake_id(u64::from_be_bytes(bytes)));
+
+                let cf_index = match target {
+                    ReadTarget::Channel(_) => &cf_channel,
+                    ReadTarget::Direct(_) => &cf_dm,
+                };
+
+                let unread = Self::count_after(
+                    &db,
+                    cf_index,
+                    &target.scope(user_id).index_prefix(),
+                    last_read,
+                )?;
+
+                states.insert(
+                    target,
+                    ReadState {
+                        last_read,
+                        unread,
+                        mentions: 0,
+                    },
+                );
+            }
+
+            // Mentions are newest first, so the scan can stop at the oldest read position.
+            let oldest_read = states
+                .values()
+                .map(|state| state.last_read.unwrap_or_default())
+                .min()
+                .unwrap_or(snowflake_id(u64::MAX));
+
+            let prefix = user_id.to_be_bytes();
+            let mut seek_key = prefix.to_vec();
+            seek_key.extend_from_slice(&u64::MAX.to_be_bytes());
+
+            let iter = db.iterator_cf(
+                &cf_mentions,
+                rocksdb::IteratorMode::From(&seek_key, rocksdb::Direction::Reverse),
+            );
+
+            for (scanned, item) in iter.enumerate() {
+                let (key, value) = item.context("Failed to get item from iterator")?;
+
+                if !key.starts_with(&prefix) || key.len() != 12 || scanned >= MAX_UNREAD_COUNT {
+                    break;
+                }
+
+                let message_id = snowflake_id(u64::from_be_bytes(key[4..12].try_into().unwrap()));
+
+                if message_id <= oldest_read {
+                    break;
+                }
+
+                let Some(state) = ReadTarget::from_bytes(&value).and_then(|t| states.get_mut(&t))
+                else {
+                    continue;
+                };
+
+                if state
+                    .last_read
+                    .is_some_and(|last_read| message_id <= last_read)
+                    || state.mentions >= MAX_UNREAD_COUNT
+                {
+                    continue;
+                }
+
+                if db
+                    .get_pinned_cf(&cf_messages, message_id.to_be_bytes())
+                    .context("Failed to get message bytes")?
+                    .is_some()
+                {
+                    state.mentions += 1;
+                }
+            }
+
+            Ok(states)
+        })
+        .await
+        .context("Failed to spawn blocking task for read_states")?
+    }
+
+    fn scheduled_key(due_at: DateTime<Utc>, scheduled_id: snowflake_id) -> [u8; 16] {
+        let mut key = [0u8; 16];
+        key[0..8].copy_from_slice(&(due_at.timestamp_millis().max(0) as u64).to_be_bytes());
+        key[8..16].copy_from_slice(&scheduled_id.to_be_bytes());
+        key
+    }
+
+    fn user_scheduled_key(user_id: id, scheduled_id: snowflake_id) -> [u8; 12] {
+        let mut key = [0u8; 12];
+        key[0..4].copy_from_slice(&user_id.to_be_bytes());
+        key[4..12].copy_from_slice(&scheduled_id.to_be_bytes());
+        key
+    }
+
+    fn put_scheduled(
+        db: &Arc<RocksDB>,
+        batch: &mut rocksdb::WriteBatch,
+        scheduled: &ScheduledMessage,
+    ) -> Result<()> {
+        let cf_scheduled = db
+            .cf_handle(CF_SCHEDULED)
+            .context("CF_SCHEDULED cf not found")?;
+        let cf_user_scheduled = db
+            .cf_handle(CF_USER_SCHEDULED)
+            .context("CF_USER_SCHEDULED cf not found")?;
+
+        /*
+            Scheduled:
+                due_at + scheduled_id -> scheduled message
+
+            User Scheduled:
+                user_id + scheduled_id -> due_at
+        */
+
+        let scheduled_bytes =
+            rmp_serde::to_vec_named(scheduled).context("Failed to serialize scheduled message")?;
+        let key = Self::scheduled_key(scheduled.due_at, scheduled.id);
+
+        batch.put_cf(&cf_scheduled, key, scheduled_bytes);
+        batch.put_cf(
+            &cf_user_scheduled,
+            Self::user_scheduled_key(scheduled.from, scheduled.id),
+            &key[0..8],
+        );
+
+        Ok(())
+    }
+
+    fn get_user_scheduled(
+        db: &Arc<RocksDB>,
+        user_id: id,
+        scheduled_id: snowflake_id,
+    ) -> Result<Option<ScheduledMessage>> {
+        let cf_scheduled = db
+            .cf_handle(CF_SCHEDULED)
+            .context("CF_SCHEDULED cf not found")?;
+        let cf_user_scheduled = db
+            .cf_handle(CF_USER_SCHEDULED)
+            .context("CF_USER_SCHEDULED cf not found")?;
+
+        let Some(due) = db
+            .get_pinned_cf(
+                &cf_user_scheduled,
+                Self::user_scheduled_key(user_id, scheduled_id),
+            )
+            .context("Failed to get user scheduled")?
+        else {
+            return Ok(None);
+        };
+
+        let mut key = [0u8; 16];
+        key[0..8].copy_from_slice(&due);
+        key[8..16].copy_from_slice(&scheduled_id.to_be_bytes());
+
+        db.get_cf(&cf_scheduled, key)
+            .context("Failed to get scheduled message")?
+            .map(|bytes| {
+                rmp_serde::from_slice(&bytes).context("Failed to deserialize scheduled message")
+            })
+            .transpose()
+    }
+
+    fn delete_scheduled(
+        db: &Arc<RocksDB>,
+        batch: &mut rocksdb::WriteBatch,
+        scheduled: &ScheduledMessage,
+    ) -> Result<()> {
+        let cf_scheduled = db
+            .cf_handle(CF_SCHEDULED)
+            .context("CF_SCHEDULED cf not found")?;
+        let cf_user_scheduled = db
+            .cf_handle(CF_USER_SCHEDULED)
+            .context("CF_USER_SCHEDULED cf not found")?;
+
+        batch.delete_cf(
+            &cf_scheduled,
+            Self::scheduled_key(scheduled.due_at, scheduled.id),
+        );
+        batch.delete_cf(
+            &cf_user_scheduled,
+            Self::user_scheduled_key(scheduled.from, scheduled.id),
+        );
+
+        Ok(())
+    }
+
+    pub async fn schedule(&self, scheduled: ScheduledMessage) -> Result<()> {
+        let db = self.db.clone();
+        let lock = self.schedule_lock.clone();
+        tokio::task::spawn_blocking(move || {
+            let _guard = lock.lock();
+
+            let mut batch = rocksdb::WriteBatch::default();
+            Self::put_scheduled(&db, &mut batch, &scheduled)?;
+            db.write(batch)
+                .context("Failed to write scheduled message")?;
+
+            Ok(())
+        })
+        .await
+        .context("Failed to spawn blocking task for schedule")?
+    }
+
+    /// Pending scheduled messages of a user, soonest first.
+    pub async fn get_scheduled(&self, user_id: id) -> Result<Vec<ScheduledMessage>> {
+        let db = self.db.clone();
+        tokio::task::spawn_blocking(move || {
+            let cf_user_scheduled = db
+                .cf_handle(CF_USER_SCHEDULED)
+                .context("CF_USER_SCHEDULED cf not found")?;
+
+            let prefix = user_id.to_be_bytes();
+            let mut scheduled = Vec::new();
+
+            for item in db.prefix_iterator_cf(&cf_user_scheduled, prefix) {
+                let (key, _) = item.context("Failed to get item from iterator")?;
+
+                if !key.starts_with(&prefix) {
+                    break;
+                }
+
+                if key.len() != 12 {
+                    continue;
+                }
+
+                let scheduled_id = snowflake_id(u64::from_be_bytes(key[4..12].try_into()?));
+
+                if let Some(message) = Self::get_user_scheduled(&db, user_id, scheduled_id)? {
+                    scheduled.push(message);
+                }
+            }
+
+            scheduled.sort_by_key(|message| message.due_at);
+
+            Ok(scheduled)
+        })
+        .await
+        .context("Failed to spawn blocking task for get_scheduled")?
+    }
+
+    /// Changes the content or due time of a pending message. Returns none when
+    /// it was already sent or cancelled.
+    pub async fn update_scheduled(
+        &self,
+        user_id: id,
+        scheduled_id: snowflake_id,
+        data: Option<Data>,
+        due_at: Option<DateTime<Utc>>,
+    ) -> Result<Option<ScheduledMessage>> {
+        let db = self.db.clone();
+        let lock = self.schedule_lock.clone();
+        tokio::task::spawn_blocking(move || {
+            let _guard = lock.lock();
+
+            let Some(previous) = Self::get_user_scheduled(&db, user_id, scheduled_id)? else {
+                return Ok(None);
+            };
+
+            let mut scheduled = previous.clone();
+
+            if let Some(data) = data {
+                scheduled.data = data;
+            }
+            if let Some(due_at) = due_at {
+                scheduled.due_at = due_at;
+            }
+
+            let mut batch = rocksdb::WriteBatch::default();
+            Self::delete_scheduled(&db, &mut batch, &previous)?;
+            Self::put_scheduled(&db, &mut batch, &scheduled)?;
+            db.write(batch)
+                .context("Failed to update scheduled message")?;
+
+            Ok(Some(scheduled))
+        })
+        .await
+        .context("Failed to spawn blocking task for update_scheduled")?
+    }
+
+    pub async fn cancel_scheduled(&self, user_id: id, scheduled_id: snowflake_id) -> Result<bool> {
+        let db = self.db.clone();
+        let lock = self.schedule_lock.clone();
+        tokio::task::spawn_blocking(move || {
+            let _guard = lock.lock();
+
+            let Some(scheduled) = Self::get_user_scheduled(&db, user_id, scheduled_id)? else {
+                return Ok(false);
+            };
+
+            let mut batch = rocksdb::WriteBatch::default();
+            Self::delete_scheduled(&db, &mut batch, &scheduled)?;
+            db.write(batch)
+                .context("Failed to cancel scheduled message")?;
+
+            Ok(true)
+        })
+        .await
+        .context("Failed to spawn blocking task for cancel_scheduled")?
+    }
+
+    /// Removes and returns the messages due by `now`. A message is taken before it
+    /// is sent, so a crash in between drops it rather than sending it twice.
+    pub async fn take_due_scheduled(&self, now: DateTime<Utc>) -> Result<Vec<ScheduledMessage>> {
+        let db = self.db.clone();
+        let lock = self.schedule_lock.clone();
+        tokio::task::spawn_blocking(move || {
+            let _guard = lock.lock();
+
+            let cf_scheduled = db
+                .cf_handle(CF_SCHEDULED)
+                .context("CF_SCHEDULED cf not found")?;
+
+            let now_millis = now.timestamp_millis().max(0) as u64;
+            let mut due = Vec::new();
+
+            for item in db.iterator_cf(&cf_scheduled, rocksdb::IteratorMode::Start) {
+                let (key, value) = item.context("Failed to get item from iterator")?;
+
+                if key.len() != 16 {
+                    continue;
+                }
+
+                if u64::from_be_bytes(key[0..8].try_into()?) > now_millis {
+                    break;
+                }
+
+                let scheduled: ScheduledMessage = rmp_serde::from_slice(&value)
+                    .context("Failed to deserialize scheduled message")?;
+                due.push(scheduled);
+            }
+
+            let mut batch = rocksdb::WriteBatch::default();
+
+            for scheduled in &due {
+                Self::delete_scheduled(&db, &mut batch, scheduled)?;
+            }
+
+            db.write(batch)
+                .context("Failed to take scheduled messages")?;
+
+            Ok(due)
+        })
+        .await
+        .context("Failed to spawn blocking task for take_due_scheduled")?
+    }
+
+    pub async fn get_revisions(&self, message_id: snowflake_id) -> Result<Vec<StoredRevision>> {
+        let db = self.db.clone();
+        tokio::task::spawn_blocking(move || {
+            let cf_revisions = db
+                .cf_handle(CF_REVISIONS)
+                .context("CF_REVISIONS cf not found")?;
+
+            let prefix = message_id.to_be_bytes();
+            let mut revisions = Vec::new();
+
+            for item in db.prefix_iterator_cf(&cf_revisions, prefix) {
+                let (key, value) = item.context("Failed to get item from iterator")?;
+
+                if !key.starts_with(&prefix) {
+                    break;
+                }
+
+                let revision: StoredRevision =
+                    rmp_serde::from_slice(&value).context("Failed to deserialize revision")?;
+                revisions.push(revision);
+            }
+
+            Ok(revisions)
+        })
+        .await
+        .context("Failed to spawn blocking task for get_revisions")?
+    }
+
+    pub async fn search(
+        &self,
+        scopes: Vec<SearchScope>,
+        filter: SearchFilter,
+    ) -> Result<Vec<StoredMessage>> {
+        let db = self.db.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut messages = Vec::new();
+
+            for scope in scopes {
+                messages.extend(Self::search_scope(&db, scope, &filter)?);
+            }
+
+            messages.sort_unstable_by_key(|message| std::cmp::Reverse(message.id));
+            messages.truncate(filter.limit);
+
+            Ok(messages)
+        })
+        .await
+        .context("Failed to spawn blocking task for search")?
+    }
+
+    /// Moves a group DM to the top of each member's DM list.
+    pub async fn touch_dms(
+        &self,
+        users: Vec<id>,
+        group_dm_id: id,
+        message_id: snowflake_id,
+    ) -> Result<()> {
+        let db = self.db.clone();
+        tokio::task::spawn_blocking(move || {
+            let cf_user_dms = db
+                .cf_handle(CF_USER_DMS)
+                .context("CF_USER_DMS cf not found")?;
+
+            /*
+                User DMs:
+                    user_id + group_dm_id -> message_id
+            */
+
+            let mut batch = rocksdb::WriteBatch::default();
+
+            for user_id in users {
+                let mut key = [0u8; 8];
+                key[0..4].copy_from_slice(&user_id.to_be_bytes());
+                key[4..8].copy_from_slice(&group_dm_id.to_be_bytes());
+                batch.put_cf(&cf_user_dms, key, message_id.to_be_bytes());
+            }
+
+            db.write(batch).context("Failed to put user dms")?;
+
+            Ok(())
+        })
+        .await
+        .context("Failed to spawn blocking task for touch_dms")?
     }
 
     pub async fn remove_dm(&self, from: id, to: id) -> Result<()> {
@@ -1,4 +1,11 @@
+pub mod audit_log;
+pub mod backup;
+pub mod disappearing;
 pub mod group;
+pub mod group_dm;
 pub mod message;
+pub mod poll;
 pub mod reaction;
+pub mod retention;
+pub mod timeout;
 pub mod user;
@@ -0,0 +1,326 @@
+use crate::db::message::StoredMessage;
+use crate::id::id;
+use crate::message::data::Poll;
+use crate::message::snowflake::snowflake_id;
+use crate::message::{Ack, Message};
+use crate::state::group::Permissions;
+use crate::{State, db};
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use std::collections::HashMap;
+use tokio::time::{self, Duration};
+
+const CLOSE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Votes per option. Voters are left out of anonymous polls.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PollResults {
+    pub counts: Vec<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub voters: Option<Vec<Vec<id>>>,
+    pub closed: bool,
+}
+
+impl PollResults {
+    fn new(poll: &Poll, votes: &[(usize, id)], now: DateTime<Utc>) -> Self {
+        let mut voters = vec![Vec::new(); poll.options.len()];
+
+        for &(option, user_id) in votes {
+            if let Some(option_voters) = voters.get_mut(option) {
+                option_voters.push(user_id);
+            }
+        }
+
+        Self {
+            counts: voters.iter().map(|v| v.len() as u32).collect(),
+            voters: (!poll.anonymous).then_some(voters),
+            closed: poll.is_closed(now),
+        }
+    }
+}
+
+pub async fn create(pool: &PgPool, message_id: snowflake_id, poll: &Poll) -> Result<()> {
+    sqlx::query!(
+        "INSERT INTO polls (message_id, expires_at) VALUES ($1, $2) ON CONFLICT DO NOTHING",
+        *message_id as i64,
+        poll.expires_at,
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn delete_for_messages(pool: &PgPool, message_ids: &[snowflake_id]) -> Result<()> {
+    let message_ids: Vec<i64> = message_ids
+        .iter()
+        .map(|message_id| message_id.0 as i64)
+        .collect();
+
+    sqlx::query!("DELETE FROM polls WHERE message_id = ANY($1)", &message_ids)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+async fn vote(
+    pool: &PgPool,
+    message_id: snowflake_id,
+    user_id: id,
+    option: u16,
+    multi_select: bool,
+) -> Result<()> {
+    let mut tx = pool.begin().await?;
+
+    if !multi_select {
+        sqlx::query!(
+            "DELETE FROM poll_votes WHERE message_id = $1 AND user_id = $2",
+            *message_id as i64,
+            *user_id,
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    sqlx::query!(
+        "INSERT INTO poll_votes (message_id, user_id, option) VALUES ($1, $2, $3) \
+         ON CONFLICT DO NOTHING",
+        *message_id as i64,
+        *user_id,
+        option as i16,
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+    Ok(())
+}
+
+async fn unvote(pool: &PgPool, message_id: snowflake_id, user_id: id, option: u16) -> Result<()> {
+    sqlx::query!(
+        "DELETE FROM poll_votes WHERE message_id = $1 AND user_id = $2 AND option = $3",
+        *message_id as i64,
+        *user_id,
+        option as i16,
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Votes of the given polls as (option, user) pairs, oldest first.
+async fn votes(
+    pool: &PgPool,
+    message_ids: &[snowflake_id],
+) -> Result<HashMap<snowflake_id, Vec<(usize, id)>>> {
+    let message_ids: Vec<i64> = message_ids
+        .iter()
+        .map(|message_id| message_id.0 as i64)
+        .collect();
+
+    let rows = sqlx::query!(
+        r#"SELECT message_id, option, user_id AS "user_id: id" FROM poll_votes
+           WHERE message_id = ANY($1) ORDER BY created_at"#,
+        &message_ids,
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows.into_iter().fold(HashMap::new(), |mut acc, row| {
+        acc.entry(snowflake_id(row.message_id as u64))
+            .or_insert_with(Vec::new)
+            .push((row.option as usize, row.user_id));
+        acc
+    }))
+}
+
+pub async fn results(pool: &PgPool, message_id: snowflake_id, poll: &Poll) -> Result<PollResults> {
+    let votes = votes(pool, &[message_id]).await?;
+
+    Ok(PollResults::new(
+        poll,
+        votes
+            .get(&message_id)
+            .map(Vec::as_slice)
+            .unwrap_or_default(),
+        Utc::now(),
+    ))
+}
+
+/// Fills in the tallies of the polls among `messages`.
+pub async fn attach_results(pool: &PgPool, messages: &mut [StoredMessage]) -> Result<()> {
+    let poll_ids: Vec<snowflake_id> = messages
+        .iter()
+        .filter(|message| message.data.poll().is_some())
+        .map(|message| message.id)
+        .collect();
+
+    if poll_ids.is_empty() {
+        return Ok(());
+    }
+
+    let votes = votes(pool, &poll_ids).await?;
+    let now = Utc::now();
+
+    for message in messages.iter_mut() {
+        if let Some(poll) = message.data.poll() {
+            message.poll = Some(PollResults::new(
+                poll,
+                votes
+                    .get(&message.id)
+                    .map(Vec::as_slice)
+                    .unwrap_or_default(),
+                now,
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+async fn notify_poll(state: &State, ack: Message<Ack>, stored: &StoredMessage) -> Result<()> {
+    if let Some(gid) = stored.group_id {
+        if let Some(group) = state.groups.get(&gid) {
+            group.notify_with_permissions(ack, Permissions::VIEW_MESSAGES, Some(stored.to), state);
+        }
+    } else {
+        for participant in db::group_dm::participants(state, stored.from, stored.to).await? {
+            if let Some(u) = state.users.get(&participant) {
+                u.send_message(ack.clone());
+            }
+        }
+    }
+
+    Ok(())
+}
+
+pub async fn handle_vote(
+    state: &State,
+    event_id: snowflake_id,
+    user_id: id,
+    target_message_id: snowflake_id,
+    option: u16,
+    add: bool,
+) -> Result<()> {
+    let stored = state.messages.get(target_message_id).await?;
+
+    let Some(poll) = stored.data.poll() else {
+        anyhow::bail!("Message is not a poll");
+    };
+
+    if let Some(gid) = stored.group_id {
+        let group = state
+            .groups
+            .get(&gid)
+            .ok_or_else(|| anyhow::anyhow!("Group not found"))?;
+
+        if !group
+            .compute_permissions(user_id, Some(stored.to))
+            .contains(Permissions::VIEW_MESSAGES)
+        {
+            anyhow::bail!("You don't have permission to vote in this poll");
+        }
+    } else if !db::group_dm::participants(state, stored.from, stored.to)
+        .await?
+        .contains(&user_id)
+    {
+        anyhow::bail!("You don't have permission to vote in this poll");
+    }
+
+    if poll.is_closed(Utc::now()) {
+        anyhow::bail!("Poll is closed");
+    }
+
+    if option as usize >= poll.options.len() {
+        anyhow::bail!("Invalid poll option");
+    }
+
+    if add {
+        vote(
+            &state.pool,
+            target_message_id,
+            user_id,
+            option,
+            poll.multi_select,
+        )
+        .await?;
+    } else {
+        unvote(&state.pool, target_message_id, user_id, option).await?;
+    }
+
+    let results = results(&state.pool, target_message_id, poll).await?;
+
+    // Event ids carry the sender in their high bits, so anonymous polls get a fresh one.
+    let (ack_id, from) = if poll.anonymous {
+        (state.snowflake.generate(), stored.from)
+    } else {
+        (event_id, user_id)
+    };
+
+    let ack = Message {
+        id: ack_id,
+        from,
+        to: stored.to,
+        data: Ack::PollUpdated {
+            message: target_message_id,
+            results,
+        },
+        ..Message::default()
+    };
+
+    notify_poll(state, ack, &stored).await
+}
+
+async fn close_expired(state: &State) -> Result<()> {
+    let expired = sqlx::query_scalar!(
+        "UPDATE polls SET closed = true WHERE NOT closed AND expires_at <= now() \
+         RETURNING message_id"
+    )
+    .fetch_all(&state.pool)
+    .await?;
+
+    for message_id in expired {
+        let message_id = snowflake_id(message_id as u64);
+
+        let Ok(stored) = state.messages.get(message_id).await else {
+            continue;
+        };
+
+        let Some(poll) = stored.data.poll() else {
+            continue;
+        };
+
+        let results = results(&state.pool, message_id, poll).await?;
+
+        let ack = Message {
+            id: state.snowflake.generate(),
+            from: stored.from,
+            to: stored.to,
+            data: Ack::PollClosed {
+                message: message_id,
+                results,
+            },
+            ..Message::default()
+        };
+
+        notify_poll(state, ack, &stored).await?;
+    }
+
+    Ok(())
+}
+
+pub async fn poll_schedular(state: State) {
+    let mut interval = time::interval(CLOSE_INTERVAL);
+
+    loop {
+        tokio::select! {
+            _ = state.shutdown.cancelled() => break,
+            _ = interval.tick() => {}
+        }
+
+        if let Err(e) = close_expired(&state).await {
+            log::error!("Error while closing expired polls: {:?}", e);
+        }
+    }
+}
@@ -4,27 +4,100 @@ use crate::message::{Ack, Message};
 use crate::state::group::Permissions;
 use crate::{State, db};
 use anyhow::Result;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use sqlx::PgPool;
+use std::collections::HashMap;
 
-#[derive(Serialize, sqlx::FromRow)]
+const MAX_EMOJI_CHARS: usize = 16;
+const CUSTOM_EMOJI_PREFIX: &str = "custom:";
+
+/// A unicode emoji sequence or the id of a custom emoji.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[serde(untagged)]
+pub enum Emoji {
+    Unicode(String),
+    Custom(id),
+}
+
+impl Emoji {
+    pub fn validate(&self) -> Result<()> {
+        match self {
+            Emoji::Unicode(emoji) if !is_emoji(emoji) => anyhow::bail!("Invalid emoji"),
+            Emoji::Custom(emoji_id) if **emoji_id <= 0 => anyhow::bail!("Invalid custom emoji"),
+            _ => Ok(()),
+        }
+    }
+
+    fn to_db(&self) -> String {
+        match self {
+            Emoji::Unicode(emoji) => emoji.clone(),
+            Emoji::Custom(emoji_id) => format!("{}{}", CUSTOM_EMOJI_PREFIX, **emoji_id),
+        }
+    }
+
+    fn from_db(reaction: String) -> Self {
+        match reaction
+            .strip_prefix(CUSTOM_EMOJI_PREFIX)
+            .and_then(|emoji_id| emoji_id.parse().ok())
+        {
+            Some(emoji_id) => Emoji::Custom(id(emoji_id)),
+            None => Emoji::Unicode(reaction),
+        }
+    }
+}
+
+fn is_pictographic(c: char) -> bool {
+    matches!(c as u32,
+        0x00A9 | 0x00AE | 0x203C | 0x2049 | 0x2122 | 0x2139 | 0x24C2 | 0x3030 | 0x303D
+        | 0x3297 | 0x3299 | 0x2194..=0x21FF | 0x2300..=0x23FF | 0x25A0..=0x27BF
+        | 0x2900..=0x297F | 0x2B00..=0x2BFF | 0x1F000..=0x1FAFF)
+}
+
+fn is_emoji_component(c: char) -> bool {
+    matches!(
+        c as u32,
+        0x200D | 0xFE0E | 0xFE0F | 0x20E3 | 0xE0020..=0xE007F
+    ) || c.is_ascii_digit()
+        || c == '#'
+        || c == '*'
+}
+
+/// Accepts single emoji as well as flags, skin tones, keycaps and ZWJ sequences.
+fn is_emoji(emoji: &str) -> bool {
+    let count = emoji.chars().count();
+
+    (1..=MAX_EMOJI_CHARS).contains(&count)
+        && emoji
+            .chars()
+            .all(|c| is_pictographic(c) || is_emoji_component(c))
+        && emoji.chars().any(|c| is_pictographic(c) || c == '\u{20E3}')
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ReactionSummary {
+    pub reaction: Emoji,
+    pub count: u32,
+    pub me: bool,
+}
+
+#[derive(Serialize)]
 pub struct Reaction {
     pub user_id: id,
-    pub reaction: String,
+    pub reaction: Emoji,
 }
 
 pub async fn insert(
     pool: &PgPool,
     message_id: snowflake_id,
     user_id: id,
-    reaction: char,
+    reaction: &Emoji,
 ) -> Result<bool> {
     let row = sqlx::query!(
         "INSERT INTO reactions (message_id, user_id, reaction) VALUES ($1, $2, $3) \
          ON CONFLICT (message_id, user_id, reaction) DO NOTHING RETURNING 1 AS inserted",
         *message_id as i64,
         *user_id,
-        reaction.to_string(),
+        reaction.to_db(),
     )
     .fetch_optional(pool)
     .await?;
@@ -35,13 +108,13 @@ pub async fn delete(
     pool: &PgPool,
     message_id: snowflake_id,
     user_id: id,
-    reaction: char,
+    reaction: &Emoji,
 ) -> Result<()> {
     sqlx::query!(
         "DELETE FROM reactions WHERE message_id = $1 AND user_id = $2 AND reaction = $3",
         *message_id as i64,
         *user_id,
-        reaction.to_string(),
+        reaction.to_db(),
     )
     .execute(pool)
     .await?;
@@ -49,15 +122,54 @@ pub async fn delete(
 }
 
 pub async fn list(pool: &PgPool, message_id: snowflake_id) -> Result<Vec<Reaction>> {
-    let rows = sqlx::query_as!(
-        Reaction,
+    let rows = sqlx::query!(
         r#"SELECT user_id AS "user_id: id", reaction FROM reactions
            WHERE message_id = $1 ORDER BY created_at"#,
         *message_id as i64,
     )
     .fetch_all(pool)
     .await?;
-    Ok(rows)
+
+    Ok(rows
+        .into_iter()
+        .map(|row| Reaction {
+            user_id: row.user_id,
+            reaction: Emoji::from_db(row.reaction),
+        })
+        .collect())
+}
+
+/// Reaction counts per message, with whether `user_id` is among the reactors.
+pub async fn summaries(
+    pool: &PgPool,
+    user_id: id,
+    message_ids: &[snowflake_id],
+) -> Result<HashMap<snowflake_id, Vec<ReactionSummary>>> {
+    let message_ids: Vec<i64> = message_ids
+        .iter()
+        .map(|message_id| message_id.0 as i64)
+        .collect();
+
+    let rows = sqlx::query!(
+        r#"SELECT message_id, reaction, COUNT(*) AS "count!", BOOL_OR(user_id = $2) AS "me!"
+           FROM reactions WHERE message_id = ANY($1)
+           GROUP BY message_id, reaction ORDER BY MIN(created_at)"#,
+        &message_ids,
+        *user_id,
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows.into_iter().fold(HashMap::new(), |mut acc, row| {
+        acc.entry(snowflake_id(row.message_id as u64))
+            .or_insert_with(Vec::new)
+            .push(ReactionSummary {
+                reaction: Emoji::from_db(row.reaction),
+                count: row.count as u32,
+                me: row.me,
+            });
+        acc
+    }))
 }
 
 pub async fn delete_for_message(pool: &PgPool, message_id: snowflake_id) -> Result<()> {
@@ -70,16 +182,38 @@ pub async fn delete_for_message(pool: &PgPool, message_id: snowflake_id) -> Resu
     Ok(())
 }
 
+pub async fn delete_for_messages(pool: &PgPool, message_ids: &[snowflake_id]) -> Result<()> {
+    let message_ids: Vec<i64> = message_ids
+        .iter()
+        .map(|message_id| message_id.0 as i64)
+        .collect();
+
+    sqlx::query!(
+        "DELETE FROM reactions WHERE message_id = ANY($1)",
+        &message_ids,
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
 pub async fn handle_reaction(
     state: &State,
     event_id: snowflake_id,
     user_id: id,
     target_message_id: snowflake_id,
-    reaction: char,
+    reaction: Emoji,
     add: bool,
 ) -> Result<()> {
+    reaction.validate()?;
+
     let mut stored = state.messages.get(target_message_id).await?;
 
+    let participants = match stored.group_id {
+        Some(_) => Vec::new(),
+        None => db::group_dm::participants(state, stored.from, stored.to).await?,
+    };
+
     if let Some(gid) = stored.group_id {
         let group = state
             .groups
@@ -92,19 +226,39 @@ pub async fn handle_reaction(
         {
             anyhow::bail!("You don't have permission to react to this message");
         }
-    } else if user_id != stored.from && user_id != stored.to {
+
+        if add && group.is_timed_out(user_id) {
+            anyhow::bail!("You are timed out in this group");
+        }
+    } else if !participants.contains(&user_id) {
         anyhow::bail!("You don't have permission to react to this message");
     }
 
+    if add && stored.group_id.is_none() && !stored.to.is_group_dm() {
+        let other = if stored.from == user_id {
+            stored.to
+        } else {
+            stored.from
+        };
+
+        if state
+            .users
+            .get(&user_id)
+            .is_some_and(|user| user.state.is_blocked(other))
+        {
+            anyhow::bail!("Cannot react to messages from this user");
+        }
+    }
+
     if add {
         let inserted =
-            db::reaction::insert(&state.pool, target_message_id, user_id, reaction).await?;
+            db::reaction::insert(&state.pool, target_message_id, user_id, &reaction).await?;
         if inserted && stored.reacted != Some(true) {
             stored.reacted = Some(true);
             state.messages.overwrite(stored.clone()).await?;
         }
     } else {
-        db::reaction::delete(&state.pool, target_message_id, user_id, reaction).await?;
+        db::reaction::delete(&state.pool, target_message_id, user_id, &reaction).await?;
     }
 
     let ack_data = if add {
@@ -132,12 +286,9 @@ pub async fn handle_reaction(
             group.notify(ack, state);
         }
     } else {
-        if let Some(u) = state.users.get(&stored.from) {
-            u.send_message(ack.clone());
-        }
-        if stored.to != stored.from {
-            if let Some(u) = state.users.get(&stored.to) {
-                u.send_message(ack);
+        for participant in participants {
+            if let Some(u) = state.users.get(&participant) {
+                u.send_message(ack.clone());
             }
         }
     }
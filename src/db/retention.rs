@@ -0,0 +1,219 @@
+use crate::db::message::SearchScope;
+use crate::id::id;
+use crate::message::snowflake::snowflake_id;
+use crate::{State, db};
+use anyhow::Result;
+use chrono::{Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use tokio::time::{self, Duration as TokioDuration};
+
+const PURGE_INTERVAL: TokioDuration = TokioDuration::from_secs(60 * 60);
+
+pub const MIN_RETENTION_AGE: u32 = 60 * 60;
+
+/// How long messages are kept. `max_age` is in seconds, an empty policy keeps everything.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+pub struct Retention {
+    pub max_age: Option<u32>,
+    pub max_count: Option<u32>,
+}
+
+impl Retention {
+    pub fn from_row(max_age: Option<i32>, max_count: Option<i32>) -> Self {
+        Self {
+            max_age: max_age.map(|age| age as u32),
+            max_count: max_count.map(|count| count as u32),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.max_age.is_none() && self.max_count.is_none()
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.max_age.is_some_and(|age| age < MIN_RETENTION_AGE) {
+            anyhow::bail!(
+                "Retention age must be at least {} seconds",
+                MIN_RETENTION_AGE
+            );
+        }
+
+        if self.max_count.is_some_and(|count| count == 0) {
+            anyhow::bail!("Retention count must be at least 1");
+        }
+
+        if self.max_age.is_some_and(|age| age > i32::MAX as u32)
+            || self.max_count.is_some_and(|count| count > i32::MAX as u32)
+        {
+            anyhow::bail!("Retention is too large");
+        }
+
+        Ok(())
+    }
+}
+
+pub async fn set_group_retention(pool: &PgPool, group_id: id, retention: Retention) -> Result<()> {
+    sqlx::query!(
+        "UPDATE groups SET retention_max_age = $2, retention_max_count = $3 WHERE id = $1",
+        *group_id,
+        retention.max_age.map(|age| age as i32),
+        retention.max_count.map(|count| count as i32),
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn set_channel_retention(
+    pool: &PgPool,
+    channel_id: id,
+    retention: Retention,
+) -> Result<()> {
+    sqlx::query!(
+        "UPDATE channels SET retention_max_age = $2, retention_max_count = $3 WHERE id = $1",
+        *channel_id,
+        retention.max_age.map(|age| age as i32),
+        retention.max_count.map(|count| count as i32),
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn set_dm_retention(
+    pool: &PgPool,
+    user1: id,
+    user2: id,
+    retention: Retention,
+) -> Result<()> {
+    let (user1, user2) = user1.sort_pair(user2);
+
+    if retention.is_empty() {
+        sqlx::query!(
+            "DELETE FROM dm_retention WHERE user1 = $1 AND user2 = $2",
+            *user1,
+            *user2,
+        )
+        .execute(pool)
+        .await?;
+        return Ok(());
+    }
+
+    sqlx::query!(
+        "INSERT INTO dm_retention (user1, user2, max_age, max_count) VALUES ($1, $2, $3, $4) \
+         ON CONFLICT (user1, user2) DO UPDATE SET max_age = $3, max_count = $4",
+        *user1,
+        *user2,
+        retention.max_age.map(|age| age as i32),
+        retention.max_count.map(|count| count as i32),
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn get_dm_retention(pool: &PgPool, user1: id, user2: id) -> Result<Retention> {
+    let (user1, user2) = user1.sort_pair(user2);
+
+    let row = sqlx::query!(
+        "SELECT max_age, max_count FROM dm_retention WHERE user1 = $1 AND user2 = $2",
+        *user1,
+        *user2,
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(row
+        .map(|row| Retention::from_row(row.max_age, row.max_count))
+        .unwrap_or_default())
+}
+
+/// Text channels with a retention of their own or inherited from the group.
+async fn channel_policies(pool: &PgPool) -> Result<Vec<(id, Retention)>> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT
+            c.id AS "id:id",
+            CASE WHEN c.retention_max_age IS NULL AND c.retention_max_count IS NULL
+                THEN g.retention_max_age ELSE c.retention_max_age END AS max_age,
+            CASE WHEN c.retention_max_age IS NULL AND c.retention_max_count IS NULL
+                THEN g.retention_max_count ELSE c.retention_max_count END AS max_count
+        FROM channels c
+        JOIN groups g ON g.id = c.group_id
+        WHERE c.channel_type = false
+            AND (c.retention_max_age IS NOT NULL OR c.retention_max_count IS NOT NULL
+                OR g.retention_max_age IS NOT NULL OR g.retention_max_count IS NOT NULL)
+        "#
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| (row.id, Retention::from_row(row.max_age, row.max_count)))
+        .collect())
+}
+
+async fn dm_policies(pool: &PgPool) -> Result<Vec<(id, id, Retention)>> {
+    let rows = sqlx::query!(
+        r#"SELECT user1 AS "user1:id", user2 AS "user2:id", max_age, max_count FROM dm_retention"#
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| {
+            (
+                row.user1,
+                row.user2,
+                Retention::from_row(row.max_age, row.max_count),
+            )
+        })
+        .collect())
+}
+
+async fn purge_scope(state: &State, scope: SearchScope, retention: Retention) -> Result<()> {
+    let before = retention
+        .max_age
+        .map(|age| snowflake_id::from(Utc::now() - Duration::seconds(age as i64)));
+    let keep = retention.max_count.map(|count| count as usize);
+
+    let purged = state.messages.purge(scope, before, keep).await?;
+
+    if !purged.is_empty() {
+        db::reaction::delete_for_messages(&state.pool, &purged).await?;
+        db::poll::delete_for_messages(&state.pool, &purged).await?;
+        log::info!("Purged {} expired messages from {:?}", purged.len(), scope);
+    }
+
+    Ok(())
+}
+
+async fn purge_expired(state: &State) -> Result<()> {
+    for (channel_id, retention) in channel_policies(&state.pool).await? {
+        purge_scope(state, SearchScope::Channel(channel_id), retention).await?;
+    }
+
+    for (user1, user2, retention) in dm_policies(&state.pool).await? {
+        purge_scope(state, SearchScope::Direct(user1, user2), retention).await?;
+    }
+
+    Ok(())
+}
+
+pub async fn purge_schedular(state: State) {
+    let mut interval = time::interval(PURGE_INTERVAL);
+
+    loop {
+        tokio::select! {
+            _ = state.shutdown.cancelled() => break,
+            _ = interval.tick() => {}
+        }
+
+        if let Err(e) = purge_expired(&state).await {
+            log::error!("Error while purging expired messages: {:?}", e);
+        }
+    }
+}
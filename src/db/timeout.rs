@@ -0,0 +1,107 @@
+use crate::State;
+use crate::id::id;
+use crate::message::{Ack, Message};
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use tokio::time::{self, Duration};
+
+const EXPIRE_INTERVAL: Duration = Duration::from_secs(5);
+
+pub const MAX_TIMEOUT: u32 = 28 * 24 * 60 * 60;
+
+pub fn validate_duration(duration: u32) -> Result<()> {
+    if duration > MAX_TIMEOUT {
+        anyhow::bail!("Timeout can be at most {} seconds", MAX_TIMEOUT);
+    }
+
+    Ok(())
+}
+
+/// Sets the timeout of a member, `None` lifts it.
+pub async fn set_timeout(
+    pool: &PgPool,
+    group_id: id,
+    user_id: id,
+    until: Option<DateTime<Utc>>,
+) -> Result<()> {
+    sqlx::query!(
+        "UPDATE group_users SET timeout_until = $3 WHERE group_id = $1 AND user_id = $2",
+        *group_id,
+        *user_id,
+        until,
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Clears every elapsed timeout, returning the `(group_id, user_id)` pairs.
+async fn take_expired(pool: &PgPool, now: DateTime<Utc>) -> Result<Vec<(id, id)>> {
+    let expired = sqlx::query!(
+        "UPDATE group_users SET timeout_until = NULL WHERE timeout_until <= $1 \
+         RETURNING group_id, user_id",
+        now,
+    )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|row| (id(row.group_id), id(row.user_id)))
+    .collect();
+
+    Ok(expired)
+}
+
+async fn expire_timeouts(state: &State) -> Result<()> {
+    let now = Utc::now();
+
+    for (group_id, user_id) in take_expired(&state.pool, now).await? {
+        let _lock = state.group_locks.write(group_id).await;
+
+        let Some(mut group) = state.groups.get_mut(&group_id) else {
+            continue;
+        };
+
+        // A newer timeout may have been set since the row was cleared.
+        let elapsed = group
+            .members
+            .get(&user_id)
+            .and_then(|member| member.timeout_until())
+            .is_some_and(|until| until <= now);
+
+        if !elapsed {
+            continue;
+        }
+
+        group.set_timeout(user_id, None);
+
+        let ack = Message {
+            from: group_id,
+            to: user_id,
+            data: Ack::TimedOut { until: None },
+            ..Message::default()
+        };
+
+        let group = group.downgrade();
+        group.notify(ack, state);
+        group.notify_permissions([user_id], None, state);
+    }
+
+    Ok(())
+}
+
+pub async fn timeout_schedular(state: State) {
+    let mut interval = time::interval(EXPIRE_INTERVAL);
+
+    loop {
+        tokio::select! {
+            _ = state.shutdown.cancelled() => break,
+            _ = interval.tick() => {}
+        }
+
+        if let Err(e) = expire_timeouts(&state).await {
+            log::error!("Error while expiring timeouts: {:?}", e);
+        }
+    }
+}
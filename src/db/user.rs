@@ -388,7 +388,36 @@ pub async fn get_blocked_by(pool: &Pool<Postgres>, user_id: id) -> Result<Vec<id
     .await
 }
 
+/// Whether either user has blocked the other.
+pub async fn is_blocked(
+    pool: &Pool<Postgres>,
+    user_1: id,
+    user_2: id,
+) -> Result<bool, sqlx::Error> {
+    let exists = sqlx::query_scalar!(
+        r#"
+        SELECT EXISTS(
+            SELECT 1 FROM blocks
+            WHERE ("from" = $1 AND "to" = $2)
+            OR ("from" = $2 AND "to" = $1)
+        )
+        "#,
+        *user_1,
+        *user_2
+    )
+    .fetch_one(pool)
+    .await?
+    .unwrap_or(false);
+
+    Ok(exists)
+}
+
+/// Blocks a user and removes any friendship or pending request between the two.
 pub async fn block(pool: &Pool<Postgres>, user_id: id, block_id: id) -> Result<()> {
+    let (user_1, user_2) = user_id.sort_pair(block_id);
+
+    let mut tx = pool.begin().await?;
+
     sqlx::query!(
         r#"
         INSERT INTO blocks ("from", "to")
@@ -398,8 +427,47 @@ pub async fn block(pool: &Pool<Postgres>, user_id: id, block_id: id) -> Result<(
         *user_id,
         *block_id
     )
-    .execute(pool)
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query!(
+        r#"
+        DELETE FROM friends
+        WHERE user_1 = $1 AND user_2 = $2
+        "#,
+        *user_1,
+        *user_2
+    )
+    .execute(&mut *tx)
     .await?;
 
+    sqlx::query!(
+        r#"
+        DELETE FROM friend_requests
+        WHERE ("from" = $1 AND "to" = $2)
+        OR ("from" = $2 AND "to" = $1)
+        "#,
+        *user_id,
+        *block_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
     Ok(())
 }
+
+pub async fn unblock(pool: &Pool<Postgres>, user_id: id, block_id: id) -> Result<bool> {
+    let result = sqlx::query!(
+        r#"
+        DELETE FROM blocks
+        WHERE "from" = $1 AND "to" = $2
+        "#,
+        *user_id,
+        *block_id
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
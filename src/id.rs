@@ -35,6 +35,11 @@ impl id {
             (other, self)
         }
     }
+
+    /// Group DM ids are negative, so they share DM lists with user ids.
+    pub fn is_group_dm(self) -> bool {
+        self.0 < 0
+    }
 }
 
 impl StdAdd<i32> for id {
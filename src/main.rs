@@ -15,12 +15,14 @@ use nohash_hasher::BuildNoHashHasher;
 use once_cell::sync::Lazy;
 use sqlx::PgPool;
 use state::app::AppState;
+use state::typing::Typing;
 use std::env;
 use std::time::Duration;
 use tokio_util::sync::CancellationToken;
 
 pub type State = web::Data<AppState>;
 
+mod cli;
 mod db;
 mod id;
 mod lockmap;
@@ -64,11 +66,23 @@ async fn main() -> std::io::Result<()> {
     #[cfg(feature = "mail")]
     tokio::spawn(route::auth::clear_otp_schedular());
 
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    if let Some((command, args)) = args.split_first() {
+        if let Err(e) = cli::run(command, args).await {
+            eprintln!("{:#}", e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    db::backup::restore_if_missing().expect("Failed to restore the message store");
+
     let pool = db_connection()
         .await
         .expect("Failed to connect to database");
 
-    let message_store = db::message::MessageStore::open("data/messages")
+    let message_store = db::message::MessageStore::open(db::message::MESSAGE_STORE_PATH)
         .expect("Failed to open RocksDB message store");
 
     let messages = MessageService::new(message_store);
@@ -84,7 +98,9 @@ async fn main() -> std::io::Result<()> {
     let state = web::Data::new(AppState {
         users: DashMap::with_hasher_and_shard_amount(hasher.clone(), 8),
         groups: DashMap::with_hasher_and_shard_amount(hasher.clone(), 8),
+        group_dms: DashMap::with_hasher_and_shard_amount(hasher.clone(), 8),
         voice_direct: DashMap::with_hasher_and_shard_amount(hasher.clone(), 8),
+        typing: Typing::default(),
         user_locks: LockMap::new(),
         group_locks: LockMap::new(),
         pool,
@@ -94,6 +110,14 @@ async fn main() -> std::io::Result<()> {
         tracker: tracker.clone(),
     });
 
+    tracker.spawn(db::retention::purge_schedular(state.clone()));
+    tracker.spawn(db::backup::backup_schedular(state.clone()));
+    tracker.spawn(message::dispatch::scheduled_schedular(state.clone()));
+    tracker.spawn(db::poll::poll_schedular(state.clone()));
+    tracker.spawn(db::disappearing::reap_schedular(state.clone()));
+    tracker.spawn(db::timeout::timeout_schedular(state.clone()));
+    tracker.spawn(state::typing::typing_schedular(state.clone()));
+
     let state_ws = state.clone();
     tokio::spawn(async move {
         log::info!("WebSocket server listening on {}", 8081);
@@ -1,9 +1,14 @@
-use crate::db::message::StoredMessage;
+use crate::db::group_dm::GroupDm;
+use crate::db::message::{ReadState, ReadTarget, StoredMessage};
+use crate::db::poll::PollResults;
+use crate::db::reaction::Emoji;
+use crate::db::retention::Retention;
 use crate::id::id;
 use crate::message::event;
 use crate::message::snowflake::snowflake_id;
-use crate::state::group::{Group, OverrideTarget, Permissions};
+use crate::state::group::{Group, OverrideTarget, Permissions, Thread};
 use crate::state::user;
+use chrono::{DateTime, Utc};
 use serde::Serialize;
 use std::collections::HashMap;
 
@@ -11,6 +16,8 @@ use std::collections::HashMap;
 pub struct VoiceStateSnapshot {
     pub mute: bool,
     pub deafen: bool,
+    pub server_mute: bool,
+    pub server_deafen: bool,
 }
 
 #[serde_with::skip_serializing_none]
@@ -21,19 +28,53 @@ pub enum Ack {
     None,
     Error(String),
     MessageError(String),
+    /// Rejected by slow mode, `retry_after` is in milliseconds.
+    SlowMode {
+        retry_after: u64,
+    },
+
+    // Session
+    /// The n-th frame of the session, this one included, has sequence number n.
+    /// Connect with `seq_header=1` to get it as a big-endian u64 before every
+    /// frame, and resume with the last one received as `seq`.
+    Session {
+        session_id: snowflake_id,
+    },
+    Resumed {
+        replayed: usize,
+    },
+    InvalidSession,
 
     // Message
     Received(snowflake_id),
     Deleted(snowflake_id),
+    BulkDeleted(Vec<snowflake_id>),
     Overwritten(Box<StoredMessage>),
     Reacted {
         message: snowflake_id,
-        reaction: char,
+        reaction: Emoji,
     },
     RemovedReaction {
         message: snowflake_id,
-        reaction: char,
+        reaction: Emoji,
+    },
+    PollUpdated {
+        message: snowflake_id,
+        results: PollResults,
     },
+    PollClosed {
+        message: snowflake_id,
+        results: PollResults,
+    },
+    StartedTyping(id),
+    StoppedTyping(id),
+    Pinned(snowflake_id),
+    Unpinned(snowflake_id),
+    MarkedRead {
+        target: ReadTarget,
+        message: snowflake_id,
+    },
+    UpdatedRetention(Retention),
 
     // USER
     Initialized(Box<user::State>),
@@ -44,6 +85,21 @@ pub enum Ack {
     ReceivedFriendRequest,
     SentFriendRequest,
     DeletedFriend,
+    BlockedUser,
+    UnblockedUser,
+    JoinedGroupDm(GroupDm),
+    AddedGroupDmMember(id),
+    RemovedGroupDmMember {
+        user: id,
+        #[serialize_always]
+        owner: Option<id>,
+    },
+    UpdatedGroupDm {
+        #[serialize_always]
+        name: Option<String>,
+        #[serialize_always]
+        icon: Option<String>,
+    },
     UpdatedUser {
         name: Option<String>,
         avatar: Option<String>,
@@ -56,6 +112,8 @@ pub enum Ack {
         permissions: Permissions,
         channel_permissions: HashMap<id, Permissions>,
         voice_states: HashMap<id, VoiceStateSnapshot>,
+        threads: HashMap<id, Vec<Thread>>,
+        read_states: HashMap<id, ReadState>,
     },
     Unsubscribed,
     PermissionsChanged {
@@ -65,6 +123,10 @@ pub enum Ack {
     ChannelPermissionsChanged(Permissions),
     JoinedMember,
     LeftMember,
+    TimedOut {
+        #[serialize_always]
+        until: Option<DateTime<Utc>>,
+    },
     CreatedGroup {
         name: String,
         icon: Option<String>,
@@ -90,12 +152,15 @@ pub enum Ack {
         name: Option<String>,
         description: Option<String>,
         icon: Option<String>,
+        retention: Option<Retention>,
     },
     UpdatedChannel {
         name: Option<String>,
         #[serialize_always]
         title: Option<String>,
         position: Option<usize>,
+        retention: Option<Retention>,
+        slow_mode: Option<u32>,
     },
     UpdatedRole {
         name: Option<String>,
@@ -118,6 +183,13 @@ pub enum Ack {
         position: usize,
     },
 
+    // THREAD
+    CreatedThread(Box<Thread>),
+    SubscribedThread(snowflake_id),
+    UnsubscribedThread(snowflake_id),
+    ArchivedThread(snowflake_id),
+    UnarchivedThread(snowflake_id),
+
     // VOICE
     JoinedVoice {
         channel_id: id,
@@ -128,6 +200,8 @@ pub enum Ack {
     MovedToVoice(id),
     Muted(bool),
     Deafened(bool),
+    ServerMuted(bool),
+    ServerDeafened(bool),
 
     // ==== WATCH PARTY ====
     JoinedParty(id),
@@ -1,7 +1,14 @@
+use chrono::{DateTime, Duration, Utc};
 use serde::{Deserialize, Deserializer, Serialize, de};
 
+use crate::id::id;
 use crate::message::snowflake::snowflake_id;
 
+pub const MAX_POLL_OPTIONS: usize = 10;
+const MAX_POLL_QUESTION_CHARS: usize = 300;
+const MAX_POLL_OPTION_CHARS: usize = 100;
+const MAX_POLL_DURATION_DAYS: i64 = 30;
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct FileMeta {
     pub url: String,
@@ -9,6 +16,22 @@ pub struct FileMeta {
     pub size: String,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Default)]
+pub struct Mentions {
+    #[serde(default)]
+    pub users: Vec<id>,
+    #[serde(default)]
+    pub roles: Vec<id>,
+    #[serde(default)]
+    pub everyone: bool,
+}
+
+impl Mentions {
+    pub fn is_empty(&self) -> bool {
+        self.users.is_empty() && self.roles.is_empty() && !self.everyone
+    }
+}
+
 #[derive(Debug, Clone, Serialize, PartialEq)]
 #[serde_with::skip_serializing_none]
 pub struct MultiData {
@@ -17,6 +40,7 @@ pub struct MultiData {
     videos: Option<Vec<String>>,
     files: Option<Vec<FileMeta>>,
     links: Option<Vec<String>>,
+    mentions: Option<Mentions>,
 }
 
 impl MultiData {
@@ -27,6 +51,18 @@ impl MultiData {
     pub fn has_links(&self) -> bool {
         self.text.is_some() && self.links.is_some()
     }
+
+    pub fn text(&self) -> Option<&str> {
+        self.text.as_deref()
+    }
+
+    pub fn file_names(&self) -> impl Iterator<Item = &str> {
+        self.files.iter().flatten().map(|f| f.name.as_str())
+    }
+
+    pub fn mentions(&self) -> Option<&Mentions> {
+        self.mentions.as_ref()
+    }
 }
 
 impl<'de> Deserialize<'de> for MultiData {
@@ -41,6 +77,7 @@ impl<'de> Deserialize<'de> for MultiData {
             videos: Option<Vec<String>>,
             files: Option<Vec<FileMeta>>,
             links: Option<Vec<String>>,
+            mentions: Option<Mentions>,
         }
 
         impl Helper {
@@ -51,11 +88,15 @@ impl<'de> Deserialize<'de> for MultiData {
             fn has_links(&self) -> bool {
                 self.text.is_some() && self.links.is_some()
             }
+
+            fn has_mentions(&self) -> bool {
+                self.text.is_some() && self.mentions.as_ref().is_some_and(|m| !m.is_empty())
+            }
         }
 
         let helper = Helper::deserialize(deserializer)?;
 
-        if !helper.has_attachment() && !helper.has_links() {
+        if !helper.has_attachment() && !helper.has_links() && !helper.has_mentions() {
             return Err(de::Error::custom("At least one field must be Some"));
         }
 
@@ -65,10 +106,79 @@ impl<'de> Deserialize<'de> for MultiData {
             videos: helper.videos,
             files: helper.files,
             links: helper.links,
+            mentions: helper.mentions.filter(|m| !m.is_empty()),
         })
     }
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Poll {
+    pub question: String,
+    pub options: Vec<String>,
+    #[serde(default)]
+    pub multi_select: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub anonymous: bool,
+}
+
+impl Poll {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.question.trim().is_empty()
+            || self.question.chars().count() > MAX_POLL_QUESTION_CHARS
+        {
+            anyhow::bail!(
+                "Poll question must be 1-{} characters",
+                MAX_POLL_QUESTION_CHARS
+            );
+        }
+
+        if self.options.len() < 2 || self.options.len() > MAX_POLL_OPTIONS {
+            anyhow::bail!("Poll must have 2-{} options", MAX_POLL_OPTIONS);
+        }
+
+        if self
+            .options
+            .iter()
+            .any(|o| o.trim().is_empty() || o.chars().count() > MAX_POLL_OPTION_CHARS)
+        {
+            anyhow::bail!(
+                "Poll options must be 1-{} characters",
+                MAX_POLL_OPTION_CHARS
+            );
+        }
+
+        if let Some(expires_at) = self.expires_at {
+            let now = Utc::now();
+
+            if expires_at <= now {
+                anyhow::bail!("Poll expiry must be in the future");
+            }
+
+            if expires_at > now + Duration::days(MAX_POLL_DURATION_DAYS) {
+                anyhow::bail!("Polls can run for at most {} days", MAX_POLL_DURATION_DAYS);
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn is_closed(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+}
+
+/// Entries the server writes into a conversation, clients can't send them.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "system", rename_all = "snake_case")]
+pub enum SystemEntry {
+    DisappearingTimer { ttl: Option<u32> },
+    GroupDmCreated,
+    MemberAdded { user: id },
+    MemberRemoved { user: id },
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 #[serde(untagged)]
 pub enum Data {
@@ -88,6 +198,8 @@ pub enum Data {
         replied: snowflake_id,
         data: MultiData,
     },
+    Poll(Poll),
+    System(SystemEntry),
 }
 
 fn require_option<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
@@ -98,6 +210,36 @@ where
     Option::deserialize(deserializer)
 }
 
+impl Data {
+    pub fn has_attachment(&self) -> bool {
+        match self {
+            Data::MultiData(data) | Data::Reply { data, .. } => data.has_attachment(),
+            _ => false,
+        }
+    }
+
+    pub fn mentions(&self) -> Option<&Mentions> {
+        match self {
+            Data::MultiData(data) | Data::Reply { data, .. } => data.mentions(),
+            _ => None,
+        }
+    }
+
+    pub fn replied(&self) -> Option<snowflake_id> {
+        match self {
+            Data::Reply { replied, .. } => Some(*replied),
+            _ => None,
+        }
+    }
+
+    pub fn poll(&self) -> Option<&Poll> {
+        match self {
+            Data::Poll(poll) => Some(poll),
+            _ => None,
+        }
+    }
+}
+
 impl Default for Data {
     fn default() -> Self {
         Data::Text("".to_string())
@@ -1,13 +1,46 @@
 use super::ack::Ack;
-use crate::db::message::StoredMessage;
+use crate::db::message::{ReadTarget, ScheduledMessage, StoredMessage};
 use crate::id::id;
+use crate::message::data::Mentions;
 use crate::message::{Data, Event, Message, MessageType};
-use crate::state::group::Permissions;
-use crate::{State, msgpack};
+use crate::state::group::{Group, Permissions};
+use crate::state::typing::TypingTarget;
+use crate::{State, db, msgpack};
 use anyhow::Result;
 use bytes::Bytes;
+use chrono::Utc;
 use flume::Sender;
 use serde::Serialize;
+use std::fmt;
+use tokio::time::{self, Duration};
+
+const SCHEDULED_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Debug)]
+pub struct SlowModeError {
+    pub retry_after: chrono::Duration,
+}
+
+impl fmt::Display for SlowModeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Slow mode is enabled, wait {} seconds",
+            (self.retry_after.num_milliseconds() + 999) / 1000
+        )
+    }
+}
+
+impl std::error::Error for SlowModeError {}
+
+fn error_ack(error: &anyhow::Error) -> Ack {
+    match error.downcast_ref::<SlowModeError>() {
+        Some(slow_mode) => Ack::SlowMode {
+            retry_after: slow_mode.retry_after.num_milliseconds().max(0) as u64,
+        },
+        None => Ack::MessageError(error.to_string()),
+    }
+}
 
 pub async fn handle_bytes(
     bytes: Bytes,
@@ -50,16 +83,16 @@ pub async fn handle_bytes(
 
         let message_id = message.id;
 
-        if let Err(e) = dispatch_message(state, message, connection_id).await {
-            if let Some(user) = state.users.get(&user_id) {
-                let error = Message {
-                    id: message_id,
-                    data: Ack::MessageError(e.to_string()),
-                    to: user_id,
-                    ..Default::default()
-                };
-                user.send_message(error);
-            }
+        if let Err(e) = dispatch_message(state, message, connection_id).await
+            && let Some(user) = state.users.get(&user_id)
+        {
+            let error = Message {
+                id: message_id,
+                data: error_ack(&e),
+                to: user_id,
+                ..Default::default()
+            };
+            user.send_message(error);
         }
 
         return Ok(());
@@ -87,18 +120,92 @@ pub async fn handle_bytes(
 
 async fn dispatch_message(
     state: &State,
-    message: Message<Data>,
+    mut message: Message<Data>,
     connection_id: usize,
 ) -> Result<()> {
+    if let Some(poll) = message.data.poll() {
+        poll.validate()?;
+    }
+
+    if matches!(message.data, Data::System(_)) {
+        anyhow::bail!("System entries can't be sent");
+    }
+
     match message.r#type {
+        MessageType::Direct if message.to.is_group_dm() => {
+            dispatch_group_dm_message(state, message).await?;
+        }
+
         MessageType::Direct => {
-            let stored: StoredMessage = message.clone().try_into()?;
+            if message.thread.is_some() {
+                anyhow::bail!("Threads are only available in group channels");
+            }
+
+            // Scheduled messages can be sent while the author is offline.
+            let cached = state
+                .users
+                .get(&message.from)
+                .map(|user| user.state.is_blocked(message.to));
+
+            let blocked = match cached {
+                Some(blocked) => blocked,
+                None => db::user::is_blocked(&state.pool, message.from, message.to).await?,
+            };
+
+            if blocked {
+                anyhow::bail!("Cannot send messages to this user");
+            }
+
+            match message.ttl {
+                Some(ttl) => db::disappearing::validate_ttl(ttl)?,
+                None => {
+                    message.ttl =
+                        db::disappearing::get_timer(&state.pool, message.from, message.to).await?
+                }
+            }
+
+            let mentioned = match message.data.mentions() {
+                Some(mentions) => {
+                    conversation_mentions(mentions, message.from, &[message.from, message.to])?
+                }
+                None => Vec::new(),
+            };
+
+            let mut stored: StoredMessage = message.clone().try_into()?;
+            stored.expires_at = message
+                .ttl
+                .map(|ttl| Utc::now() + chrono::Duration::seconds(ttl as i64));
 
             state.messages.write(stored).await?;
+
+            if let Some(poll) = message.data.poll() {
+                db::poll::create(&state.pool, message.id, poll).await?;
+            }
+
+            state
+                .typing
+                .stop(message.from, TypingTarget::Direct(message.to));
+
+            if !mentioned.is_empty() {
+                state
+                    .messages
+                    .index_mentions(message.id, ReadTarget::Direct(message.from), mentioned)
+                    .await?;
+            }
+
+            state
+                .messages
+                .mark_read(message.from, ReadTarget::Direct(message.to), message.id)
+                .await?;
+
             send_message(state, message);
         }
 
         MessageType::Group(group_id) => {
+            if message.ttl.is_some() {
+                anyhow::bail!("Disappearing messages are only available in direct messages");
+            }
+
             let Some(group) = state.groups.get(&group_id) else {
                 anyhow::bail!("Group not found")
             };
@@ -110,6 +217,20 @@ async fn dispatch_message(
                 anyhow::bail!("You don't have permission to send messages");
             }
 
+            if let Some(thread_id) = message.thread {
+                let Some(thread) = group.threads.get(&thread_id) else {
+                    anyhow::bail!("Thread not found");
+                };
+
+                if thread.channel_id != message.to {
+                    anyhow::bail!("Thread does not belong to this channel");
+                }
+
+                if thread.archived {
+                    anyhow::bail!("Thread is archived");
+                }
+            }
+
             if let Data::MultiData(data) = &message.data {
                 if data.has_attachment()
                     && !group
@@ -128,9 +249,54 @@ async fn dispatch_message(
                 }
             }
 
+            let mentioned = match message.data.mentions() {
+                Some(mentions) => channel_mentions(&group, mentions, message.from, message.to)?,
+                None => Vec::new(),
+            };
+
+            let bypass_slow_mode = group
+                .compute_permissions(message.from, Some(message.to))
+                .intersects(Permissions::MANAGE_MESSAGES | Permissions::MANAGE_CHANNELS);
+
+            drop(group);
+
+            if !bypass_slow_mode
+                && let Some(mut group) = state.groups.get_mut(&group_id)
+                && let Some(channel) = group.channels.get_mut(&message.to)
+                && let Some(retry_after) = channel.try_send(message.from, Utc::now())
+            {
+                return Err(SlowModeError { retry_after }.into());
+            }
+
             let stored: StoredMessage = message.clone().try_into()?;
             state.messages.write(stored).await?;
 
+            if let Some(poll) = message.data.poll() {
+                db::poll::create(&state.pool, message.id, poll).await?;
+            }
+
+            state.typing.stop(
+                message.from,
+                TypingTarget::Channel {
+                    group_id,
+                    channel_id: message.to,
+                },
+            );
+
+            if !mentioned.is_empty() {
+                state
+                    .messages
+                    .index_mentions(message.id, ReadTarget::Channel(message.to), mentioned)
+                    .await?;
+            }
+
+            if message.thread.is_none() {
+                state
+                    .messages
+                    .mark_read(message.from, ReadTarget::Channel(message.to), message.id)
+                    .await?;
+            }
+
             send_group_message(state, message, group_id, connection_id);
         }
         _ => {}
@@ -139,6 +305,234 @@ async fn dispatch_message(
     Ok(())
 }
 
+async fn dispatch_group_dm_message(state: &State, message: Message<Data>) -> Result<()> {
+    if message.thread.is_some() {
+        anyhow::bail!("Threads are only available in group channels");
+    }
+
+    if message.ttl.is_some() {
+        anyhow::bail!("Disappearing messages are only available in one-to-one conversations");
+    }
+
+    let group_dm_id = message.to;
+    let members = db::group_dm::participants(state, message.from, group_dm_id).await?;
+
+    if !members.contains(&message.from) {
+        anyhow::bail!("You are not a member of this group DM");
+    }
+
+    let mentioned = match message.data.mentions() {
+        Some(mentions) => conversation_mentions(mentions, message.from, &members)?,
+        None => Vec::new(),
+    };
+
+    let stored: StoredMessage = message.clone().try_into()?;
+    state.messages.write(stored).await?;
+
+    if let Some(poll) = message.data.poll() {
+        db::poll::create(&state.pool, message.id, poll).await?;
+    }
+
+    state
+        .messages
+        .touch_dms(members, group_dm_id, message.id)
+        .await?;
+
+    state
+        .typing
+        .stop(message.from, TypingTarget::Direct(group_dm_id));
+
+    if !mentioned.is_empty() {
+        state
+            .messages
+            .index_mentions(message.id, ReadTarget::Direct(group_dm_id), mentioned)
+            .await?;
+    }
+
+    state
+        .messages
+        .mark_read(message.from, ReadTarget::Direct(group_dm_id), message.id)
+        .await?;
+
+    let from = message.from;
+    db::group_dm::send_to_members(state, group_dm_id, message, Some(from));
+
+    Ok(())
+}
+
+/// Mentioned users of a direct or group DM message, the author excluded.
+fn conversation_mentions(mentions: &Mentions, from: id, members: &[id]) -> Result<Vec<id>> {
+    if mentions.everyone || !mentions.roles.is_empty() {
+        anyhow::bail!("Only users can be mentioned in direct messages");
+    }
+
+    if mentions.users.iter().any(|u| !members.contains(u)) {
+        anyhow::bail!("Mentioned user is not in this conversation");
+    }
+
+    Ok(mentions
+        .users
+        .iter()
+        .copied()
+        .filter(|&u| u != from)
+        .collect())
+}
+
+/// Mentioned users of a channel message who can view the channel, the author excluded.
+fn channel_mentions(
+    group: &Group,
+    mentions: &Mentions,
+    from: id,
+    channel_id: id,
+) -> Result<Vec<id>> {
+    if mentions.everyone
+        && !group
+            .compute_permissions(from, Some(channel_id))
+            .contains(Permissions::MENTION_EVERYONE)
+    {
+        anyhow::bail!("You don't have permission to mention everyone");
+    }
+
+    if mentions
+        .users
+        .iter()
+        .any(|u| !group.members.contains_key(u))
+    {
+        anyhow::bail!("Mentioned user is not a member of this group");
+    }
+
+    if mentions.roles.iter().any(|r| !group.roles.contains_key(r)) {
+        anyhow::bail!("Mentioned role does not exist");
+    }
+
+    let mut mentioned = group.mentioned_users(mentions, channel_id);
+    mentioned.retain(|&u| u != from);
+
+    Ok(mentioned)
+}
+
+/// Checks the mentions of an edit like `dispatch_message` does and returns the mention
+/// index target with the users mentioned before and after the edit.
+pub async fn edit_mentions(
+    state: &State,
+    message: &StoredMessage,
+    data: &Data,
+) -> Result<(ReadTarget, Vec<id>, Vec<id>)> {
+    if let Some(group_id) = message.group_id {
+        let Some(group) = state.groups.get(&group_id) else {
+            anyhow::bail!("Group not found")
+        };
+
+        let mut before = message
+            .data
+            .mentions()
+            .map(|mentions| group.mentioned_users(mentions, message.to))
+            .unwrap_or_default();
+        before.retain(|&u| u != message.from);
+
+        let after = match data.mentions() {
+            Some(mentions) => channel_mentions(&group, mentions, message.from, message.to)?,
+            None => Vec::new(),
+        };
+
+        return Ok((ReadTarget::Channel(message.to), before, after));
+    }
+
+    let members = db::group_dm::participants(state, message.from, message.to).await?;
+
+    let before = message
+        .data
+        .mentions()
+        .map(|mentions| {
+            mentions
+                .users
+                .iter()
+                .copied()
+                .filter(|&u| u != message.from)
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let after = match data.mentions() {
+        Some(mentions) => conversation_mentions(mentions, message.from, &members)?,
+        None => Vec::new(),
+    };
+
+    let target = if message.to.is_group_dm() {
+        ReadTarget::Direct(message.to)
+    } else {
+        ReadTarget::Direct(message.from)
+    };
+
+    Ok((target, before, after))
+}
+
+async fn send_scheduled(state: &State, scheduled: ScheduledMessage) -> Result<()> {
+    if let Some(group_id) = scheduled.group_id
+        && !state.groups.contains_key(&group_id)
+    {
+        let _write_guard = state.group_locks.write(group_id).await;
+        if !state.groups.contains_key(&group_id) {
+            let group = db::group::init_group(&state.pool, group_id).await?;
+            state.groups.insert(group_id, group);
+        }
+    }
+
+    let mut message: Message<Data> = scheduled.into();
+    message.id = state.snowflake.generate();
+
+    if matches!(message.r#type, MessageType::Direct)
+        && let Some(mut user) = state.users.get_mut(&message.from)
+    {
+        user.state.dms.insert(message.to);
+
+        let user = user.downgrade();
+        let bytes = Bytes::from(msgpack!(message));
+
+        for connection in user.connections.iter() {
+            connection.writer.send(bytes.clone());
+        }
+    }
+
+    dispatch_message(state, message, 0).await
+}
+
+async fn send_due_scheduled(state: &State) -> Result<()> {
+    for scheduled in state.messages.take_due_scheduled(Utc::now()).await? {
+        let scheduled_id = scheduled.id;
+        let user_id = scheduled.from;
+
+        if let Err(e) = send_scheduled(state, scheduled).await
+            && let Some(user) = state.users.get(&user_id)
+        {
+            let error = Message {
+                id: scheduled_id,
+                data: error_ack(&e),
+                to: user_id,
+                ..Default::default()
+            };
+            user.send_message(error);
+        }
+    }
+
+    Ok(())
+}
+
+pub async fn scheduled_schedular(state: State) {
+    let mut interval = time::interval(SCHEDULED_INTERVAL);
+
+    loop {
+        tokio::select! {
+            _ = state.shutdown.cancelled() => break,
+            _ = interval.tick() => {}
+        }
+
+        if let Err(e) = send_due_scheduled(&state).await {
+            log::error!("Error while sending scheduled messages: {:?}", e);
+        }
+    }
+}
+
 pub fn send_message<T: Serialize>(state: &State, message: Message<T>) {
     if let Some(mut user) = state.users.get_mut(&message.to) {
         if matches!(message.r#type, MessageType::Direct) {
@@ -165,10 +559,17 @@ fn send_group_message<T: Serialize>(
     let from = message.from;
     let channel_id = message.to;
 
+    let subscribers = match message.thread {
+        Some(thread_id) => match group.threads.get(&thread_id) {
+            Some(thread) => &thread.subscribers,
+            None => return,
+        },
+        None => &group.subscribers,
+    };
+
     let bytes = Bytes::from(msgpack!(message));
 
-    group
-        .subscribers
+    subscribers
         .iter()
         .filter(|&&(user_id, conn_id)| {
             let perms = group.compute_permissions(user_id, Some(channel_id));
@@ -1,16 +1,23 @@
+use crate::db::audit_log::{self, AuditAction, AuditChange};
+use crate::db::message::ReadTarget;
+use crate::db::reaction::Emoji;
+use crate::db::retention::Retention;
 use crate::db::{self};
 use crate::id::id;
-use crate::message::data::Data;
+use crate::message::data::{Data, SystemEntry};
+use crate::message::dispatch;
 use crate::message::snowflake::snowflake_id;
 use crate::message::{Ack, MessageType};
 use crate::msgpack;
 use crate::state::group::WatchPartyOpt;
 use crate::state::group::{ChannelType, Group, OverrideTarget};
-use crate::state::group::{Permissions, WatchParty};
+use crate::state::group::{MAX_SLOW_MODE, Permissions, WatchParty};
+use crate::state::typing::{TypingTarget, notify_typing};
 use crate::state::user::{self, Voice, VoiceType};
 use crate::{State, message::Message};
 use anyhow::Result;
 use bytes::Bytes;
+use chrono::Utc;
 use dashmap::Entry;
 use serde::{Deserialize, Serialize};
 use std::collections::HashSet;
@@ -35,6 +42,21 @@ pub enum Event {
     FriendRequest,
     FriendAccept,
     FriendRemove,
+    Block,
+    Unblock,
+
+    /* ===== GROUP DM ===== */
+    CreateGroupDm {
+        name: Option<String>,
+        members: Vec<id>,
+    },
+    UpdateGroupDm {
+        name: Option<String>,
+        icon: Option<String>,
+    },
+    AddGroupDmMember(id),
+    RemoveGroupDmMember(id),
+    LeaveGroupDm,
 
     /* ===== GROUP ===== */
     CreateGroup {
@@ -46,6 +68,8 @@ pub enum Event {
         name: Option<String>,
         description: Option<String>,
         icon: Option<String>,
+        #[serde(default)]
+        retention: Option<Retention>,
     },
     DeleteGroup,
     Subscribe,
@@ -89,6 +113,10 @@ pub enum Event {
         name: Option<String>,
         title: Option<String>,
         position: Option<usize>,
+        #[serde(default)]
+        retention: Option<Retention>,
+        #[serde(default)]
+        slow_mode: Option<u32>,
     },
     DeleteChannel,
     SetPermissionOverride {
@@ -100,6 +128,24 @@ pub enum Event {
         target: OverrideTarget,
     },
 
+    /* ===== THREAD ===== */
+    CreateThread {
+        message: snowflake_id,
+        name: String,
+    },
+    SubscribeThread {
+        thread: snowflake_id,
+    },
+    UnsubscribeThread {
+        thread: snowflake_id,
+    },
+    ArchiveThread {
+        thread: snowflake_id,
+    },
+    UnarchiveThread {
+        thread: snowflake_id,
+    },
+
     /* ===== VOICE ===== */
     JoinVoice {
         #[serde(default)]
@@ -111,10 +157,25 @@ pub enum Event {
     MoveToVoice(id),
     Mute(bool),
     Deafen(bool),
+    /// Moderator actions, `to` is the member in a voice channel of the group.
+    MuteMember(bool),
+    DeafenMember(bool),
+    MoveMember(id),
+    DisconnectMember,
 
     /* ===== MODERATION ===== */
     KickUser,
+    /// Same as `KickUser`, with a reason kept in the audit log.
+    KickUserWithReason(String),
     BanUser,
+    /// Same as `BanUser`, with a reason kept in the audit log.
+    BanUserWithReason(String),
+    /// Duration in seconds, 0 lifts the timeout.
+    Timeout {
+        duration: u32,
+        #[serde(default)]
+        reason: Option<String>,
+    },
     LeaveGroup,
 
     // ==== webRTC ====
@@ -147,12 +208,33 @@ pub enum Event {
     /* ===== MESSAGE ===== */
     Reaction {
         message: snowflake_id,
-        reaction: char,
+        reaction: Emoji,
     },
     RemoveReaction {
         message: snowflake_id,
-        reaction: char,
+        reaction: Emoji,
+    },
+    StartTyping,
+    StopTyping,
+    Vote {
+        message: snowflake_id,
+        option: u16,
+    },
+    Unvote {
+        message: snowflake_id,
+        option: u16,
+    },
+    Pin {
+        message: snowflake_id,
+    },
+    Unpin {
+        message: snowflake_id,
+    },
+    MarkRead {
+        message: snowflake_id,
     },
+    SetRetention(Retention),
+    SetDisappearingTimer(Option<u32>),
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -180,6 +262,11 @@ fn broadcast_voice_ack(state: &State, user_id: id, voice_type: &VoiceType, ack:
                 group.notify(ack, state);
             }
         }
+        VoiceType::Direct {
+            user: group_dm_id, ..
+        } if group_dm_id.is_group_dm() => {
+            db::group_dm::send_to_members(state, group_dm_id, ack, None);
+        }
         VoiceType::Direct { user: other, .. } => {
             if let Some(me) = state.users.get(&user_id) {
                 me.send_message(ack.clone());
@@ -191,6 +278,361 @@ fn broadcast_voice_ack(state: &State, user_id: id, voice_type: &VoiceType, ack:
     }
 }
 
+/// Role overrides follow the role hierarchy, member overrides the member's rank.
+fn can_manage_override(group: &Group, actor: id, target: &OverrideTarget) -> bool {
+    match *target {
+        OverrideTarget::Role(role_id) => group.can_manage_role(actor, role_id),
+        OverrideTarget::User(user_id) => group.outranks(actor, user_id),
+    }
+}
+
+/// Voice channel `target` is connected to in the group, once `moderator` is
+/// checked for `permission` in it.
+fn moderated_voice_channel(
+    state: &State,
+    group_id: id,
+    moderator: id,
+    target: id,
+    permission: Permissions,
+) -> Result<id> {
+    let channel_id = state
+        .users
+        .get(&target)
+        .and_then(|user| match user.state.voice.as_ref()?.r#type {
+            VoiceType::Channel {
+                group_id: voice_group_id,
+                channel_id,
+            } if voice_group_id == group_id => Some(channel_id),
+            _ => None,
+        })
+        .ok_or_else(|| anyhow::anyhow!("Member is not in a voice channel of this group"))?;
+
+    let group = state
+        .groups
+        .get(&group_id)
+        .ok_or_else(|| anyhow::anyhow!("Group not found"))?;
+
+    if !group
+        .compute_permissions(moderator, Some(channel_id))
+        .contains(permission)
+    {
+        anyhow::bail!("Unauthorized to moderate this member");
+    }
+
+    if !group.outranks(moderator, target) {
+        anyhow::bail!("Cannot moderate a member with an equal or higher role");
+    }
+
+    Ok(channel_id)
+}
+
+/// Settings of a 1:1 conversation apply to both sides, so they need a DM or a
+/// friendship between the two and no block in either direction.
+async fn check_shared_conversation(state: &State, from: id, to: id) -> Result<()> {
+    let Some((blocked, has_dm)) = state
+        .users
+        .get(&from)
+        .map(|user| (user.state.is_blocked(to), user.state.dms.contains(&to)))
+    else {
+        anyhow::bail!("User not found");
+    };
+
+    if blocked {
+        anyhow::bail!("Cannot change the settings of this conversation");
+    }
+
+    if !has_dm && !db::user::are_friends(&state.pool, from, to).await? {
+        anyhow::bail!("Invalid conversation");
+    }
+
+    Ok(())
+}
+
+async fn join_group_dm_voice(
+    state: &State,
+    event_id: snowflake_id,
+    user_id: id,
+    group_dm_id: id,
+    connection_id: usize,
+    mute: bool,
+    deafen: bool,
+) -> Result<()> {
+    if !db::group_dm::participants(state, user_id, group_dm_id)
+        .await?
+        .contains(&user_id)
+    {
+        anyhow::bail!("You are not a member of this group DM");
+    }
+
+    if state
+        .users
+        .get(&user_id)
+        .is_some_and(|u| u.state.voice.is_some())
+    {
+        anyhow::bail!("Already in a voice channel");
+    }
+
+    let existing_id = state.voice_direct.get(&group_dm_id).and_then(|users| {
+        users.iter().find_map(
+            |other| match state.users.get(other)?.state.voice.as_ref()?.r#type {
+                VoiceType::Direct { user, message_id } if user == group_dm_id => Some(message_id),
+                _ => None,
+            },
+        )
+    });
+
+    let (call_message_id, is_new) = match existing_id {
+        Some(i) => (i, false),
+        None => (state.snowflake.generate(), true),
+    };
+
+    let call = Message {
+        id: call_message_id,
+        from: user_id,
+        to: group_dm_id,
+        data: Data::Call { end_time: None },
+        r#type: MessageType::Direct,
+        thread: None,
+        ttl: None,
+    };
+
+    if is_new {
+        state.messages.write(call.clone().try_into()?).await?;
+        state
+            .messages
+            .touch_dms(
+                db::group_dm::members(state, group_dm_id),
+                group_dm_id,
+                call_message_id,
+            )
+            .await?;
+    }
+
+    if let Some(mut user) = state.users.get_mut(&user_id) {
+        user.state.voice = Some(Voice {
+            connection_id,
+            r#type: VoiceType::Direct {
+                user: group_dm_id,
+                message_id: call_message_id,
+            },
+            mute,
+            deafen,
+            server_mute: false,
+            server_deafen: false,
+        });
+    }
+
+    state
+        .voice_direct
+        .entry(group_dm_id)
+        .or_default()
+        .insert(user_id);
+
+    let ack = Message {
+        id: event_id,
+        from: user_id,
+        to: group_dm_id,
+        data: Ack::JoinedVoice {
+            channel_id: group_dm_id,
+            mute,
+            deafen,
+        },
+        ..Message::default()
+    };
+
+    db::group_dm::send_to_members(state, group_dm_id, ack, None);
+
+    if is_new {
+        db::group_dm::send_to_members(state, group_dm_id, call, None);
+    }
+
+    Ok(())
+}
+
+/// Takes the user out of a group DM call and ends the call once it is empty.
+pub async fn exit_group_dm_voice(
+    state: &State,
+    event_id: snowflake_id,
+    user_id: id,
+    group_dm_id: id,
+    call_message_id: snowflake_id,
+) -> Result<()> {
+    state.voice_direct.remove_if_mut(&group_dm_id, |_, users| {
+        users.remove(&user_id);
+        users.is_empty()
+    });
+
+    let ack = Message {
+        id: event_id,
+        from: user_id,
+        to: group_dm_id,
+        data: Ack::ExitedVoice(group_dm_id),
+        ..Message::default()
+    };
+
+    if let Some(user) = state.users.get(&user_id) {
+        user.send_message(ack.clone());
+    }
+    db::group_dm::send_to_members(state, group_dm_id, ack, Some(user_id));
+
+    if state.voice_direct.contains_key(&group_dm_id) {
+        return Ok(());
+    }
+
+    let mut stored = state.messages.get(call_message_id).await?;
+    stored.data = Data::Call {
+        end_time: Some(
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_millis() as f64,
+        ),
+    };
+    state.messages.overwrite(stored.clone()).await?;
+
+    let overwrite_ack = Message {
+        id: event_id,
+        from: user_id,
+        to: group_dm_id,
+        data: Ack::Overwritten(Box::new(stored)),
+        ..Message::default()
+    };
+
+    db::group_dm::send_to_members(state, group_dm_id, overwrite_ack, None);
+
+    Ok(())
+}
+
+async fn handle_pin(
+    state: &State,
+    event_id: snowflake_id,
+    user_id: id,
+    target_message_id: snowflake_id,
+    pin: bool,
+) -> Result<()> {
+    let stored = state.messages.get(target_message_id).await?;
+
+    let participants = match stored.group_id {
+        Some(_) => Vec::new(),
+        None => db::group_dm::participants(state, stored.from, stored.to).await?,
+    };
+
+    if let Some(gid) = stored.group_id {
+        let group = state
+            .groups
+            .get(&gid)
+            .ok_or_else(|| anyhow::anyhow!("Group not found"))?;
+
+        if !group
+            .compute_permissions(user_id, Some(stored.to))
+            .contains(Permissions::VIEW_MESSAGES | Permissions::MANAGE_MESSAGES)
+        {
+            anyhow::bail!("You don't have permission to pin messages");
+        }
+    } else if !participants.contains(&user_id) {
+        anyhow::bail!("You don't have permission to pin this message");
+    }
+
+    let changed = if pin {
+        state.messages.pin(stored.clone(), user_id).await?
+    } else {
+        state.messages.unpin(stored.clone()).await?
+    };
+
+    if !changed {
+        return Ok(());
+    }
+
+    let ack = Message {
+        id: event_id,
+        from: user_id,
+        to: stored.to,
+        data: if pin {
+            Ack::Pinned(target_message_id)
+        } else {
+            Ack::Unpinned(target_message_id)
+        },
+        ..Message::default()
+    };
+
+    if let Some(gid) = stored.group_id {
+        if let Some(group) = state.groups.get(&gid) {
+            group.notify_with_permissions(ack, Permissions::VIEW_MESSAGES, Some(stored.to), state);
+        }
+    } else {
+        for participant in participants {
+            if let Some(u) = state.users.get(&participant) {
+                u.send_message(ack.clone());
+            }
+        }
+    }
+
+    Ok(())
+}
+
+async fn handle_mark_read(
+    state: &State,
+    event_id: snowflake_id,
+    user_id: id,
+    target_message_id: snowflake_id,
+) -> Result<()> {
+    let stored = state.messages.get(target_message_id).await?;
+
+    let target = if let Some(gid) = stored.group_id {
+        if stored.thread.is_some() {
+            anyhow::bail!("Read state is tracked per channel");
+        }
+
+        let group = state
+            .groups
+            .get(&gid)
+            .ok_or_else(|| anyhow::anyhow!("Group not found"))?;
+
+        if !group
+            .compute_permissions(user_id, Some(stored.to))
+            .contains(Permissions::VIEW_MESSAGES)
+        {
+            anyhow::bail!("You don't have permission to view this channel");
+        }
+
+        ReadTarget::Channel(stored.to)
+    } else if stored.to.is_group_dm() {
+        if !db::group_dm::participants(state, stored.from, stored.to)
+            .await?
+            .contains(&user_id)
+        {
+            anyhow::bail!("You are not part of this conversation");
+        }
+
+        ReadTarget::Direct(stored.to)
+    } else if user_id == stored.from {
+        ReadTarget::Direct(stored.to)
+    } else if user_id == stored.to {
+        ReadTarget::Direct(stored.from)
+    } else {
+        anyhow::bail!("You are not part of this conversation");
+    };
+
+    state
+        .messages
+        .mark_read(user_id, target, target_message_id)
+        .await?;
+
+    if let Some(user) = state.users.get(&user_id) {
+        user.send_message(Message {
+            id: event_id,
+            to: user_id,
+            data: Ack::MarkedRead {
+                target,
+                message: target_message_id,
+            },
+            ..Message::default()
+        });
+    }
+
+    Ok(())
+}
+
 pub async fn handle_event(
     message: Message<Event>,
     connection_id: usize,
@@ -369,6 +811,14 @@ pub async fn handle_event(
                         anyhow::bail!("Cannot send friend request to yourself");
                     }
 
+                    if state
+                        .users
+                        .get(&from)
+                        .is_some_and(|user| user.state.is_blocked(to))
+                    {
+                        anyhow::bail!("Cannot send friend request to this user");
+                    }
+
                     let _user2_lock = state.user_locks.write(to).await;
 
                     db::user::friend_request(&state.pool, from, to).await?;
@@ -482,6 +932,90 @@ pub async fn handle_event(
                     }
                 }
 
+                Event::Block => {
+                    let from = message.from;
+                    let to = message.to;
+
+                    if from == to {
+                        anyhow::bail!("Cannot block yourself");
+                    }
+
+                    if to.is_group_dm() {
+                        anyhow::bail!("Only users can be blocked");
+                    }
+
+                    let _user2_lock = state.user_locks.write(to).await;
+
+                    db::user::block(&state.pool, from, to).await?;
+
+                    if let Some(mut from_user) = state.users.get_mut(&from) {
+                        let ack = Message {
+                            id: message.id,
+                            from: to,
+                            to: from,
+                            data: Ack::BlockedUser,
+                            ..Message::default()
+                        };
+
+                        from_user.state.blocks.insert(to);
+                        from_user.state.friends.remove(&to);
+                        from_user.state.friend_requests.retain(|&i| i != to);
+                        from_user.state.friend_requests_sent.retain(|&i| i != to);
+                        from_user.send_message(ack);
+                    }
+
+                    if let Some(mut to_user) = state.users.get_mut(&to) {
+                        to_user.state.blocked_by.insert(from);
+
+                        let was_related = to_user.state.friends.remove(&from)
+                            || to_user.state.friend_requests.contains(&from)
+                            || to_user.state.friend_requests_sent.contains(&from);
+
+                        to_user.state.friend_requests.retain(|&i| i != from);
+                        to_user.state.friend_requests_sent.retain(|&i| i != from);
+
+                        if was_related {
+                            let ack = Message {
+                                id: message.id,
+                                from,
+                                to,
+                                data: Ack::DeletedFriend,
+                                ..Message::default()
+                            };
+
+                            to_user.send_message(ack);
+                        }
+                    }
+                }
+
+                Event::Unblock => {
+                    let from = message.from;
+                    let to = message.to;
+
+                    let _user2_lock = state.user_locks.write(to).await;
+
+                    if !db::user::unblock(&state.pool, from, to).await? {
+                        anyhow::bail!("User is not blocked");
+                    }
+
+                    if let Some(mut from_user) = state.users.get_mut(&from) {
+                        let ack = Message {
+                            id: message.id,
+                            from: to,
+                            to: from,
+                            data: Ack::UnblockedUser,
+                            ..Message::default()
+                        };
+
+                        from_user.state.blocks.remove(&to);
+                        from_user.send_message(ack);
+                    }
+
+                    if let Some(mut to_user) = state.users.get_mut(&to) {
+                        to_user.state.blocked_by.remove(&from);
+                    }
+                }
+
                 /* ===== GROUP ===== */
                 Event::CreateGroup {
                     name,
@@ -535,12 +1069,64 @@ pub async fn handle_event(
                     }
                 }
 
+                /* ===== GROUP DM ===== */
+                Event::CreateGroupDm { name, members } => {
+                    db::group_dm::handle_create(state, message.id, message.from, name, members)
+                        .await?;
+                }
+
+                Event::UpdateGroupDm { name, icon } => {
+                    db::group_dm::handle_update(
+                        state,
+                        message.id,
+                        message.from,
+                        message.to,
+                        name,
+                        icon,
+                    )
+                    .await?;
+                }
+
+                Event::AddGroupDmMember(user) => {
+                    db::group_dm::handle_add(state, message.id, message.from, message.to, user)
+                        .await?;
+                }
+
+                Event::RemoveGroupDmMember(user) => {
+                    db::group_dm::handle_remove(state, message.id, message.from, message.to, user)
+                        .await?;
+                }
+
+                Event::LeaveGroupDm => {
+                    db::group_dm::handle_remove(
+                        state,
+                        message.id,
+                        message.from,
+                        message.to,
+                        message.from,
+                    )
+                    .await?;
+                }
+
                 Event::Offer(_) | Event::Answer(_) | Event::IceCandidate { .. } => {
                     if let Some(user) = state.users.get(&message.to) {
                         user.send_message(message);
                     }
                 }
 
+                Event::JoinVoice { mute, deafen } if message.to.is_group_dm() => {
+                    join_group_dm_voice(
+                        state,
+                        message.id,
+                        message.from,
+                        message.to,
+                        connection_id,
+                        mute,
+                        deafen,
+                    )
+                    .await?;
+                }
+
                 Event::JoinVoice { mute, deafen } => {
                     if state
                         .users
@@ -550,6 +1136,14 @@ pub async fn handle_event(
                         anyhow::bail!("Already in a voice channel");
                     }
 
+                    if state
+                        .users
+                        .get(&message.from)
+                        .is_some_and(|user| user.state.is_blocked(message.to))
+                    {
+                        anyhow::bail!("Cannot call this user");
+                    }
+
                     let existing_id = state.users.get(&message.to).and_then(|other| {
                         match other.state.voice.as_ref()?.r#type {
                             VoiceType::Direct { user, message_id } if user == message.from => {
@@ -570,6 +1164,8 @@ pub async fn handle_event(
                         to: message.to,
                         data: Data::Call { end_time: None },
                         r#type: MessageType::Direct,
+                        thread: None,
+                        ttl: None,
                     };
 
                     if is_new {
@@ -596,6 +1192,8 @@ pub async fn handle_event(
                             },
                             mute,
                             deafen,
+                            server_mute: false,
+                            server_deafen: false,
                         });
                         user.send_message(ack.clone());
                         if is_new {
@@ -617,6 +1215,33 @@ pub async fn handle_event(
                     }
                 }
 
+                Event::ExitVoice if message.to.is_group_dm() => {
+                    let call_message_id = state
+                        .users
+                        .get(&message.from)
+                        .and_then(|user| match user.state.voice.as_ref()?.r#type {
+                            VoiceType::Direct {
+                                user: group_dm_id,
+                                message_id,
+                            } if group_dm_id == message.to => Some(message_id),
+                            _ => None,
+                        })
+                        .ok_or_else(|| anyhow::anyhow!("Not in a voice channel"))?;
+
+                    if let Some(mut user) = state.users.get_mut(&message.from) {
+                        user.state.voice = None;
+                    }
+
+                    exit_group_dm_voice(
+                        state,
+                        message.id,
+                        message.from,
+                        message.to,
+                        call_message_id,
+                    )
+                    .await?;
+                }
+
                 Event::ExitVoice => {
                     let call_message_id = state
                         .users
@@ -766,30 +1391,162 @@ pub async fn handle_event(
                     .await?;
                 }
 
-                _ => anyhow::bail!("Invalid event"),
-            }
-        }
+                Event::Vote {
+                    message: target,
+                    option,
+                } => {
+                    db::poll::handle_vote(state, message.id, message.from, target, option, true)
+                        .await?;
+                }
 
-        MessageType::InfoGroup(group_id) => {
-            match message.data {
-                /* ===== Group ===== */
-                Event::Subscribe => {
-                    if !state.groups.contains_key(&group_id) {
-                        let _write_guard = state.group_locks.write(group_id).await;
-                        if !state.groups.contains_key(&group_id) {
-                            let group: Group =
-                                db::group::init_group(&state.pool, group_id).await?.into();
-                            state.groups.insert(group_id, group);
-                        }
-                    }
+                Event::Unvote {
+                    message: target,
+                    option,
+                } => {
+                    db::poll::handle_vote(state, message.id, message.from, target, option, false)
+                        .await?;
+                }
 
-                    if let Some(mut group) = state.groups.get_mut(&group_id) {
-                        if let Some(user) = state.users.get(&message.from) {
-                            if !user.state.groups.contains(&group_id) {
-                                anyhow::bail!("User is not a member of this group");
-                            }
+                Event::Pin { message: target } => {
+                    handle_pin(state, message.id, message.from, target, true).await?;
+                }
 
-                            group.subscribe(message.from, connection_id);
+                Event::Unpin { message: target } => {
+                    handle_pin(state, message.id, message.from, target, false).await?;
+                }
+
+                Event::MarkRead { message: target } => {
+                    handle_mark_read(state, message.id, message.from, target).await?;
+                }
+
+                Event::StartTyping => {
+                    if message.to == message.from {
+                        anyhow::bail!("Invalid conversation");
+                    }
+
+                    if message.to.is_group_dm()
+                        && !db::group_dm::participants(state, message.from, message.to)
+                            .await?
+                            .contains(&message.from)
+                    {
+                        anyhow::bail!("You are not a member of this group DM");
+                    }
+
+                    if state
+                        .users
+                        .get(&message.from)
+                        .is_some_and(|user| user.state.is_blocked(message.to))
+                    {
+                        anyhow::bail!("Cannot send messages to this user");
+                    }
+
+                    let target = TypingTarget::Direct(message.to);
+
+                    if state.typing.start(message.from, target) {
+                        notify_typing(state, message.from, target, true);
+                    }
+                }
+
+                Event::StopTyping => {
+                    let target = TypingTarget::Direct(message.to);
+
+                    if state.typing.stop(message.from, target) {
+                        notify_typing(state, message.from, target, false);
+                    }
+                }
+
+                Event::SetRetention(retention) => {
+                    retention.validate()?;
+
+                    if message.to == message.from || message.to.is_group_dm() {
+                        anyhow::bail!("Invalid conversation");
+                    }
+
+                    check_shared_conversation(state, message.from, message.to).await?;
+
+                    db::retention::set_dm_retention(
+                        &state.pool,
+                        message.from,
+                        message.to,
+                        retention,
+                    )
+                    .await?;
+
+                    let ack = Message {
+                        id: message.id,
+                        from: message.from,
+                        to: message.to,
+                        data: Ack::UpdatedRetention(retention),
+                        ..Message::default()
+                    };
+
+                    if let Some(user) = state.users.get(&message.from) {
+                        user.send_message(ack.clone());
+                    }
+                    if let Some(user) = state.users.get(&message.to) {
+                        user.send_message(ack);
+                    }
+                }
+
+                Event::SetDisappearingTimer(ttl) => {
+                    if let Some(ttl) = ttl {
+                        db::disappearing::validate_ttl(ttl)?;
+                    }
+
+                    if message.to == message.from || message.to.is_group_dm() {
+                        anyhow::bail!("Invalid conversation");
+                    }
+
+                    check_shared_conversation(state, message.from, message.to).await?;
+
+                    db::disappearing::set_timer(&state.pool, message.from, message.to, ttl).await?;
+
+                    let entry = Message {
+                        id: state.snowflake.generate(),
+                        from: message.from,
+                        to: message.to,
+                        data: Data::System(SystemEntry::DisappearingTimer { ttl }),
+                        r#type: MessageType::Direct,
+                        ..Message::default()
+                    };
+
+                    state.messages.write(entry.clone().try_into()?).await?;
+
+                    if let Some(mut user) = state.users.get_mut(&message.from) {
+                        user.state.dms.insert(message.to);
+                        user.downgrade().send_message(entry.clone());
+                    }
+
+                    dispatch::send_message(state, entry);
+                }
+
+                _ => anyhow::bail!("Invalid event"),
+            }
+        }
+
+        MessageType::InfoGroup(group_id) => {
+            match message.data {
+                /* ===== Group ===== */
+                Event::Subscribe => {
+                    if !state.groups.contains_key(&group_id) {
+                        let _write_guard = state.group_locks.write(group_id).await;
+                        if !state.groups.contains_key(&group_id) {
+                            let group: Group =
+                                db::group::init_group(&state.pool, group_id).await?.into();
+                            state.groups.insert(group_id, group);
+                        }
+                    }
+
+                    let mut ack = None;
+                    let mut text_channels = Vec::new();
+
+                    if let Some(mut group) = state.groups.get_mut(&group_id) {
+                        if let Some(user) = state.users.get(&message.from) {
+                            if !user.state.groups.contains(&group_id) {
+                                anyhow::bail!("User is not a member of this group");
+                            }
+
+                            group.subscribe(message.from, connection_id);
 
                             let group = group.downgrade();
 
@@ -819,6 +1576,8 @@ pub async fn handle_event(
                                                         crate::message::ack::VoiceStateSnapshot {
                                                             mute: v.mute,
                                                             deafen: v.deafen,
+                                                            server_mute: v.server_mute,
+                                                            server_deafen: v.server_deafen,
                                                         },
                                                     );
                                                 }
@@ -828,7 +1587,26 @@ pub async fn handle_event(
                                 }
                             }
 
-                            let ack = Message {
+                            let mut threads = group.active_threads();
+                            threads.retain(|cid, _| {
+                                group
+                                    .compute_permissions(message.from, Some(*cid))
+                                    .contains(Permissions::VIEW_MESSAGES)
+                            });
+
+                            text_channels = group
+                                .channels
+                                .iter()
+                                .filter(|(cid, channel)| {
+                                    matches!(channel.r#type, ChannelType::Text)
+                                        && group
+                                            .compute_permissions(message.from, Some(**cid))
+                                            .contains(Permissions::VIEW_MESSAGES)
+                                })
+                                .map(|(cid, _)| ReadTarget::Channel(*cid))
+                                .collect();
+
+                            ack = Some(Message {
                                 id: message.id,
                                 from: group_id,
                                 to: message.from,
@@ -837,15 +1615,39 @@ pub async fn handle_event(
                                     permissions,
                                     channel_permissions,
                                     voice_states,
+                                    threads,
+                                    read_states: std::collections::HashMap::new(),
                                 },
                                 ..Message::default()
-                            };
-
-                            user.send_message(ack);
+                            });
                         }
                     } else {
                         anyhow::bail!("Group not found");
                     }
+
+                    let Some(mut ack) = ack else {
+                        return Ok(());
+                    };
+
+                    // Counted after the group guard is released, the index scans block.
+                    let counts = state
+                        .messages
+                        .read_states(message.from, text_channels)
+                        .await?;
+
+                    if let Ack::Subscribed { read_states, .. } = &mut ack.data {
+                        *read_states = counts
+                            .into_iter()
+                            .filter_map(|(target, read_state)| match target {
+                                ReadTarget::Channel(cid) => Some((cid, read_state)),
+                                ReadTarget::Direct(_) => None,
+                            })
+                            .collect();
+                    }
+
+                    if let Some(user) = state.users.get(&message.from) {
+                        user.send_message(ack);
+                    }
                 }
 
                 Event::Unsubscribe => {
@@ -895,6 +1697,7 @@ pub async fn handle_event(
                     name,
                     description,
                     icon,
+                    retention,
                 } => {
                     if let Some(group) = state.groups.get(&group_id) {
                         let perms = group.compute_permissions(message.from, None);
@@ -905,8 +1708,51 @@ pub async fn handle_event(
                         anyhow::bail!("Group not found");
                     }
 
+                    if let Some(retention) = &retention {
+                        retention.validate()?;
+                    }
+
                     let _lock = state.group_locks.write(group_id).await;
 
+                    let description_before = match &description {
+                        Some(_) => db::group::get_groups_info(&state.pool, vec![group_id])
+                            .await?
+                            .pop()
+                            .and_then(|group| group.description),
+                        None => None,
+                    };
+
+                    let changes = state
+                        .groups
+                        .get(&group_id)
+                        .map(|group| {
+                            [
+                                name.as_ref()
+                                    .and_then(|name| audit_log::change("name", &group.name, name)),
+                                icon.as_ref()
+                                    .and_then(|icon| audit_log::change("icon", &group.icon, icon)),
+                                description.as_ref().and_then(|description| {
+                                    audit_log::change(
+                                        "description",
+                                        &description_before,
+                                        description,
+                                    )
+                                }),
+                                retention.and_then(|retention| {
+                                    audit_log::change("retention", group.retention, retention)
+                                }),
+                            ]
+                            .into_iter()
+                            .flatten()
+                            .collect()
+                        })
+                        .unwrap_or_default();
+
+                    if let Some(retention) = retention {
+                        db::retention::set_group_retention(&state.pool, group_id, retention)
+                            .await?;
+                    }
+
                     db::group::update_group(
                         &state.pool,
                         group_id,
@@ -916,9 +1762,24 @@ pub async fn handle_event(
                     )
                     .await?;
 
+                    audit_log::record(
+                        &state.pool,
+                        group_id,
+                        message.from,
+                        AuditAction::UpdateGroup,
+                        None,
+                        changes,
+                        None,
+                    )
+                    .await;
+
                     if let Some(mut group) = state.groups.get_mut(&group_id) {
                         group.update_group(name.clone(), icon.clone());
 
+                        if let Some(retention) = retention {
+                            group.retention = retention;
+                        }
+
                         let ack = Message {
                             id: message.id,
                             from: group_id,
@@ -927,6 +1788,7 @@ pub async fn handle_event(
                                 name,
                                 description,
                                 icon,
+                                retention,
                             },
                             ..Message::default()
                         };
@@ -1012,6 +1874,26 @@ pub async fn handle_event(
                     )
                     .await?;
 
+                    let changes = [
+                        audit_log::change("name", (), &name),
+                        audit_log::change("title", (), &title),
+                        audit_log::change("is_voice", (), is_voice),
+                    ]
+                    .into_iter()
+                    .flatten()
+                    .collect();
+
+                    audit_log::record(
+                        &state.pool,
+                        group_id,
+                        message.from,
+                        AuditAction::CreateChannel,
+                        Some(channel_id),
+                        changes,
+                        None,
+                    )
+                    .await;
+
                     if let Some(mut group) = state.groups.get_mut(&group_id) {
                         let position =
                             group.create_channel(channel_id, name.clone(), is_voice, title.clone());
@@ -1039,6 +1921,8 @@ pub async fn handle_event(
                     name,
                     title,
                     position,
+                    retention,
+                    slow_mode,
                 } => {
                     let channel_id = message.to;
 
@@ -1051,8 +1935,47 @@ pub async fn handle_event(
                         anyhow::bail!("Group not found");
                     }
 
+                    if let Some(retention) = &retention {
+                        retention.validate()?;
+                    }
+
+                    if slow_mode.is_some_and(|s| s > MAX_SLOW_MODE) {
+                        anyhow::bail!("Slow mode can be at most {} seconds", MAX_SLOW_MODE);
+                    }
+
                     let _lock = state.group_locks.write(group_id).await;
 
+                    let changes = state
+                        .groups
+                        .get(&group_id)
+                        .and_then(|group| {
+                            let channel = group.channels.get(&channel_id)?;
+
+                            let changes = [
+                                name.as_ref().and_then(|name| {
+                                    audit_log::change("name", channel.name(), name)
+                                }),
+                                audit_log::change("title", channel.title(), &title),
+                                position.and_then(|position| {
+                                    audit_log::change("position", channel.position(), position)
+                                }),
+                                retention.and_then(|retention| {
+                                    audit_log::change("retention", channel.retention, retention)
+                                }),
+                                slow_mode.and_then(|slow_mode| {
+                                    audit_log::change("slow_mode", channel.slow_mode, slow_mode)
+                                }),
+                            ];
+
+                            Some(changes.into_iter().flatten().collect())
+                        })
+                        .unwrap_or_default();
+
+                    if let Some(retention) = retention {
+                        db::retention::set_channel_retention(&state.pool, channel_id, retention)
+                            .await?;
+                    }
+
                     db::group::update_channel(
                         &state.pool,
                         group_id,
@@ -1060,12 +1983,33 @@ pub async fn handle_event(
                         name.clone(),
                         title.clone(),
                         position,
+                        slow_mode,
                     )
                     .await?;
 
+                    audit_log::record(
+                        &state.pool,
+                        group_id,
+                        message.from,
+                        AuditAction::UpdateChannel,
+                        Some(channel_id),
+                        changes,
+                        None,
+                    )
+                    .await;
+
                     if let Some(mut group) = state.groups.get_mut(&group_id) {
                         group.update_channel(channel_id, name.clone(), title.clone(), position);
 
+                        if let Some(channel) = group.channels.get_mut(&channel_id) {
+                            if let Some(retention) = retention {
+                                channel.retention = retention;
+                            }
+                            if let Some(slow_mode) = slow_mode {
+                                channel.set_slow_mode(slow_mode);
+                            }
+                        }
+
                         let ack = Message {
                             id: message.id,
                             from: group_id,
@@ -1074,6 +2018,8 @@ pub async fn handle_event(
                                 name,
                                 title,
                                 position,
+                                retention,
+                                slow_mode,
                             },
                             ..Message::default()
                         };
@@ -1101,9 +2047,35 @@ pub async fn handle_event(
 
                     let _lock = state.group_locks.write(group_id).await;
 
+                    let changes = state
+                        .groups
+                        .get(&group_id)
+                        .and_then(|group| {
+                            let channel = group.channels.get(&channel_id)?;
+
+                            let changes = [
+                                audit_log::change("name", channel.name(), ()),
+                                audit_log::change("title", channel.title(), ()),
+                            ];
+
+                            Some(changes.into_iter().flatten().collect())
+                        })
+                        .unwrap_or_default();
+
                     db::group::delete_channel(&state.pool, group_id, channel_id).await?;
                     state.messages.delete_channel_messages(channel_id).await?;
 
+                    audit_log::record(
+                        &state.pool,
+                        group_id,
+                        message.from,
+                        AuditAction::DeleteChannel,
+                        Some(channel_id),
+                        changes,
+                        None,
+                    )
+                    .await;
+
                     if let Some(mut group) = state.groups.get_mut(&group_id) {
                         group.delete_channel(channel_id);
 
@@ -1132,8 +2104,23 @@ pub async fn handle_event(
                         if !perms.contains(Permissions::MANAGE_ROLES) {
                             anyhow::bail!("Unauthorized to set permission override");
                         }
-                        if !group.channels.contains_key(&channel_id) {
+                        if !can_manage_override(&group, message.from, &target) {
+                            anyhow::bail!("Cannot manage overrides of a role or member above you");
+                        }
+                        let Some(channel) = group.channels.get(&channel_id) else {
                             anyhow::bail!("Channel not found");
+                        };
+                        let (old_allow, old_deny) = channel
+                            .permission_overrides
+                            .iter()
+                            .find(|o| o.target == target)
+                            .map_or((Permissions::empty(), Permissions::empty()), |o| {
+                                (o.allow, o.deny)
+                            });
+                        let changed = (Permissions::from_bits_truncate(allow) ^ old_allow)
+                            | (Permissions::from_bits_truncate(deny) ^ old_deny);
+                        if !perms.contains(changed) {
+                            anyhow::bail!("Cannot grant permissions you don't have");
                         }
                     } else {
                         anyhow::bail!("Group not found");
@@ -1141,6 +2128,16 @@ pub async fn handle_event(
 
                     let _lock = state.group_locks.write(group_id).await;
 
+                    let existing = state.groups.get(&group_id).and_then(|group| {
+                        group
+                            .channels
+                            .get(&channel_id)?
+                            .permission_overrides
+                            .iter()
+                            .find(|o| o.target == target)
+                            .map(|o| (o.allow.bits(), o.deny.bits()))
+                    });
+
                     db::group::set_permission_override(
                         &state.pool,
                         group_id,
@@ -1151,6 +2148,30 @@ pub async fn handle_event(
                     )
                     .await?;
 
+                    let changes = [
+                        Some(AuditChange::new(
+                            "target",
+                            existing.map(|_| &target),
+                            &target,
+                        )),
+                        audit_log::change("allow", existing.map(|(allow, _)| allow), allow),
+                        audit_log::change("deny", existing.map(|(_, deny)| deny), deny),
+                    ]
+                    .into_iter()
+                    .flatten()
+                    .collect();
+
+                    audit_log::record(
+                        &state.pool,
+                        group_id,
+                        message.from,
+                        AuditAction::SetPermissionOverride,
+                        Some(channel_id),
+                        changes,
+                        None,
+                    )
+                    .await;
+
                     if let Some(mut group) = state.groups.get_mut(&group_id) {
                         group.set_permission_override(
                             channel_id,
@@ -1192,8 +2213,19 @@ pub async fn handle_event(
                         if !perms.contains(Permissions::MANAGE_ROLES) {
                             anyhow::bail!("Unauthorized to delete permission override");
                         }
-                        if !group.channels.contains_key(&channel_id) {
+                        if !can_manage_override(&group, message.from, &target) {
+                            anyhow::bail!("Cannot manage overrides of a role or member above you");
+                        }
+                        let Some(channel) = group.channels.get(&channel_id) else {
                             anyhow::bail!("Channel not found");
+                        };
+                        let changed = channel
+                            .permission_overrides
+                            .iter()
+                            .find(|o| o.target == target)
+                            .map_or(Permissions::empty(), |o| o.allow | o.deny);
+                        if !perms.contains(changed) {
+                            anyhow::bail!("Cannot grant permissions you don't have");
                         }
                     } else {
                         anyhow::bail!("Group not found");
@@ -1201,8 +2233,38 @@ pub async fn handle_event(
 
                     let _lock = state.group_locks.write(group_id).await;
 
+                    let existing = state.groups.get(&group_id).and_then(|group| {
+                        group
+                            .channels
+                            .get(&channel_id)?
+                            .permission_overrides
+                            .iter()
+                            .find(|o| o.target == target)
+                            .map(|o| (o.allow.bits(), o.deny.bits()))
+                    });
+
                     db::group::delete_permission_override(&state.pool, channel_id, &target).await?;
 
+                    let changes = [
+                        Some(AuditChange::new("target", &target, ())),
+                        existing.and_then(|(allow, _)| audit_log::change("allow", allow, ())),
+                        existing.and_then(|(_, deny)| audit_log::change("deny", deny, ())),
+                    ]
+                    .into_iter()
+                    .flatten()
+                    .collect();
+
+                    audit_log::record(
+                        &state.pool,
+                        group_id,
+                        message.from,
+                        AuditAction::DeletePermissionOverride,
+                        Some(channel_id),
+                        changes,
+                        None,
+                    )
+                    .await;
+
                     if let Some(mut group) = state.groups.get_mut(&group_id) {
                         group.remove_permission_override(channel_id, &target);
 
@@ -1229,6 +2291,198 @@ pub async fn handle_event(
                     }
                 }
 
+                /* ===== THREAD ===== */
+                Event::CreateThread {
+                    message: parent_id,
+                    name,
+                } => {
+                    let channel_id = message.to;
+                    let name = name.trim().to_string();
+
+                    if name.is_empty() || name.chars().count() > 100 {
+                        anyhow::bail!("Thread name must be between 1 and 100 characters");
+                    }
+
+                    if let Some(group) = state.groups.get(&group_id) {
+                        let perms = group.compute_permissions(message.from, Some(channel_id));
+                        if !perms.contains(Permissions::VIEW_MESSAGES | Permissions::SEND_MESSAGE) {
+                            anyhow::bail!("Unauthorized to create thread");
+                        }
+                        if !matches!(
+                            group.channels.get(&channel_id).map(|c| &c.r#type),
+                            Some(ChannelType::Text)
+                        ) {
+                            anyhow::bail!("Text channel not found");
+                        }
+                        if group.threads.contains_key(&parent_id) {
+                            anyhow::bail!("Thread already exists");
+                        }
+                    } else {
+                        anyhow::bail!("Group not found");
+                    }
+
+                    let parent = state.messages.get(parent_id).await?;
+
+                    if parent.group_id != Some(group_id)
+                        || parent.to != channel_id
+                        || parent.thread.is_some()
+                    {
+                        anyhow::bail!("Message is not in this channel");
+                    }
+
+                    let _lock = state.group_locks.write(group_id).await;
+
+                    db::group::create_thread(
+                        &state.pool,
+                        parent_id,
+                        group_id,
+                        channel_id,
+                        &name,
+                        message.from,
+                    )
+                    .await?;
+
+                    if let Some(mut group) = state.groups.get_mut(&group_id) {
+                        let thread = group.create_thread(parent_id, channel_id, name, message.from);
+                        thread.subscribers.insert((message.from, connection_id));
+
+                        let ack = Message {
+                            id: message.id,
+                            from: group_id,
+                            to: channel_id,
+                            data: Ack::CreatedThread(Box::new(thread.clone())),
+                            ..Message::default()
+                        };
+
+                        let group = group.downgrade();
+                        group.notify_with_permissions(
+                            ack,
+                            Permissions::VIEW_MESSAGES,
+                            Some(channel_id),
+                            state,
+                        );
+                    }
+                }
+
+                Event::SubscribeThread { thread: thread_id } => {
+                    if let Some(mut group) = state.groups.get_mut(&group_id) {
+                        let Some(channel_id) = group.threads.get(&thread_id).map(|t| t.channel_id)
+                        else {
+                            anyhow::bail!("Thread not found");
+                        };
+
+                        if !group
+                            .compute_permissions(message.from, Some(channel_id))
+                            .contains(Permissions::VIEW_MESSAGES)
+                        {
+                            anyhow::bail!("Unauthorized to view thread");
+                        }
+
+                        if let Some(thread) = group.threads.get_mut(&thread_id) {
+                            thread.subscribers.insert((message.from, connection_id));
+                        }
+
+                        drop(group);
+
+                        if let Some(user) = state.users.get(&message.from) {
+                            let ack = Message {
+                                id: message.id,
+                                from: group_id,
+                                to: channel_id,
+                                data: Ack::SubscribedThread(thread_id),
+                                ..Message::default()
+                            };
+
+                            user.send_message_connection(connection_id, ack);
+                        }
+                    } else {
+                        anyhow::bail!("Group not found");
+                    }
+                }
+
+                Event::UnsubscribeThread { thread: thread_id } => {
+                    if let Some(mut group) = state.groups.get_mut(&group_id) {
+                        let Some(thread) = group.threads.get_mut(&thread_id) else {
+                            anyhow::bail!("Thread not found");
+                        };
+
+                        thread.subscribers.remove(&(message.from, connection_id));
+                        let channel_id = thread.channel_id;
+
+                        drop(group);
+
+                        if let Some(user) = state.users.get(&message.from) {
+                            let ack = Message {
+                                id: message.id,
+                                from: group_id,
+                                to: channel_id,
+                                data: Ack::UnsubscribedThread(thread_id),
+                                ..Message::default()
+                            };
+
+                            user.send_message_connection(connection_id, ack);
+                        }
+                    } else {
+                        anyhow::bail!("Group not found");
+                    }
+                }
+
+                Event::ArchiveThread { thread: thread_id }
+                | Event::UnarchiveThread { thread: thread_id } => {
+                    let archived = matches!(message.data, Event::ArchiveThread { .. });
+
+                    let channel_id = if let Some(group) = state.groups.get(&group_id) {
+                        let Some(thread) = group.threads.get(&thread_id) else {
+                            anyhow::bail!("Thread not found");
+                        };
+
+                        let perms =
+                            group.compute_permissions(message.from, Some(thread.channel_id));
+                        let is_owner = thread.owner == message.from
+                            && perms.contains(Permissions::VIEW_MESSAGES);
+
+                        if !(is_owner || perms.contains(Permissions::MANAGE_MESSAGES)) {
+                            anyhow::bail!("Unauthorized to archive thread");
+                        }
+
+                        if thread.archived == archived {
+                            return Ok(());
+                        }
+
+                        thread.channel_id
+                    } else {
+                        anyhow::bail!("Group not found");
+                    };
+
+                    let _lock = state.group_locks.write(group_id).await;
+
+                    db::group::set_thread_archived(&state.pool, thread_id, archived).await?;
+
+                    if let Some(mut group) = state.groups.get_mut(&group_id) {
+                        group.set_thread_archived(thread_id, archived);
+
+                        let ack = Message {
+                            id: message.id,
+                            from: group_id,
+                            to: channel_id,
+                            data: if archived {
+                                Ack::ArchivedThread(thread_id)
+                            } else {
+                                Ack::UnarchivedThread(thread_id)
+                            },
+                            ..Message::default()
+                        };
+
+                        let group = group.downgrade();
+                        group.notify_with_permissions(
+                            ack,
+                            Permissions::VIEW_MESSAGES,
+                            Some(channel_id),
+                            state,
+                        );
+                    }
+                }
+
                 // ==== VOICE ====
                 Event::JoinVoice { mute, deafen } => {
                     let channel_id = message.to;
@@ -1243,6 +2497,13 @@ pub async fn handle_event(
                     }
 
                     if let Some(mut group) = state.groups.get_mut(&group_id) {
+                        let (server_mute, server_deafen) = group
+                            .members
+                            .get(&message.from)
+                            .map_or((false, false), |member| {
+                                (member.server_mute(), member.server_deafen())
+                            });
+
                         let Some(channel) = group.channels.get_mut(&channel_id) else {
                             anyhow::bail!("Channel not found");
                         };
@@ -1273,6 +2534,8 @@ pub async fn handle_event(
                                 },
                                 mute,
                                 deafen,
+                                server_mute,
+                                server_deafen,
                             });
                         }
 
@@ -1288,82 +2551,364 @@ pub async fn handle_event(
                             ..Message::default()
                         };
 
-                        group.notify(ack, &state);
-                    } else {
-                        anyhow::bail!("Group not found");
+                        group.notify(ack, &state);
+
+                        let server_acks = [
+                            server_mute.then_some(Ack::ServerMuted(true)),
+                            server_deafen.then_some(Ack::ServerDeafened(true)),
+                        ];
+
+                        for data in server_acks.into_iter().flatten() {
+                            let ack = Message {
+                                id: message.id,
+                                from: group_id,
+                                to: message.from,
+                                data,
+                                ..Message::default()
+                            };
+
+                            group.notify(ack, state);
+                        }
+                    } else {
+                        anyhow::bail!("Group not found");
+                    }
+                }
+
+                Event::ExitVoice => {
+                    let channel_id = message.to;
+
+                    if let Some(mut group) = state.groups.get_mut(&group_id) {
+                        let Some(channel) = group.channels.get_mut(&channel_id) else {
+                            anyhow::bail!("Channel not found");
+                        };
+
+                        match channel.r#type {
+                            ChannelType::Voice { ref mut users, .. } => {
+                                users.remove(&message.from);
+                            }
+                            ChannelType::Text => {
+                                anyhow::bail!("Cannot join a text channel");
+                            }
+                        }
+
+                        let group = group.downgrade();
+
+                        if let Some(mut user) = state.users.get_mut(&message.from) {
+                            user.state.voice = None;
+                        }
+
+                        let ack = Message {
+                            id: message.id,
+                            from: group_id,
+                            to: message.from,
+                            data: Ack::ExitedVoice(channel_id),
+                            ..Message::default()
+                        };
+
+                        group.notify(ack, &state);
+                    } else {
+                        anyhow::bail!("Group not found");
+                    }
+                }
+
+                Event::MoveToVoice(channel_id) => {
+                    if let Some(mut group) = state.groups.get_mut(&group_id) {
+                        let Some(channel) = group.channels.get_mut(&channel_id) else {
+                            anyhow::bail!("Channel not found");
+                        };
+
+                        match channel.r#type {
+                            ChannelType::Voice { ref mut users, .. } => {
+                                users.insert(message.from);
+                            }
+                            ChannelType::Text => {
+                                anyhow::bail!("Cannot join a text channel");
+                            }
+                        }
+
+                        let ack = Message {
+                            id: message.id,
+                            from: group_id,
+                            to: message.from,
+                            data: Ack::MovedToVoice(channel_id),
+                            ..Message::default()
+                        };
+
+                        let group = group.downgrade();
+
+                        group.notify(ack, &state);
+                    } else {
+                        anyhow::bail!("Group not found");
+                    }
+                }
+
+                Event::MuteMember(mute) => {
+                    let target = message.to;
+
+                    moderated_voice_channel(
+                        state,
+                        group_id,
+                        message.from,
+                        target,
+                        Permissions::MUTE_MEMBERS,
+                    )?;
+
+                    let _lock = state.group_locks.write(group_id).await;
+
+                    let before = state.groups.get(&group_id).is_some_and(|group| {
+                        group
+                            .members
+                            .get(&target)
+                            .is_some_and(|member| member.server_mute())
+                    });
+
+                    db::group::set_server_mute(&state.pool, group_id, target, mute).await?;
+
+                    if let Some(mut group) = state.groups.get_mut(&group_id) {
+                        group.set_server_mute(target, mute);
+                    }
+
+                    // The member may have left voice meanwhile, the flag then applies on
+                    // their next join.
+                    if let Some(mut user) = state.users.get_mut(&target)
+                        && let Some(voice) = user.state.voice.as_mut()
+                        && matches!(
+                            voice.r#type,
+                            VoiceType::Channel { group_id: voice_group_id, .. }
+                                if voice_group_id == group_id
+                        )
+                    {
+                        voice.server_mute = mute;
+                    }
+
+                    audit_log::record(
+                        &state.pool,
+                        group_id,
+                        message.from,
+                        AuditAction::MuteMember,
+                        Some(target),
+                        audit_log::change("server_mute", before, mute)
+                            .into_iter()
+                            .collect(),
+                        None,
+                    )
+                    .await;
+
+                    let ack = Message {
+                        id: message.id,
+                        from: message.from,
+                        to: target,
+                        data: Ack::ServerMuted(mute),
+                        ..Message::default()
+                    };
+
+                    if let Some(group) = state.groups.get(&group_id) {
+                        group.notify(ack, state);
+                    }
+                }
+
+                Event::DeafenMember(deafen) => {
+                    let target = message.to;
+
+                    moderated_voice_channel(
+                        state,
+                        group_id,
+                        message.from,
+                        target,
+                        Permissions::DEAFEN_MEMBERS,
+                    )?;
+
+                    let _lock = state.group_locks.write(group_id).await;
+
+                    let before = state.groups.get(&group_id).is_some_and(|group| {
+                        group
+                            .members
+                            .get(&target)
+                            .is_some_and(|member| member.server_deafen())
+                    });
+
+                    db::group::set_server_deafen(&state.pool, group_id, target, deafen).await?;
+
+                    if let Some(mut group) = state.groups.get_mut(&group_id) {
+                        group.set_server_deafen(target, deafen);
+                    }
+
+                    // The member may have left voice meanwhile, the flag then applies on
+                    // their next join.
+                    if let Some(mut user) = state.users.get_mut(&target)
+                        && let Some(voice) = user.state.voice.as_mut()
+                        && matches!(
+                            voice.r#type,
+                            VoiceType::Channel { group_id: voice_group_id, .. }
+                                if voice_group_id == group_id
+                        )
+                    {
+                        voice.server_deafen = deafen;
+                    }
+
+                    audit_log::record(
+                        &state.pool,
+                        group_id,
+                        message.from,
+                        AuditAction::DeafenMember,
+                        Some(target),
+                        audit_log::change("server_deafen", before, deafen)
+                            .into_iter()
+                            .collect(),
+                        None,
+                    )
+                    .await;
+
+                    let ack = Message {
+                        id: message.id,
+                        from: message.from,
+                        to: target,
+                        data: Ack::ServerDeafened(deafen),
+                        ..Message::default()
+                    };
+
+                    if let Some(group) = state.groups.get(&group_id) {
+                        group.notify(ack, state);
                     }
                 }
 
-                Event::ExitVoice => {
-                    let channel_id = message.to;
+                Event::MoveMember(channel_id) => {
+                    let target = message.to;
+
+                    let from_channel = moderated_voice_channel(
+                        state,
+                        group_id,
+                        message.from,
+                        target,
+                        Permissions::MOVE_MEMBERS,
+                    )?;
+
+                    if from_channel == channel_id {
+                        anyhow::bail!("Member is already in this channel");
+                    }
 
                     if let Some(mut group) = state.groups.get_mut(&group_id) {
+                        if !group
+                            .compute_permissions(message.from, Some(channel_id))
+                            .contains(Permissions::MOVE_MEMBERS)
+                        {
+                            anyhow::bail!("Unauthorized to move members to this channel");
+                        }
+
                         let Some(channel) = group.channels.get_mut(&channel_id) else {
                             anyhow::bail!("Channel not found");
                         };
 
                         match channel.r#type {
                             ChannelType::Voice { ref mut users, .. } => {
-                                users.remove(&message.from);
+                                users.insert(target);
                             }
                             ChannelType::Text => {
-                                anyhow::bail!("Cannot join a text channel");
+                                anyhow::bail!("Cannot move to a text channel");
                             }
                         }
 
-                        let group = group.downgrade();
-
-                        if let Some(mut user) = state.users.get_mut(&message.from) {
-                            user.state.voice = None;
+                        if let Some(ChannelType::Voice { users, .. }) = group
+                            .channels
+                            .get_mut(&from_channel)
+                            .map(|channel| &mut channel.r#type)
+                        {
+                            users.remove(&target);
                         }
-
-                        let ack = Message {
-                            id: message.id,
-                            from: group_id,
-                            to: message.from,
-                            data: Ack::ExitedVoice(channel_id),
-                            ..Message::default()
-                        };
-
-                        group.notify(ack, &state);
                     } else {
                         anyhow::bail!("Group not found");
                     }
-                }
 
-                Event::MoveToVoice(channel_id) => {
-                    if let Some(mut group) = state.groups.get_mut(&group_id) {
-                        let Some(channel) = group.channels.get_mut(&channel_id) else {
-                            anyhow::bail!("Channel not found");
-                        };
+                    let voice_type = VoiceType::Channel {
+                        group_id,
+                        channel_id,
+                    };
 
-                        match channel.r#type {
-                            ChannelType::Voice { ref mut users, .. } => {
-                                users.insert(message.from);
-                            }
-                            ChannelType::Text => {
-                                anyhow::bail!("Cannot join a text channel");
-                            }
-                        }
+                    if let Some(mut user) = state.users.get_mut(&target)
+                        && let Some(voice) = user.state.voice.as_mut()
+                    {
+                        voice.r#type = voice_type.clone();
+                    }
 
-                        let ack = Message {
-                            id: message.id,
-                            from: group_id,
-                            to: message.from,
-                            data: Ack::MovedToVoice(channel_id),
-                            ..Message::default()
-                        };
+                    audit_log::record(
+                        &state.pool,
+                        group_id,
+                        message.from,
+                        AuditAction::MoveMember,
+                        Some(target),
+                        vec![AuditChange::new("channel", from_channel, channel_id)],
+                        None,
+                    )
+                    .await;
 
-                        let group = group.downgrade();
+                    let ack = Message {
+                        id: message.id,
+                        from: group_id,
+                        to: target,
+                        data: Ack::MovedToVoice(channel_id),
+                        ..Message::default()
+                    };
 
-                        group.notify(ack, &state);
-                    } else {
-                        anyhow::bail!("Group not found");
+                    broadcast_voice_ack(state, target, &voice_type, ack);
+                }
+
+                Event::DisconnectMember => {
+                    let target = message.to;
+
+                    let channel_id = moderated_voice_channel(
+                        state,
+                        group_id,
+                        message.from,
+                        target,
+                        Permissions::MOVE_MEMBERS,
+                    )?;
+
+                    if let Some(mut group) = state.groups.get_mut(&group_id)
+                        && let Some(ChannelType::Voice { users, .. }) = group
+                            .channels
+                            .get_mut(&channel_id)
+                            .map(|channel| &mut channel.r#type)
+                    {
+                        users.remove(&target);
+                    }
+
+                    if let Some(mut user) = state.users.get_mut(&target) {
+                        user.state.voice = None;
                     }
+
+                    audit_log::record(
+                        &state.pool,
+                        group_id,
+                        message.from,
+                        AuditAction::DisconnectMember,
+                        Some(target),
+                        vec![AuditChange::new("channel", channel_id, ())],
+                        None,
+                    )
+                    .await;
+
+                    let ack = Message {
+                        id: message.id,
+                        from: group_id,
+                        to: target,
+                        data: Ack::ExitedVoice(channel_id),
+                        ..Message::default()
+                    };
+
+                    let voice_type = VoiceType::Channel {
+                        group_id,
+                        channel_id,
+                    };
+
+                    broadcast_voice_ack(state, target, &voice_type, ack);
                 }
 
                 /* ===== MEMBER ===== */
-                Event::KickUser => {
+                Event::KickUser | Event::KickUserWithReason(_) => {
+                    let reason = match message.data {
+                        Event::KickUserWithReason(reason) => Some(reason),
+                        _ => None,
+                    };
                     let target = message.to;
 
                     if let Some(group) = state.groups.get(&group_id) {
@@ -1380,6 +2925,9 @@ pub async fn handle_event(
                         if !group.members.contains_key(&target) {
                             anyhow::bail!("Target is not a member");
                         }
+                        if !group.outranks(message.from, target) {
+                            anyhow::bail!("Cannot kick a member with an equal or higher role");
+                        }
                     } else {
                         anyhow::bail!("Group not found");
                     }
@@ -1388,6 +2936,17 @@ pub async fn handle_event(
 
                     db::group::remove_member(&state.pool, group_id, target).await?;
 
+                    audit_log::record(
+                        &state.pool,
+                        group_id,
+                        message.from,
+                        AuditAction::KickUser,
+                        Some(target),
+                        Vec::new(),
+                        reason,
+                    )
+                    .await;
+
                     let ack = Message {
                         id: message.id,
                         from: group_id,
@@ -1416,7 +2975,11 @@ pub async fn handle_event(
                     }
                 }
 
-                Event::BanUser => {
+                Event::BanUser | Event::BanUserWithReason(_) => {
+                    let reason = match message.data {
+                        Event::BanUserWithReason(reason) => Some(reason),
+                        _ => None,
+                    };
                     let target = message.to;
 
                     if let Some(group) = state.groups.get(&group_id) {
@@ -1433,6 +2996,9 @@ pub async fn handle_event(
                         if !group.members.contains_key(&target) {
                             anyhow::bail!("Target is not a member");
                         }
+                        if !group.outranks(message.from, target) {
+                            anyhow::bail!("Cannot ban a member with an equal or higher role");
+                        }
                         let target_perms = group.compute_permissions(target, None);
                         if target_perms.contains(Permissions::ADMINISTRATOR) {
                             anyhow::bail!("Cannot ban another administrator");
@@ -1445,6 +3011,17 @@ pub async fn handle_event(
 
                     db::group::ban_user(&state.pool, group_id, target, message.from).await?;
 
+                    audit_log::record(
+                        &state.pool,
+                        group_id,
+                        message.from,
+                        AuditAction::BanUser,
+                        Some(target),
+                        Vec::new(),
+                        reason,
+                    )
+                    .await;
+
                     let ack = Message {
                         id: message.id,
                         from: group_id,
@@ -1474,6 +3051,78 @@ pub async fn handle_event(
                     }
                 }
 
+                Event::Timeout { duration, reason } => {
+                    let target = message.to;
+
+                    db::timeout::validate_duration(duration)?;
+
+                    if let Some(group) = state.groups.get(&group_id) {
+                        if target == group.owner {
+                            anyhow::bail!("Cannot time out the owner");
+                        }
+                        if target == message.from {
+                            anyhow::bail!("Cannot time out yourself");
+                        }
+                        let perms = group.compute_permissions(message.from, None);
+                        if !perms.contains(Permissions::MODERATE_MEMBERS) {
+                            anyhow::bail!("Unauthorized to time out");
+                        }
+                        if !group.members.contains_key(&target) {
+                            anyhow::bail!("Target is not a member");
+                        }
+                        if !group.outranks(message.from, target) {
+                            anyhow::bail!("Cannot time out a member with an equal or higher role");
+                        }
+                        let target_perms = group.compute_permissions(target, None);
+                        if target_perms.contains(Permissions::ADMINISTRATOR) {
+                            anyhow::bail!("Cannot time out an administrator");
+                        }
+                    } else {
+                        anyhow::bail!("Group not found");
+                    }
+
+                    let _lock = state.group_locks.write(group_id).await;
+
+                    let until = (duration > 0)
+                        .then(|| Utc::now() + chrono::Duration::seconds(duration as i64));
+
+                    let before = state
+                        .groups
+                        .get(&group_id)
+                        .and_then(|group| group.members.get(&target)?.timeout_until());
+
+                    db::timeout::set_timeout(&state.pool, group_id, target, until).await?;
+
+                    audit_log::record(
+                        &state.pool,
+                        group_id,
+                        message.from,
+                        AuditAction::TimeoutUser,
+                        Some(target),
+                        audit_log::change("timeout_until", before, until)
+                            .into_iter()
+                            .collect(),
+                        reason,
+                    )
+                    .await;
+
+                    if let Some(mut group) = state.groups.get_mut(&group_id) {
+                        group.set_timeout(target, until);
+
+                        let ack = Message {
+                            id: message.id,
+                            from: group_id,
+                            to: target,
+                            data: Ack::TimedOut { until },
+                            ..Message::default()
+                        };
+
+                        let group = group.downgrade();
+                        group.notify(ack, state);
+                        group.notify_permissions([target], None, state);
+                    }
+                }
+
                 Event::LeaveGroup => {
                     let target = message.from;
 
@@ -1537,6 +3186,9 @@ pub async fn handle_event(
                         if !perms.contains(Permissions::MANAGE_ROLES) {
                             anyhow::bail!("Unauthorized to create role");
                         }
+                        if !perms.contains(Permissions::from_bits_truncate(permissions)) {
+                            anyhow::bail!("Cannot grant permissions you don't have");
+                        }
                     } else {
                         anyhow::bail!("Group not found");
                     }
@@ -1552,6 +3204,26 @@ pub async fn handle_event(
                     )
                     .await?;
 
+                    let changes = [
+                        audit_log::change("name", (), &name),
+                        audit_log::change("color", (), &color),
+                        audit_log::change("permissions", (), permissions),
+                    ]
+                    .into_iter()
+                    .flatten()
+                    .collect();
+
+                    audit_log::record(
+                        &state.pool,
+                        group_id,
+                        message.from,
+                        AuditAction::CreateRole,
+                        Some(role_id),
+                        changes,
+                        None,
+                    )
+                    .await;
+
                     if let Some(mut group) = state.groups.get_mut(&group_id) {
                         group.create_role(
                             role_id,
@@ -1586,6 +3258,28 @@ pub async fn handle_event(
                         if !perms.contains(Permissions::MANAGE_ROLES) {
                             anyhow::bail!("Unauthorized to update role");
                         }
+                        if !group.can_manage_role(message.from, role) {
+                            anyhow::bail!("Cannot manage a role above your highest role");
+                        }
+                        if let Some(position) = position
+                            && message.from != group.owner
+                            && position <= group.top_role_position(message.from)
+                        {
+                            anyhow::bail!("Cannot move a role above your highest role");
+                        }
+                        if let Some(bits) = permissions {
+                            let current = match role {
+                                id(0) => group.everyone,
+                                _ => group
+                                    .roles
+                                    .get(&role)
+                                    .map_or(Permissions::empty(), |r| r.permissions),
+                            };
+                            let added = Permissions::from_bits_truncate(bits) - current;
+                            if !perms.contains(added) {
+                                anyhow::bail!("Cannot grant permissions you don't have");
+                            }
+                        }
                     } else {
                         anyhow::bail!("Group not found");
                     }
@@ -1597,9 +3291,27 @@ pub async fn handle_event(
                             anyhow::bail!("permissions required for everyone role");
                         };
 
+                        let everyone_before = state
+                            .groups
+                            .get(&group_id)
+                            .map(|group| group.everyone.bits());
+
                         db::group::update_everyone_permissions(&state.pool, group_id, perms_bits)
                             .await?;
 
+                        audit_log::record(
+                            &state.pool,
+                            group_id,
+                            message.from,
+                            AuditAction::UpdateRole,
+                            Some(role),
+                            audit_log::change("permissions", everyone_before, perms_bits)
+                                .into_iter()
+                                .collect(),
+                            None,
+                        )
+                        .await;
+
                         if let Some(mut group) = state.groups.get_mut(&group_id) {
                             group.everyone = Permissions::from_bits_truncate(perms_bits);
 
@@ -1630,6 +3342,35 @@ pub async fn handle_event(
                         return Ok(());
                     }
 
+                    let changes = state
+                        .groups
+                        .get(&group_id)
+                        .and_then(|group| {
+                            let before = group.roles.get(&role)?;
+
+                            let changes = [
+                                name.as_ref().and_then(|name| {
+                                    audit_log::change("name", before.name(), name)
+                                }),
+                                position.and_then(|position| {
+                                    audit_log::change("position", before.position(), position)
+                                }),
+                                color.as_ref().and_then(|color| {
+                                    audit_log::change("color", before.color(), color)
+                                }),
+                                permissions.and_then(|permissions| {
+                                    audit_log::change(
+                                        "permissions",
+                                        before.permissions.bits(),
+                                        permissions,
+                                    )
+                                }),
+                            ];
+
+                            Some(changes.into_iter().flatten().collect())
+                        })
+                        .unwrap_or_default();
+
                     db::group::update_role(
                         &state.pool,
                         group_id,
@@ -1641,6 +3382,17 @@ pub async fn handle_event(
                     )
                     .await?;
 
+                    audit_log::record(
+                        &state.pool,
+                        group_id,
+                        message.from,
+                        AuditAction::UpdateRole,
+                        Some(role),
+                        changes,
+                        None,
+                    )
+                    .await;
+
                     if let Some(mut group) = state.groups.get_mut(&group_id) {
                         let permissions = permissions.map(Permissions::from_bits_truncate);
 
@@ -1712,14 +3464,44 @@ pub async fn handle_event(
                         if !perms.contains(Permissions::MANAGE_ROLES) {
                             anyhow::bail!("Unauthorized to delete role");
                         }
+                        if !group.can_manage_role(message.from, role) {
+                            anyhow::bail!("Cannot manage a role above your highest role");
+                        }
                     } else {
                         anyhow::bail!("Group not found");
                     }
 
                     let _lock = state.group_locks.write(group_id).await;
 
+                    let changes = state
+                        .groups
+                        .get(&group_id)
+                        .and_then(|group| {
+                            let before = group.roles.get(&role)?;
+
+                            let changes = [
+                                audit_log::change("name", before.name(), ()),
+                                audit_log::change("color", before.color(), ()),
+                                audit_log::change("permissions", before.permissions.bits(), ()),
+                            ];
+
+                            Some(changes.into_iter().flatten().collect())
+                        })
+                        .unwrap_or_default();
+
                     db::group::delete_role(&state.pool, role).await?;
 
+                    audit_log::record(
+                        &state.pool,
+                        group_id,
+                        message.from,
+                        AuditAction::DeleteRole,
+                        Some(role),
+                        changes,
+                        None,
+                    )
+                    .await;
+
                     if let Some(mut group) = state.groups.get_mut(&group_id) {
                         group.delete_role(role);
 
@@ -1751,6 +3533,9 @@ pub async fn handle_event(
                         if !perms.contains(Permissions::MANAGE_ROLES) {
                             anyhow::bail!("Unauthorized to assign role");
                         }
+                        if !group.can_manage_role(message.from, role) {
+                            anyhow::bail!("Cannot manage a role above your highest role");
+                        }
                     } else {
                         anyhow::bail!("Group not found");
                     }
@@ -1759,6 +3544,17 @@ pub async fn handle_event(
 
                     db::group::assign_role(&state.pool, user, role, group_id).await?;
 
+                    audit_log::record(
+                        &state.pool,
+                        group_id,
+                        message.from,
+                        AuditAction::AssignRole,
+                        Some(user),
+                        vec![AuditChange::new("role", (), role)],
+                        None,
+                    )
+                    .await;
+
                     if let Some(mut group) = state.groups.get_mut(&group_id) {
                         group.assign_role(user, role);
 
@@ -1783,6 +3579,9 @@ pub async fn handle_event(
                         if !perms.contains(Permissions::MANAGE_ROLES) {
                             anyhow::bail!("Unauthorized to remove role");
                         }
+                        if !group.can_manage_role(message.from, role) {
+                            anyhow::bail!("Cannot manage a role above your highest role");
+                        }
                     } else {
                         anyhow::bail!("Group not found");
                     }
@@ -1791,6 +3590,17 @@ pub async fn handle_event(
 
                     db::group::remove_role(&state.pool, user, role, group_id).await?;
 
+                    audit_log::record(
+                        &state.pool,
+                        group_id,
+                        message.from,
+                        AuditAction::RemoveRole,
+                        Some(user),
+                        vec![AuditChange::new("role", role, ())],
+                        None,
+                    )
+                    .await;
+
                     if let Some(mut group) = state.groups.get_mut(&group_id) {
                         group.remove_role(user, role);
 
@@ -1997,6 +3807,78 @@ pub async fn handle_event(
                     .await?;
                 }
 
+                Event::StartTyping => {
+                    let channel_id = message.to;
+
+                    {
+                        let Some(group) = state.groups.get(&group_id) else {
+                            anyhow::bail!("Group not found");
+                        };
+
+                        if !matches!(
+                            group.channels.get(&channel_id).map(|c| &c.r#type),
+                            Some(ChannelType::Text)
+                        ) {
+                            anyhow::bail!("Text channel not found");
+                        }
+
+                        if !group
+                            .compute_permissions(message.from, Some(channel_id))
+                            .contains(Permissions::VIEW_CHANNEL | Permissions::SEND_MESSAGE)
+                        {
+                            anyhow::bail!("You don't have permission to send messages");
+                        }
+                    }
+
+                    let target = TypingTarget::Channel {
+                        group_id,
+                        channel_id,
+                    };
+
+                    if state.typing.start(message.from, target) {
+                        notify_typing(state, message.from, target, true);
+                    }
+                }
+
+                Event::StopTyping => {
+                    let target = TypingTarget::Channel {
+                        group_id,
+                        channel_id: message.to,
+                    };
+
+                    if state.typing.stop(message.from, target) {
+                        notify_typing(state, message.from, target, false);
+                    }
+                }
+
+                Event::Vote {
+                    message: target,
+                    option,
+                } => {
+                    db::poll::handle_vote(state, message.id, message.from, target, option, true)
+                        .await?;
+                }
+
+                Event::Unvote {
+                    message: target,
+                    option,
+                } => {
+                    db::poll::handle_vote(state, message.id, message.from, target, option, false)
+                        .await?;
+                }
+
+                Event::Pin { message: target } => {
+                    handle_pin(state, message.id, message.from, target, true).await?;
+                }
+
+                Event::Unpin { message: target } => {
+                    handle_pin(state, message.id, message.from, target, false).await?;
+                }
+
+                Event::MarkRead { message: target } => {
+                    handle_mark_read(state, message.id, message.from, target).await?;
+                }
+
                 _ => anyhow::bail!("Invalid event"),
             }
         }
@@ -11,6 +11,11 @@ pub struct Message<T> {
     pub data: T,
     #[serde(flatten)]
     pub r#type: MessageType,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thread: Option<snowflake_id>,
+    /// Seconds until a direct message disappears.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ttl: Option<u32>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Default, Debug, Copy)]
@@ -31,6 +36,8 @@ impl<T> Message<T> {
             from: self.from,
             to: self.to,
             r#type: self.r#type,
+            thread: self.thread,
+            ttl: self.ttl,
             data: f(self.data),
         }
     }
@@ -44,6 +51,8 @@ impl<T: Default> Default for Message<T> {
             to: Default::default(),
             data: Default::default(),
             r#type: Default::default(),
+            thread: Default::default(),
+            ttl: Default::default(),
         }
     }
 }
@@ -1,8 +1,15 @@
 use crate::db::message::MessageStore;
-use crate::db::message::StoredMessage;
+use crate::db::message::{
+    BulkSelection, Page, ReadState, ReadTarget, ScheduledMessage, SearchFilter, SearchScope,
+};
+use crate::db::message::{StoredMessage, StoredRevision};
 use crate::id::id;
+use crate::message::Data;
 use crate::message::snowflake::snowflake_id;
 use anyhow::Result;
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use std::path::PathBuf;
 
 pub struct MessageService {
     store: MessageStore,
@@ -25,14 +32,154 @@ impl MessageService {
         self.store.overwrite(message).await
     }
 
+    pub async fn get_many(
+        &self,
+        message_ids: Vec<snowflake_id>,
+    ) -> Result<HashMap<snowflake_id, StoredMessage>> {
+        self.store.get_many(message_ids).await
+    }
+
     pub async fn delete(&self, message_id: snowflake_id) -> Result<()> {
         self.store.delete(message_id).await
     }
 
+    pub async fn pin(&self, message: StoredMessage, pinned_by: id) -> Result<bool> {
+        self.store.pin(message, pinned_by).await
+    }
+
+    pub async fn unpin(&self, message: StoredMessage) -> Result<bool> {
+        self.store.unpin(message).await
+    }
+
+    pub async fn get_channel_pins(&self, channel_id: id) -> Result<Vec<StoredMessage>> {
+        self.store.get_channel_pins(channel_id).await
+    }
+
+    pub async fn get_dm_pins(&self, user1: id, user2: id) -> Result<Vec<StoredMessage>> {
+        self.store.get_dm_pins(user1, user2).await
+    }
+
+    pub async fn schedule(&self, scheduled: ScheduledMessage) -> Result<()> {
+        self.store.schedule(scheduled).await
+    }
+
+    pub async fn get_scheduled(&self, user_id: id) -> Result<Vec<ScheduledMessage>> {
+        self.store.get_scheduled(user_id).await
+    }
+
+    pub async fn update_scheduled(
+        &self,
+        user_id: id,
+        scheduled_id: snowflake_id,
+        data: Option<Data>,
+        due_at: Option<DateTime<Utc>>,
+    ) -> Result<Option<ScheduledMessage>> {
+        self.store
+            .update_scheduled(user_id, scheduled_id, data, due_at)
+            .await
+    }
+
+    pub async fn cancel_scheduled(&self, user_id: id, scheduled_id: snowflake_id) -> Result<bool> {
+        self.store.cancel_scheduled(user_id, scheduled_id).await
+    }
+
+    pub async fn take_due_scheduled(&self, now: DateTime<Utc>) -> Result<Vec<ScheduledMessage>> {
+        self.store.take_due_scheduled(now).await
+    }
+
+    pub async fn delete_expired(
+        &self,
+        now: DateTime<Utc>,
+        limit: usize,
+    ) -> Result<Vec<StoredMessage>> {
+        self.store.delete_expired(now, limit).await
+    }
+
+    pub async fn backup(&self, backup_dir: PathBuf, keep: usize) -> Result<()> {
+        self.store.backup(backup_dir, keep).await
+    }
+
+    pub async fn bulk_delete(
+        &self,
+        channel_id: id,
+        selection: BulkSelection,
+    ) -> Result<Vec<snowflake_id>> {
+        self.store.bulk_delete(channel_id, selection).await
+    }
+
+    pub async fn purge(
+        &self,
+        scope: SearchScope,
+        before: Option<snowflake_id>,
+        keep: Option<usize>,
+    ) -> Result<Vec<snowflake_id>> {
+        self.store.purge(scope, before, keep).await
+    }
+
+    pub async fn index_mentions(
+        &self,
+        message_id: snowflake_id,
+        target: ReadTarget,
+        users: Vec<id>,
+    ) -> Result<()> {
+        self.store.index_mentions(message_id, target, users).await
+    }
+
+    pub async fn reindex_mentions(
+        &self,
+        message_id: snowflake_id,
+        target: ReadTarget,
+        before: Vec<id>,
+        after: Vec<id>,
+    ) -> Result<()> {
+        self.store
+            .reindex_mentions(message_id, target, before, after)
+            .await
+    }
+
+    pub async fn mark_read(
+        &self,
+        user_id: id,
+        target: ReadTarget,
+        message_id: snowflake_id,
+    ) -> Result<()> {
+        self.store.mark_read(user_id, target, message_id).await
+    }
+
+    pub async fn read_states(
+        &self,
+        user_id: id,
+        targets: Vec<ReadTarget>,
+    ) -> Result<HashMap<ReadTarget, ReadState>> {
+        self.store.read_states(user_id, targets).await
+    }
+
+    pub async fn get_mentions(
+        &self,
+        user_id: id,
+        before: Option<snowflake_id>,
+        len: Option<i64>,
+    ) -> Result<Vec<StoredMessage>> {
+        self.store.get_mentions(user_id, len, before).await
+    }
+
+    pub async fn get_revisions(&self, message_id: snowflake_id) -> Result<Vec<StoredRevision>> {
+        self.store.get_revisions(message_id).await
+    }
+
     pub async fn delete_channel_messages(&self, channel_id: id) -> Result<()> {
         self.store.delete_channel_messages(channel_id).await
     }
 
+    pub async fn touch_dms(
+        &self,
+        users: Vec<id>,
+        group_dm_id: id,
+        message_id: snowflake_id,
+    ) -> Result<()> {
+        self.store.touch_dms(users, group_dm_id, message_id).await
+    }
+
     pub async fn remove_dm(&self, from: id, to: id) -> Result<()> {
         self.store.remove_dm(from, to).await
     }
@@ -40,11 +187,21 @@ impl MessageService {
     pub async fn get_channel_messages(
         &self,
         channel_id: id,
-        before: Option<snowflake_id>,
+        page: Page,
+        len: Option<i64>,
+    ) -> Result<Vec<StoredMessage>> {
+        self.store.get_channel_messages(channel_id, len, page).await
+    }
+
+    pub async fn get_thread_messages(
+        &self,
+        channel_id: id,
+        thread_id: snowflake_id,
+        page: Page,
         len: Option<i64>,
     ) -> Result<Vec<StoredMessage>> {
         self.store
-            .get_channel_messages(channel_id, len, before)
+            .get_thread_messages(channel_id, thread_id, len, page)
             .await
     }
 
@@ -52,15 +209,23 @@ impl MessageService {
         &self,
         user1: id,
         user2: id,
-        before: Option<snowflake_id>,
+        page: Page,
         len: Option<i64>,
     ) -> Result<Vec<StoredMessage>> {
         self.store
-            .get_direct_messages(user1, user2, len, before)
+            .get_direct_messages(user1, user2, len, page)
             .await
     }
 
     pub async fn get_dms(&self, user_id: id) -> Result<Vec<(id, snowflake_id)>> {
         self.store.get_dms(user_id).await
     }
+
+    pub async fn search(
+        &self,
+        scopes: Vec<SearchScope>,
+        filter: SearchFilter,
+    ) -> Result<Vec<StoredMessage>> {
+        self.store.search(scopes, filter).await
+    }
 }
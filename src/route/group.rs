@@ -1,11 +1,12 @@
+use crate::db::audit_log::{self, AuditAction, AuditLogEntry, AuditLogFilter};
 use crate::db::group::BannedUser;
 use crate::id::id;
 use crate::middleware::JwtUser;
 use crate::msgpack::MsgPack;
-use crate::state::group::{OverrideTarget, Permissions};
+use crate::state::group::{OverrideTarget, PermissionExplanation, Permissions};
 use crate::{State, db};
 use actix_web::{Error, HttpResponse, error, web};
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
 #[derive(Serialize)]
@@ -21,6 +22,12 @@ struct ChannelOverride {
     deny: u64,
 }
 
+#[derive(Deserialize)]
+struct ExplainQuery {
+    user: id,
+    channel: Option<id>,
+}
+
 fn require_ban_perm(state: &State, group_id: id, user_id: id) -> Result<(), Error> {
     let group = state
         .groups
@@ -109,6 +116,45 @@ async fn list_channel_overrides(
     Ok(MsgPack(overrides))
 }
 
+async fn explain_permissions(
+    state: State,
+    user: web::ReqData<JwtUser>,
+    path: web::Path<id>,
+    query: web::Query<ExplainQuery>,
+) -> Result<MsgPack<PermissionExplanation>, Error> {
+    let group_id = path.into_inner();
+    let query = query.into_inner();
+
+    let group = state
+        .groups
+        .get(&group_id)
+        .ok_or_else(|| error::ErrorNotFound("Group not found"))?;
+
+    if !group
+        .compute_permissions(user.id, None)
+        .contains(Permissions::MANAGE_ROLES)
+    {
+        return Err(error::ErrorForbidden(
+            "You don't have permission to explain permissions",
+        ));
+    }
+
+    if !group.members.contains_key(&query.user) {
+        return Err(error::ErrorNotFound("Member not found"));
+    }
+
+    if query
+        .channel
+        .is_some_and(|cid| !group.channels.contains_key(&cid))
+    {
+        return Err(error::ErrorNotFound("Channel not found"));
+    }
+
+    Ok(MsgPack(
+        group.explain_permissions(query.user, query.channel),
+    ))
+}
+
 async fn list_bans(
     state: State,
     user: web::ReqData<JwtUser>,
@@ -150,6 +196,17 @@ async fn unban(
         return Err(error::ErrorNotFound("User is not banned"));
     }
 
+    audit_log::record(
+        &state.pool,
+        group_id,
+        user.id,
+        AuditAction::UnbanUser,
+        Some(target),
+        Vec::new(),
+        None,
+    )
+    .await;
+
     if let Some(mut group) = state.groups.get_mut(&group_id) {
         group.remove_ban(target);
     }
@@ -157,15 +214,54 @@ async fn unban(
     Ok(HttpResponse::NoContent().finish())
 }
 
+async fn list_audit_log(
+    state: State,
+    user: web::ReqData<JwtUser>,
+    path: web::Path<id>,
+    query: web::Query<AuditLogFilter>,
+) -> Result<MsgPack<Vec<AuditLogEntry>>, Error> {
+    let group_id = path.into_inner();
+
+    {
+        let group = state
+            .groups
+            .get(&group_id)
+            .ok_or_else(|| error::ErrorNotFound("Group not found"))?;
+
+        if !group
+            .compute_permissions(user.id, None)
+            .contains(Permissions::VIEW_AUDIT_LOG)
+        {
+            return Err(error::ErrorForbidden(
+                "You don't have permission to view the audit log",
+            ));
+        }
+    }
+
+    let entries = audit_log::get(&state.pool, group_id, query.into_inner())
+        .await
+        .map_err(|e| {
+            log::error!("Error list_audit_log: {}", e);
+            error::ErrorInternalServerError("Error list_audit_log")
+        })?;
+
+    Ok(MsgPack(entries))
+}
+
 pub fn configure(cfg: &mut web::ServiceConfig) {
     cfg.service(
         web::scope("/group")
             .route("/{group_id}/bans", web::get().to(list_bans))
+            .route("/{group_id}/audit_log", web::get().to(list_audit_log))
             .route("/{group_id}/bans/{user_id}", web::delete().to(unban))
             .route(
                 "/{group_id}/roles/permissions",
                 web::get().to(list_role_permissions),
             )
+            .route(
+                "/{group_id}/permissions/explain",
+                web::get().to(explain_permissions),
+            )
             .route(
                 "/{group_id}/channels/{channel_id}/overrides",
                 web::get().to(list_channel_overrides),
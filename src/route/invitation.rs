@@ -1,3 +1,4 @@
+use crate::db::audit_log::{self, AuditAction, AuditChange};
 use crate::db::group::Invitation;
 use crate::id::id;
 use crate::message::Ack;
@@ -62,6 +63,23 @@ async fn create_invitation(
         error::ErrorInternalServerError("Error while creating invitation")
     })?;
 
+    let changes = vec![
+        AuditChange::new("code", (), &invitation.code),
+        AuditChange::new("max_uses", (), invitation.max_uses),
+        AuditChange::new("expires_at", (), invitation.expires_at),
+    ];
+
+    audit_log::record(
+        &state.pool,
+        invitation.group_id,
+        user.id,
+        AuditAction::CreateInvitation,
+        Some(invitation.id),
+        changes,
+        None,
+    )
+    .await;
+
     Ok(MsgPack(invitation))
 }
 
@@ -110,6 +128,17 @@ async fn join_invitation(
             error::ErrorInternalServerError("Error increment invitation uses")
         })?;
 
+    audit_log::record(
+        &state.pool,
+        invitation.group_id,
+        user.id,
+        AuditAction::JoinInvitation,
+        Some(invitation.id),
+        Vec::new(),
+        None,
+    )
+    .await;
+
     let ack = Message {
         from: invitation.group_id,
         to: user.id,
@@ -123,9 +152,10 @@ async fn join_invitation(
     }
 
     if let Some(mut group) = state.groups.get_mut(&invitation.group_id) {
-        group
-            .members
-            .insert(user.id, Member::new(user.id, None, vec![]));
+        group.members.insert(
+            user.id,
+            Member::new(user.id, None, vec![], None, false, false),
+        );
 
         let group = group.downgrade();
 
@@ -168,6 +198,17 @@ async fn delete_invitation(
             error::ErrorInternalServerError("Error delete_invitation")
         })?;
 
+    audit_log::record(
+        &state.pool,
+        invitation.group_id,
+        user.id,
+        AuditAction::DeleteInvitation,
+        Some(invitation_id),
+        vec![AuditChange::new("code", &invitation.code, ())],
+        None,
+    )
+    .await;
+
     Ok(HttpResponse::Ok().finish())
 }
 
@@ -1,19 +1,134 @@
 use crate::db;
+use crate::db::message::{
+    BulkSelection, MAX_BULK_DELETE, Page, ScheduledMessage, SearchFilter, SearchScope,
+    StoredRevision, search_terms,
+};
 use crate::db::reaction::Reaction;
+use crate::db::retention::Retention;
 use crate::id::id;
-use crate::message::{Ack, Message};
+use crate::message::{Ack, Message, dispatch};
 use crate::message::{Data, snowflake::snowflake_id};
 use crate::state::group::Permissions;
 use crate::{State, db::message::StoredMessage, middleware::JwtUser, msgpack::MsgPack};
 use actix_web::error;
 use actix_web::{Error, error::ErrorInternalServerError, web};
+use chrono::{DateTime, Duration, Utc};
 use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+
+fn page(
+    before: Option<snowflake_id>,
+    after: Option<snowflake_id>,
+    around: Option<snowflake_id>,
+) -> Result<Page, Error> {
+    match (before, after, around) {
+        (before, None, None) => Ok(Page::Before(before)),
+        (None, Some(after), None) => Ok(Page::After(after)),
+        (None, None, Some(around)) => Ok(Page::Around(around)),
+        _ => Err(error::ErrorBadRequest(
+            "Specify only one of before, after or around",
+        )),
+    }
+}
+
+/// Rejects group DMs the user is not a member of, other conversations pass.
+async fn check_group_dm(state: &State, user_id: id, group_dm_id: id) -> Result<(), Error> {
+    if !group_dm_id.is_group_dm() {
+        return Ok(());
+    }
+
+    let participants = db::group_dm::participants(state, user_id, group_dm_id)
+        .await
+        .map_err(|_| error::ErrorNotFound("Group DM not found"))?;
+
+    if !participants.contains(&user_id) {
+        return Err(error::ErrorForbidden(
+            "You are not a member of this group DM",
+        ));
+    }
+
+    Ok(())
+}
+
+/// Users a direct message is shared with.
+async fn participants(state: &State, message: &StoredMessage) -> Result<Vec<id>, Error> {
+    db::group_dm::participants(state, message.from, message.to)
+        .await
+        .map_err(ErrorInternalServerError)
+}
+
+/// Fills in reaction summaries and reply previews of a history page.
+async fn hydrate(state: &State, user_id: id, messages: &mut [StoredMessage]) -> Result<(), Error> {
+    let reacted: Vec<snowflake_id> = messages
+        .iter()
+        .filter(|message| message.reacted == Some(true))
+        .map(|message| message.id)
+        .collect();
+
+    let mut reactions = if reacted.is_empty() {
+        HashMap::new()
+    } else {
+        db::reaction::summaries(&state.pool, user_id, &reacted)
+            .await
+            .map_err(ErrorInternalServerError)?
+    };
+
+    let replied: HashSet<snowflake_id> = messages
+        .iter()
+        .filter_map(|message| message.data.replied())
+        .collect();
+
+    let replied = if replied.is_empty() {
+        HashMap::new()
+    } else {
+        state
+            .messages
+            .get_many(replied.into_iter().collect())
+            .await
+            .map_err(ErrorInternalServerError)?
+    };
+
+    for message in messages.iter_mut() {
+        if message.reacted == Some(true) {
+            message.reactions = Some(reactions.remove(&message.id).unwrap_or_default());
+        }
+
+        let Some(original) = message
+            .data
+            .replied()
+            .and_then(|replied_id| replied.get(&replied_id))
+        else {
+            continue;
+        };
+
+        let same_conversation = match message.group_id {
+            Some(_) => original.group_id == message.group_id && original.to == message.to,
+            None if message.to.is_group_dm() => {
+                original.group_id.is_none() && original.to == message.to
+            }
+            None => {
+                original.group_id.is_none()
+                    && original.from.sort_pair(original.to) == message.from.sort_pair(message.to)
+            }
+        };
+
+        if same_conversation {
+            message.reply_preview = Some(original.into());
+        }
+    }
+
+    Ok(())
+}
 
 #[derive(Deserialize, Debug)]
 pub struct MessagesQuery {
     user_id: id,
     len: Option<i64>,
     before: Option<snowflake_id>,
+    after: Option<snowflake_id>,
+    around: Option<snowflake_id>,
+    #[serde(default)]
+    hydrate: bool,
 }
 
 pub async fn get_messages(
@@ -21,15 +136,27 @@ pub async fn get_messages(
     user: web::ReqData<JwtUser>,
     query: web::Query<MessagesQuery>,
 ) -> Result<MsgPack<Vec<StoredMessage>>, Error> {
-    let messages = state
+    let page = page(query.before, query.after, query.around)?;
+
+    check_group_dm(&state, user.id, query.user_id).await?;
+
+    let mut messages = state
         .messages
-        .get_direct_messages(user.id, query.user_id, query.before, query.len)
+        .get_direct_messages(user.id, query.user_id, page, query.len)
         .await
         .map_err(|e| {
             log::error!("Error while getting messages: {:?}", e);
             error::ErrorInternalServerError("Error while getting messages")
         })?;
 
+    db::poll::attach_results(&state.pool, &mut messages)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    if query.hydrate {
+        hydrate(&state, user.id, &mut messages).await?;
+    }
+
     Ok(MsgPack(messages))
 }
 
@@ -39,6 +166,10 @@ pub struct ChannelMessagesQuery {
     channel_id: id,
     len: Option<i64>,
     before: Option<snowflake_id>,
+    after: Option<snowflake_id>,
+    around: Option<snowflake_id>,
+    #[serde(default)]
+    hydrate: bool,
 }
 
 pub async fn get_channel_messages(
@@ -56,15 +187,77 @@ pub async fn get_channel_messages(
         })
         .ok_or_else(|| error::ErrorUnauthorized("Don't have permissions to view this channel"))?;
 
-    let messages = state
+    let page = page(query.before, query.after, query.around)?;
+
+    let mut messages = state
         .messages
-        .get_channel_messages(query.channel_id, query.before, query.len)
+        .get_channel_messages(query.channel_id, page, query.len)
         .await
         .map_err(|e| {
             log::error!("Error while getting messages: {:?}", e);
             error::ErrorInternalServerError("Error while getting messages")
         })?;
 
+    db::poll::attach_results(&state.pool, &mut messages)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    if query.hydrate {
+        hydrate(&state, user.id, &mut messages).await?;
+    }
+
+    Ok(MsgPack(messages))
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ThreadMessagesQuery {
+    group_id: id,
+    thread_id: snowflake_id,
+    len: Option<i64>,
+    before: Option<snowflake_id>,
+    after: Option<snowflake_id>,
+    around: Option<snowflake_id>,
+    #[serde(default)]
+    hydrate: bool,
+}
+
+pub async fn get_thread_messages(
+    state: State,
+    user: web::ReqData<JwtUser>,
+    query: web::Query<ThreadMessagesQuery>,
+) -> Result<MsgPack<Vec<StoredMessage>>, Error> {
+    let channel_id = state
+        .groups
+        .get(&query.group_id)
+        .and_then(|group| {
+            let channel_id = group.threads.get(&query.thread_id)?.channel_id;
+
+            group
+                .compute_permissions(user.id, Some(channel_id))
+                .contains(Permissions::VIEW_MESSAGES | Permissions::READ_MESSAGE_HISTORY)
+                .then_some(channel_id)
+        })
+        .ok_or_else(|| error::ErrorUnauthorized("Don't have permissions to view this thread"))?;
+
+    let page = page(query.before, query.after, query.around)?;
+
+    let mut messages = state
+        .messages
+        .get_thread_messages(channel_id, query.thread_id, page, query.len)
+        .await
+        .map_err(|e| {
+            log::error!("Error while getting messages: {:?}", e);
+            error::ErrorInternalServerError("Error while getting messages")
+        })?;
+
+    db::poll::attach_results(&state.pool, &mut messages)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    if query.hydrate {
+        hydrate(&state, user.id, &mut messages).await?;
+    }
+
     Ok(MsgPack(messages))
 }
 
@@ -109,6 +302,18 @@ async fn overwrite_message(
         }
     }
 
+    if message.data.poll().is_some() || data.poll().is_some() {
+        return Err(error::ErrorBadRequest("Polls can't be edited"));
+    }
+
+    if matches!(message.data, Data::System(_)) || matches!(data, Data::System(_)) {
+        return Err(error::ErrorBadRequest("System entries can't be edited"));
+    }
+
+    let (target, before, after) = dispatch::edit_mentions(&state, &message, &data)
+        .await
+        .map_err(error::ErrorBadRequest)?;
+
     message.data = data;
 
     state
@@ -117,6 +322,12 @@ async fn overwrite_message(
         .await
         .map_err(ErrorInternalServerError)?;
 
+    state
+        .messages
+        .reindex_mentions(message.id, target, before, after)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
     let ack = Message {
         id: state.snowflake.generate(),
         data: Ack::Overwritten(Box::new(message.clone())),
@@ -128,11 +339,10 @@ async fn overwrite_message(
             group.notify(ack, &state);
         }
     } else {
-        if let Some(u) = state.users.get(&message.from) {
-            u.send_message(ack.clone());
-        }
-        if let Some(u) = state.users.get(&message.to) {
-            u.send_message(ack);
+        for participant in participants(&state, &message).await? {
+            if let Some(u) = state.users.get(&participant) {
+                u.send_message(ack.clone());
+            }
         }
     }
 
@@ -178,6 +388,12 @@ async fn delete_message(
         .await
         .map_err(ErrorInternalServerError)?;
 
+    if message.data.poll().is_some() {
+        db::poll::delete_for_messages(&state.pool, &[message.id])
+            .await
+            .map_err(ErrorInternalServerError)?;
+    }
+
     state
         .messages
         .delete(message.id)
@@ -195,17 +411,100 @@ async fn delete_message(
             group.notify(ack, &state);
         }
     } else {
-        if let Some(u) = state.users.get(&message.from) {
-            u.send_message(ack.clone());
-        }
-        if let Some(u) = state.users.get(&message.to) {
-            u.send_message(ack);
+        for participant in participants(&state, &message).await? {
+            if let Some(u) = state.users.get(&participant) {
+                u.send_message(ack.clone());
+            }
         }
     }
 
     Ok(())
 }
 
+#[derive(Deserialize, Debug)]
+pub struct BulkDeleteRequest {
+    group_id: id,
+    channel_id: id,
+    ids: Option<Vec<snowflake_id>>,
+    after: Option<snowflake_id>,
+    before: Option<snowflake_id>,
+    author: Option<id>,
+}
+
+async fn bulk_delete_messages(
+    state: State,
+    user: web::ReqData<JwtUser>,
+    MsgPack(request): MsgPack<BulkDeleteRequest>,
+) -> Result<MsgPack<Vec<snowflake_id>>, Error> {
+    state
+        .groups
+        .get(&request.group_id)
+        .filter(|group| {
+            group.channels.contains_key(&request.channel_id)
+                && group
+                    .compute_permissions(user.id, Some(request.channel_id))
+                    .contains(Permissions::VIEW_MESSAGES | Permissions::MANAGE_MESSAGES)
+        })
+        .ok_or_else(|| {
+            error::ErrorUnauthorized("You don't have permission to delete messages in this channel")
+        })?;
+
+    let selection = match request.ids {
+        Some(ids) if ids.len() > MAX_BULK_DELETE => {
+            return Err(error::ErrorBadRequest("Too many messages"));
+        }
+        Some(ids) => BulkSelection::Ids(ids),
+        None if request.after.is_none() && request.before.is_none() && request.author.is_none() => {
+            return Err(error::ErrorBadRequest("Specify ids, a range or an author"));
+        }
+        None => BulkSelection::Range {
+            after: request.after,
+            before: request.before,
+            author: request.author,
+        },
+    };
+
+    let deleted = state
+        .messages
+        .bulk_delete(request.channel_id, selection)
+        .await
+        .map_err(|e| {
+            log::error!("Error while bulk deleting messages: {:?}", e);
+            error::ErrorInternalServerError("Error while deleting messages")
+        })?;
+
+    if deleted.is_empty() {
+        return Ok(MsgPack(deleted));
+    }
+
+    db::reaction::delete_for_messages(&state.pool, &deleted)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    db::poll::delete_for_messages(&state.pool, &deleted)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    let ack = Message {
+        id: state.snowflake.generate(),
+        from: request.group_id,
+        to: request.channel_id,
+        data: Ack::BulkDeleted(deleted.clone()),
+        ..Default::default()
+    };
+
+    if let Some(group) = state.groups.get(&request.group_id) {
+        group.notify_with_permissions(
+            ack,
+            Permissions::VIEW_MESSAGES,
+            Some(request.channel_id),
+            &state,
+        );
+    }
+
+    Ok(MsgPack(deleted))
+}
+
 async fn get_message_reactions(
     state: State,
     user: web::ReqData<JwtUser>,
@@ -233,7 +532,7 @@ async fn get_message_reactions(
                 "You don't have permission to view this message",
             ));
         }
-    } else if message.from != user.id && message.to != user.id {
+    } else if !participants(&state, &message).await?.contains(&user.id) {
         return Err(error::ErrorForbidden(
             "You don't have permission to view this message",
         ));
@@ -246,6 +545,52 @@ async fn get_message_reactions(
     Ok(MsgPack(reactions))
 }
 
+async fn get_message_revisions(
+    state: State,
+    user: web::ReqData<JwtUser>,
+    path: web::Path<snowflake_id>,
+) -> Result<MsgPack<Vec<StoredRevision>>, Error> {
+    let message_id = path.into_inner();
+
+    let message = state
+        .messages
+        .get(message_id)
+        .await
+        .map_err(|_| error::ErrorNotFound("Message not found"))?;
+
+    let is_author = message.from == user.id;
+
+    if let Some(group_id) = message.group_id {
+        let group = state
+            .groups
+            .get(&group_id)
+            .ok_or_else(|| error::ErrorNotFound("Group not found"))?;
+
+        let perms = group.compute_permissions(user.id, Some(message.to));
+
+        let can_view = perms.contains(Permissions::VIEW_MESSAGES);
+        let can_manage = perms.contains(Permissions::MANAGE_MESSAGES);
+
+        if !(can_manage || (can_view && is_author)) {
+            return Err(error::ErrorForbidden(
+                "You don't have permission to view the revisions of this message",
+            ));
+        }
+    } else if !is_author {
+        return Err(error::ErrorForbidden(
+            "You are not the author of this message",
+        ));
+    }
+
+    let revisions = state
+        .messages
+        .get_revisions(message_id)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    Ok(MsgPack(revisions))
+}
+
 async fn get_message(
     state: State,
     user: web::ReqData<JwtUser>,
@@ -273,7 +618,7 @@ async fn get_message(
                 "You don't have permission to view this message",
             ));
         }
-    } else if message.from != user.id && message.to != user.id {
+    } else if !participants(&state, &message).await?.contains(&user.id) {
         return Err(error::ErrorForbidden(
             "You don't have permission to view this message",
         ));
@@ -282,6 +627,389 @@ async fn get_message(
     Ok(MsgPack(message))
 }
 
+#[derive(Deserialize, Debug)]
+pub struct SearchQuery {
+    query: Option<String>,
+    group_id: Option<id>,
+    channel_id: Option<id>,
+    user_id: Option<id>,
+    author: Option<id>,
+    before: Option<DateTime<Utc>>,
+    after: Option<DateTime<Utc>>,
+    has_attachment: Option<bool>,
+    len: Option<i64>,
+}
+
+async fn search_messages(
+    state: State,
+    user: web::ReqData<JwtUser>,
+    query: web::Query<SearchQuery>,
+) -> Result<MsgPack<Vec<StoredMessage>>, Error> {
+    let query = query.into_inner();
+
+    let scopes = match (query.user_id, query.group_id) {
+        (Some(group_dm_id), None) if group_dm_id.is_group_dm() => {
+            check_group_dm(&state, user.id, group_dm_id).await?;
+            vec![SearchScope::GroupDirect(group_dm_id)]
+        }
+        (Some(user_id), None) => {
+            let (user1, user2) = user.id.sort_pair(user_id);
+            vec![SearchScope::Direct(user1, user2)]
+        }
+        (None, Some(group_id)) => {
+            let group = state
+                .groups
+                .get(&group_id)
+                .ok_or_else(|| error::ErrorNotFound("Group not found"))?;
+
+            let can_read = |channel_id: id| {
+                group
+                    .compute_permissions(user.id, Some(channel_id))
+                    .contains(Permissions::VIEW_MESSAGES | Permissions::READ_MESSAGE_HISTORY)
+            };
+
+            match query.channel_id {
+                Some(channel_id) => {
+                    if !group.channels.contains_key(&channel_id) || !can_read(channel_id) {
+                        return Err(error::ErrorUnauthorized(
+                            "Don't have permissions to view this channel",
+                        ));
+                    }
+                    vec![SearchScope::Channel(channel_id)]
+                }
+                None => group
+                    .channels
+                    .keys()
+                    .copied()
+                    .filter(|&channel_id| can_read(channel_id))
+                    .map(SearchScope::Channel)
+                    .collect(),
+            }
+        }
+        _ => {
+            return Err(error::ErrorBadRequest("Specify either user_id or group_id"));
+        }
+    };
+
+    let terms: Vec<String> = query
+        .query
+        .as_deref()
+        .map(search_terms)
+        .unwrap_or_default()
+        .into_iter()
+        .collect();
+
+    if terms.is_empty()
+        && query.author.is_none()
+        && query.has_attachment.is_none()
+        && query.before.is_none()
+        && query.after.is_none()
+    {
+        return Err(error::ErrorBadRequest("Empty search query"));
+    }
+
+    let filter = SearchFilter {
+        terms,
+        author: query.author,
+        before: query.before.map(snowflake_id::from),
+        after: query.after.map(snowflake_id::from),
+        has_attachment: query.has_attachment,
+        limit: query.len.unwrap_or(25).clamp(1, 100) as usize,
+    };
+
+    let messages = state.messages.search(scopes, filter).await.map_err(|e| {
+        log::error!("Error while searching messages: {:?}", e);
+        error::ErrorInternalServerError("Error while searching messages")
+    })?;
+
+    Ok(MsgPack(messages))
+}
+
+#[derive(Deserialize, Debug)]
+pub struct PinsQuery {
+    group_id: Option<id>,
+    channel_id: Option<id>,
+    user_id: Option<id>,
+}
+
+async fn get_pins(
+    state: State,
+    user: web::ReqData<JwtUser>,
+    query: web::Query<PinsQuery>,
+) -> Result<MsgPack<Vec<StoredMessage>>, Error> {
+    let pins = match (query.group_id, query.channel_id, query.user_id) {
+        (Some(group_id), Some(channel_id), None) => {
+            state
+                .groups
+                .get(&group_id)
+                .filter(|group| {
+                    group.channels.contains_key(&channel_id)
+                        && group
+                            .compute_permissions(user.id, Some(channel_id))
+                            .contains(
+                                Permissions::VIEW_MESSAGES | Permissions::READ_MESSAGE_HISTORY,
+                            )
+                })
+                .ok_or_else(|| {
+                    error::ErrorUnauthorized("Don't have permissions to view this channel")
+                })?;
+
+            state.messages.get_channel_pins(channel_id).await
+        }
+        (None, None, Some(user_id)) => {
+            check_group_dm(&state, user.id, user_id).await?;
+            state.messages.get_dm_pins(user.id, user_id).await
+        }
+        _ => {
+            return Err(error::ErrorBadRequest(
+                "Specify either group_id and channel_id, or user_id",
+            ));
+        }
+    }
+    .map_err(|e| {
+        log::error!("Error while getting pins: {:?}", e);
+        error::ErrorInternalServerError("Error while getting pins")
+    })?;
+
+    Ok(MsgPack(pins))
+}
+
+#[derive(Deserialize, Debug)]
+pub struct MentionsQuery {
+    len: Option<i64>,
+    before: Option<snowflake_id>,
+}
+
+async fn get_mentions(
+    state: State,
+    user: web::ReqData<JwtUser>,
+    query: web::Query<MentionsQuery>,
+) -> Result<MsgPack<Vec<StoredMessage>>, Error> {
+    let messages = state
+        .messages
+        .get_mentions(user.id, query.before, query.len)
+        .await
+        .map_err(|e| {
+            log::error!("Error while getting mentions: {:?}", e);
+            error::ErrorInternalServerError("Error while getting mentions")
+        })?;
+
+    let mut memberships: HashMap<id, bool> = HashMap::new();
+    let mut visible = Vec::with_capacity(messages.len());
+
+    for message in messages {
+        let Some(group_id) = message.group_id else {
+            visible.push(message);
+            continue;
+        };
+
+        let can_view = match state.groups.get(&group_id) {
+            Some(group) => group
+                .compute_permissions(user.id, Some(message.to))
+                .contains(Permissions::VIEW_MESSAGES),
+            None => match memberships.get(&group_id) {
+                Some(&is_member) => is_member,
+                None => {
+                    let is_member = db::group::is_member(&state.pool, group_id, user.id)
+                        .await
+                        .map_err(ErrorInternalServerError)?;
+                    memberships.insert(group_id, is_member);
+                    is_member
+                }
+            },
+        };
+
+        if can_view {
+            visible.push(message);
+        }
+    }
+
+    Ok(MsgPack(visible))
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ConversationQuery {
+    user_id: id,
+}
+
+async fn get_dm_retention(
+    state: State,
+    user: web::ReqData<JwtUser>,
+    query: web::Query<ConversationQuery>,
+) -> Result<MsgPack<Retention>, Error> {
+    let retention = db::retention::get_dm_retention(&state.pool, user.id, query.user_id)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    Ok(MsgPack(retention))
+}
+
+const MAX_SCHEDULED: usize = 100;
+const MAX_SCHEDULE_AHEAD_DAYS: i64 = 365;
+
+fn validate_due_at(due_at: DateTime<Utc>) -> Result<(), Error> {
+    let now = Utc::now();
+
+    if due_at <= now {
+        return Err(error::ErrorBadRequest(
+            "Scheduled time must be in the future",
+        ));
+    }
+
+    if due_at > now + Duration::days(MAX_SCHEDULE_AHEAD_DAYS) {
+        return Err(error::ErrorBadRequest("Scheduled time is too far ahead"));
+    }
+
+    Ok(())
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ScheduleRequest {
+    to: id,
+    data: Data,
+    group_id: Option<id>,
+    thread: Option<snowflake_id>,
+    due_at: DateTime<Utc>,
+}
+
+async fn schedule_message(
+    state: State,
+    user: web::ReqData<JwtUser>,
+    MsgPack(request): MsgPack<ScheduleRequest>,
+) -> Result<MsgPack<ScheduledMessage>, Error> {
+    validate_due_at(request.due_at)?;
+
+    match request.group_id {
+        Some(group_id) => {
+            let can_send = match state.groups.get(&group_id) {
+                Some(group) => {
+                    group.channels.contains_key(&request.to)
+                        && group
+                            .compute_permissions(user.id, Some(request.to))
+                            .contains(Permissions::SEND_MESSAGE)
+                }
+                None => db::group::is_member(&state.pool, group_id, user.id)
+                    .await
+                    .map_err(ErrorInternalServerError)?,
+            };
+
+            if !can_send {
+                return Err(error::ErrorUnauthorized(
+                    "You don't have permission to send messages in this channel",
+                ));
+            }
+        }
+        None if request.thread.is_some() => {
+            return Err(error::ErrorBadRequest(
+                "Threads are only available in group channels",
+            ));
+        }
+        None => {}
+    }
+
+    let pending = state
+        .messages
+        .get_scheduled(user.id)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    if pending.len() >= MAX_SCHEDULED {
+        return Err(error::ErrorBadRequest("Too many scheduled messages"));
+    }
+
+    let scheduled = ScheduledMessage {
+        id: state.snowflake.generate(),
+        from: user.id,
+        to: request.to,
+        data: request.data,
+        group_id: request.group_id,
+        thread: request.thread,
+        due_at: request.due_at,
+    };
+
+    state
+        .messages
+        .schedule(scheduled.clone())
+        .await
+        .map_err(|e| {
+            log::error!("Error while scheduling message: {:?}", e);
+            error::ErrorInternalServerError("Error while scheduling message")
+        })?;
+
+    Ok(MsgPack(scheduled))
+}
+
+async fn get_scheduled_messages(
+    state: State,
+    user: web::ReqData<JwtUser>,
+) -> Result<MsgPack<Vec<ScheduledMessage>>, Error> {
+    let scheduled = state.messages.get_scheduled(user.id).await.map_err(|e| {
+        log::error!("Error while getting scheduled messages: {:?}", e);
+        error::ErrorInternalServerError("Error while getting scheduled messages")
+    })?;
+
+    Ok(MsgPack(scheduled))
+}
+
+#[derive(Deserialize, Debug)]
+pub struct EditScheduledRequest {
+    id: snowflake_id,
+    data: Option<Data>,
+    due_at: Option<DateTime<Utc>>,
+}
+
+async fn edit_scheduled_message(
+    state: State,
+    user: web::ReqData<JwtUser>,
+    MsgPack(request): MsgPack<EditScheduledRequest>,
+) -> Result<MsgPack<ScheduledMessage>, Error> {
+    if let Some(due_at) = request.due_at {
+        validate_due_at(due_at)?;
+    }
+
+    let scheduled = state
+        .messages
+        .update_scheduled(user.id, request.id, request.data, request.due_at)
+        .await
+        .map_err(|e| {
+            log::error!("Error while editing scheduled message: {:?}", e);
+            error::ErrorInternalServerError("Error while editing scheduled message")
+        })?
+        .ok_or_else(|| error::ErrorNotFound("Scheduled message not found"))?;
+
+    Ok(MsgPack(scheduled))
+}
+
+async fn cancel_scheduled_message(
+    state: State,
+    user: web::ReqData<JwtUser>,
+    MsgPack(scheduled_id): MsgPack<snowflake_id>,
+) -> Result<(), Error> {
+    let cancelled = state
+        .messages
+        .cancel_scheduled(user.id, scheduled_id)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    if !cancelled {
+        return Err(error::ErrorNotFound("Scheduled message not found"));
+    }
+
+    Ok(())
+}
+
+async fn get_disappearing_timer(
+    state: State,
+    user: web::ReqData<JwtUser>,
+    query: web::Query<ConversationQuery>,
+) -> Result<MsgPack<Option<u32>>, Error> {
+    let ttl = db::disappearing::get_timer(&state.pool, user.id, query.user_id)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    Ok(MsgPack(ttl))
+}
+
 async fn remove_dm(
     state: State,
     user: web::ReqData<JwtUser>,
@@ -305,10 +1033,25 @@ pub fn configure(cfg: &mut web::ServiceConfig) {
         web::scope("/message")
             .route("/direct", web::get().to(get_messages))
             .route("/channel", web::get().to(get_channel_messages))
+            .route("/thread", web::get().to(get_thread_messages))
+            .route("/search", web::get().to(search_messages))
+            .route("/pins", web::get().to(get_pins))
+            .route("/mentions", web::get().to(get_mentions))
+            .route("/retention", web::get().to(get_dm_retention))
+            .route("/disappearing", web::get().to(get_disappearing_timer))
+            .route("/scheduled", web::get().to(get_scheduled_messages))
             .route("/{id}/reactions", web::get().to(get_message_reactions))
+            .route("/{id}/revisions", web::get().to(get_message_revisions))
             .route("/{id}", web::get().to(get_message))
             .route("/overwrite", web::post().to(overwrite_message))
             .route("/delete", web::post().to(delete_message))
+            .route("/bulk_delete", web::post().to(bulk_delete_messages))
+            .route("/schedule", web::post().to(schedule_message))
+            .route("/scheduled/edit", web::post().to(edit_scheduled_message))
+            .route(
+                "/scheduled/cancel",
+                web::post().to(cancel_scheduled_message),
+            )
             .route("/remove_dm", web::post().to(remove_dm)),
     );
 }
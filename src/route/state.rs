@@ -1,4 +1,5 @@
 use crate::db;
+use crate::db::group_dm::GroupDm;
 use crate::id::id;
 use crate::message::snowflake::snowflake_id;
 use crate::msgpack::MsgPack;
@@ -74,6 +75,18 @@ pub async fn get_dms(
     Ok(MsgPack(dms))
 }
 
+pub async fn get_group_dms(
+    state: State,
+    user: web::ReqData<JwtUser>,
+) -> Result<MsgPack<Vec<GroupDm>>, Error> {
+    let group_dms = db::group_dm::for_user(&state, user.id).await.map_err(|e| {
+        warn!("Error while getting group dms: {}", e);
+        error::ErrorInternalServerError("Error while getting group dms")
+    })?;
+
+    Ok(MsgPack(group_dms))
+}
+
 pub async fn get_voice_direct(
     state: State,
     user: web::ReqData<JwtUser>,
@@ -121,6 +134,7 @@ pub fn configure(cfg: &mut web::ServiceConfig) {
             .route("/friends", web::get().to(get_friends))
             .route("/friend_requests", web::get().to(get_friend_requets))
             .route("/dms", web::get().to(get_dms))
+            .route("/group_dms", web::get().to(get_group_dms))
             .route("/groups", web::get().to(get_groups))
             .route("/me", web::get().to(me))
             .route("/voice_direct", web::get().to(get_voice_direct))
@@ -1,11 +1,13 @@
 use crate::State;
 use crate::db;
+use crate::db::message::{ReadState, ReadTarget};
 use crate::id::id;
 use crate::message::Ack;
 use crate::message::Event;
 use crate::message::Message;
 use crate::message::dispatch;
 use crate::message::event;
+use crate::message::snowflake::snowflake_id;
 use crate::middleware;
 use crate::msgpack;
 use crate::state::group::ChannelType;
@@ -23,7 +25,7 @@ use futures_util::{SinkExt, StreamExt};
 use log::warn;
 use once_cell::sync::Lazy;
 use socket2::{Domain, Protocol, Socket, Type};
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::net::{Ipv4Addr, SocketAddr};
 use tokio::net::{TcpListener, TcpStream};
 use tokio::time::Duration;
@@ -84,10 +86,38 @@ static WS_CONFIG: Lazy<WebSocketConfig> = Lazy::new(|| {
         .accept_unmasked_frames(true)
 });
 
+const RESUME_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// `session_id` and last received `seq` from the query of a resuming client.
+fn resume_params(query: &str) -> Option<(snowflake_id, u64)> {
+    let mut session_id = None;
+    let mut seq = None;
+
+    for (key, value) in query.split('&').filter_map(|pair| pair.split_once('=')) {
+        match key {
+            "session_id" => session_id = value.parse().ok().map(snowflake_id),
+            "seq" => seq = value.parse().ok(),
+            _ => {}
+        }
+    }
+
+    Some((session_id?, seq?))
+}
+
+/// Whether the client asked for the sequence number before every frame.
+fn seq_header_param(query: &str) -> bool {
+    query.split('&').any(|pair| pair == "seq_header=1")
+}
+
 async fn ws_handshake(stream: TcpStream, state: State) {
     let mut user_id: id = Default::default();
+    let mut resume = None;
+    let mut seq_header = false;
 
     let callback = |req: &Request, mut response: Response| -> Result<Response, ErrorResponse> {
+        resume = req.uri().query().and_then(resume_params);
+        seq_header = req.uri().query().is_some_and(seq_header_param);
+
         let proto = req
             .headers()
             .get(http::header::SEC_WEBSOCKET_PROTOCOL)
@@ -123,7 +153,7 @@ async fn ws_handshake(stream: TcpStream, state: State) {
     match tokio_tungstenite::accept_hdr_async_with_config(stream, callback, Some(*WS_CONFIG)).await
     {
         Ok(stream) => {
-            initialize_session(stream, user_id, state).await;
+            initialize_session(stream, user_id, resume, seq_header, state).await;
         }
         Err(e) => {
             warn!("WebSocket handshake failed: {:?}", e);
@@ -134,12 +164,45 @@ async fn ws_handshake(stream: TcpStream, state: State) {
 async fn initialize_session(
     stream: WebSocketStream<TcpStream>,
     user_id: id,
+    resume: Option<(snowflake_id, u64)>,
+    seq_header: bool,
     state: State,
 ) -> anyhow::Result<()> {
     let _user_lock = state.user_locks.write(user_id).await;
 
-    if let Some(mut user) = state.users.get_mut(&user_id) {
-        add_connection(stream, user.value_mut(), &state).await;
+    let resume_failed = match resume {
+        Some((session_id, seq)) => match resume_session(user_id, session_id, seq, &state) {
+            Some((handle, message_rx)) => {
+                state.tracker.spawn(handle_connection(
+                    stream,
+                    message_rx,
+                    user_id,
+                    handle,
+                    state.clone(),
+                ));
+                return Ok(());
+            }
+            None => true,
+        },
+        None => false,
+    };
+
+    let existing_dms = state.users.get(&user_id).map(|user| user.state.dms.clone());
+
+    if let Some(dms) = existing_dms {
+        let read_states = dm_read_states(&state, user_id, dms).await?;
+
+        if let Some(mut user) = state.users.get_mut(&user_id) {
+            add_connection(
+                stream,
+                user.value_mut(),
+                read_states,
+                resume_failed,
+                seq_header,
+                &state,
+            )
+            .await;
+        }
         return Ok(());
     }
 
@@ -151,17 +214,22 @@ async fn initialize_session(
             db::user::get_groups(&pool, user_id),
             db::user::get_friends(&pool, user_id),
             db::user::friend_requests(&pool, user_id),
-            db::user::outgoing_friend_requests(&pool, user_id)
+            db::user::outgoing_friend_requests(&pool, user_id),
+            db::user::get_blocks(&pool, user_id),
+            db::user::get_blocked_by(&pool, user_id)
         )
     });
 
-    let (user_res, groups_res, friends_res, incoming_res, outgoing_res) = db_tasks.await?;
+    let (user_res, groups_res, friends_res, incoming_res, outgoing_res, blocks_res, blocked_by_res) =
+        db_tasks.await?;
 
     let user = user_res?;
     let groups: Vec<id> = groups_res?;
     let friends: HashSet<id> = friends_res?.into_iter().collect();
     let incoming = incoming_res?;
     let outgoing = outgoing_res?;
+    let blocks: HashSet<id> = blocks_res?.into_iter().collect();
+    let blocked_by: HashSet<id> = blocked_by_res?.into_iter().collect();
     let dms: HashSet<id> = state
         .messages
         .get_dms(user_id)
@@ -180,29 +248,37 @@ async fn initialize_session(
         friends,
         friend_requests: incoming,
         friend_requests_sent: outgoing,
-        dms,
+        blocks,
+        blocked_by,
+        dms: dms.clone(),
         ..Default::default()
     };
 
+    let mut snapshot = user_state.clone();
+    snapshot.read_states = dm_read_states(&state, user_id, dms).await?;
+
     let session_initialized = Message {
         to: user_id,
-        data: Ack::Initialized(Box::new(user_state.clone())),
+        data: Ack::Initialized(Box::new(snapshot)),
         ..Default::default()
     };
 
     let connection_id = 1;
+    let session_id = state.snowflake.generate();
 
-    let (message_tx, message_rx) = unbounded::<Bytes>();
+    let (writer, message_rx) = user::Writer::new(seq_header);
     let (event_tx, event_rx) = unbounded::<(usize, Message<Event>)>();
 
     state.tracker.spawn(handle_events(event_rx, state.clone()));
 
-    let _ = message_tx.send(Bytes::from(msgpack!(session_initialized)));
+    send_session_start(&writer, user_id, session_id, resume_failed);
+    writer.send(Bytes::from(msgpack!(session_initialized)));
 
     let user_session = user::Session {
         connections: vec![user::Connection {
             id: connection_id,
-            writer: message_tx,
+            session_id,
+            writer: writer.clone(),
         }],
         event_tx: event_tx.clone(),
         state: user_state,
@@ -213,44 +289,142 @@ async fn initialize_session(
     state.tracker.spawn(handle_connection(
         stream,
         message_rx,
-        event_tx,
         user_id,
-        connection_id,
+        ConnectionHandle {
+            id: connection_id,
+            writer,
+            generation: 0,
+            event_tx,
+        },
         state.clone(),
     ));
 
     Ok(())
 }
 
+fn send_session_start(
+    writer: &user::Writer,
+    user_id: id,
+    session_id: snowflake_id,
+    resume_failed: bool,
+) {
+    if resume_failed {
+        writer.send(Bytes::from(msgpack!(Message {
+            to: user_id,
+            data: Ack::InvalidSession,
+            ..Default::default()
+        })));
+    }
+
+    writer.send(Bytes::from(msgpack!(Message {
+        to: user_id,
+        data: Ack::Session { session_id },
+        ..Default::default()
+    })));
+}
+
+/// The connection a stream serves, `generation` tells which resume of it.
+struct ConnectionHandle {
+    id: usize,
+    writer: user::Writer,
+    generation: u64,
+    event_tx: Sender<(usize, Message<Event>)>,
+}
+
+/// Takes over a session kept after a disconnect and queues what the client
+/// missed. `None` when the session is gone or its replay buffer has overflowed.
+fn resume_session(
+    user_id: id,
+    session_id: snowflake_id,
+    seq: u64,
+    state: &State,
+) -> Option<(ConnectionHandle, Receiver<Bytes>)> {
+    let user = state.users.get(&user_id)?;
+
+    let connection = user
+        .connections
+        .iter()
+        .find(|connection| connection.session_id == session_id)?;
+
+    let (generation, replayed, message_rx) = connection.writer.resume(seq)?;
+
+    let handle = ConnectionHandle {
+        id: connection.id,
+        writer: connection.writer.clone(),
+        generation,
+        event_tx: user.event_tx.clone(),
+    };
+
+    handle.writer.send(Bytes::from(msgpack!(Message {
+        to: user_id,
+        data: Ack::Resumed { replayed },
+        ..Default::default()
+    })));
+
+    Some((handle, message_rx))
+}
+
+async fn dm_read_states(
+    state: &State,
+    user_id: id,
+    dms: HashSet<id>,
+) -> anyhow::Result<HashMap<id, ReadState>> {
+    let targets = dms.into_iter().map(ReadTarget::Direct).collect();
+
+    Ok(state
+        .messages
+        .read_states(user_id, targets)
+        .await?
+        .into_iter()
+        .filter_map(|(target, read_state)| match target {
+            ReadTarget::Direct(peer) => Some((peer, read_state)),
+            ReadTarget::Channel(_) => None,
+        })
+        .collect())
+}
+
 async fn add_connection(
     stream: WebSocketStream<TcpStream>,
     user: &mut user::Session,
+    read_states: HashMap<id, ReadState>,
+    resume_failed: bool,
+    seq_header: bool,
     state: &State,
 ) {
     let user_id = user.state.id;
     let connection_id = user.connections.iter().map(|c| c.id).max().unwrap_or(0) + 1;
+    let session_id = state.snowflake.generate();
+
+    let (writer, message_rx) = user::Writer::new(seq_header);
 
-    let (message_tx, message_rx) = unbounded::<Bytes>();
+    let mut snapshot = user.state.clone();
+    snapshot.read_states = read_states;
 
     let session_initialized = Message {
         to: user_id,
-        data: Ack::Initialized(Box::new(user.state.clone())),
+        data: Ack::Initialized(Box::new(snapshot)),
         ..Default::default()
     };
 
-    let _ = message_tx.send(Bytes::from(msgpack!(session_initialized)));
+    send_session_start(&writer, user_id, session_id, resume_failed);
+    writer.send(Bytes::from(msgpack!(session_initialized)));
 
     user.connections.push(user::Connection {
         id: connection_id,
-        writer: message_tx,
+        session_id,
+        writer: writer.clone(),
     });
 
     state.tracker.spawn(handle_connection(
         stream,
         message_rx,
-        user.event_tx.clone(),
         user_id,
-        connection_id,
+        ConnectionHandle {
+            id: connection_id,
+            writer,
+            generation: 0,
+            event_tx: user.event_tx.clone(),
+        },
         state.clone(),
     ));
 }
@@ -258,11 +432,14 @@ async fn add_connection(
 async fn handle_connection(
     mut stream: WebSocketStream<TcpStream>,
     message_rx: Receiver<Bytes>,
-    event_tx: Sender<(usize, Message<Event>)>,
     user_id: id,
-    connection_id: usize,
+    handle: ConnectionHandle,
     state: State,
 ) {
+    let connection_id = handle.id;
+    let event_tx = &handle.event_tx;
+    let mut resumable = true;
+
     loop {
         if state.shutdown.is_cancelled() {
             break;
@@ -270,6 +447,7 @@ async fn handle_connection(
 
         if message_rx.len() > 500 {
             log::warn!("Message queue full (>500) for {user_id}, disconnecting.");
+            resumable = false;
             break;
         }
 
@@ -278,8 +456,12 @@ async fn handle_connection(
 
             incoming = stream.next() => {
                  match incoming {
+                    Some(Ok(WsMessage::Close(_))) => {
+                        resumable = false;
+                        break;
+                    }
                     Some(Ok(message)) => {
-                        if !handle_incoming(message, &mut stream, &event_tx, user_id, connection_id, &state).await {
+                        if !handle_incoming(message, &mut stream, event_tx, user_id, connection_id, &state).await {
                             break;
                         }
                     }
@@ -301,7 +483,11 @@ async fn handle_connection(
                             break;
                         }
                     }
-                    Err(_) => break,
+                    // The session was resumed on another stream
+                    Err(_) => {
+                        resumable = false;
+                        break;
+                    }
                 }
             }
         }
@@ -321,8 +507,19 @@ async fn handle_connection(
 
     let _ = stream.close(None).await;
 
+    if resumable && !state.shutdown.is_cancelled() {
+        tokio::select! {
+            _ = state.shutdown.cancelled() => {}
+            _ = tokio::time::sleep(RESUME_TIMEOUT) => {}
+        }
+    }
+
+    if handle.writer.generation() != handle.generation {
+        return;
+    }
+
     log::info!("user disconnected: {user_id}");
-    disconnect(user_id, connection_id, state).await;
+    disconnect(user_id, connection_id, handle.generation, state).await;
 }
 
 #[inline]
@@ -405,13 +602,27 @@ async fn handle_events(event_rx: Receiver<(usize, Message<Event>)>, state: State
     }
 }
 
-async fn disconnect(user_id: id, connection_id: usize, state: State) -> Result<()> {
+/// Drops a connection unless its session was resumed on another stream meanwhile.
+async fn disconnect(
+    user_id: id,
+    connection_id: usize,
+    generation: u64,
+    state: State,
+) -> Result<()> {
     let _user_lock = state.user_locks.write(user_id).await;
     let mut update_last_seen = false;
 
     if let Entry::Occupied(mut entry) = state.users.entry(user_id) {
         let user = entry.get_mut();
 
+        if user
+            .connections
+            .iter()
+            .any(|c| c.id == connection_id && c.writer.generation() != generation)
+        {
+            return Ok(());
+        }
+
         if user.connections.len() <= 1 {
             let mut user = entry.remove();
 
@@ -486,6 +697,20 @@ async fn disconnect_voice(user_id: id, voice: user::VoiceType, state: &State) ->
             }
         }
 
+        VoiceType::Direct {
+            user: group_dm_id,
+            message_id: call_message_id,
+        } if group_dm_id.is_group_dm() => {
+            event::exit_group_dm_voice(
+                state,
+                state.snowflake.generate(),
+                user_id,
+                group_dm_id,
+                call_message_id,
+            )
+            .await?;
+        }
+
         VoiceType::Direct {
             user: other_user_id,
             message_id: call_message_id,
@@ -1,8 +1,10 @@
+use crate::db::group_dm::GroupDm;
 use crate::id::id;
 use crate::lockmap::LockMap;
 use crate::message::service::MessageService;
 use crate::message::snowflake::SnowflakeGenerator;
 use crate::state::group::Group;
+use crate::state::typing::Typing;
 use crate::state::user;
 use dashmap::DashMap;
 use nohash_hasher::BuildNoHashHasher;
@@ -16,7 +18,9 @@ pub struct AppState {
     pub user_locks: LockMap<id>,
     pub groups: DashMap<id, Group, BuildNoHashHasher<id>>,
     pub group_locks: LockMap<id>,
+    pub group_dms: DashMap<id, GroupDm, BuildNoHashHasher<id>>,
     pub voice_direct: DashMap<id, HashSet<id>, BuildNoHashHasher<id>>,
+    pub typing: Typing,
     pub pool: PgPool,
     pub snowflake: SnowflakeGenerator,
     pub messages: MessageService,
@@ -1,11 +1,15 @@
 use crate::State;
+use crate::db::retention::Retention;
 use crate::id as Id;
 use crate::message::Ack;
 use crate::message::Message;
+use crate::message::data::Mentions;
+use crate::message::snowflake::snowflake_id;
 use crate::msgpack;
 use crate::state::user::Status;
 use bitflags::bitflags;
 use bytes::Bytes;
+use chrono::{DateTime, Duration, Utc};
 use derive_more::Constructor;
 use serde::Deserialize;
 use serde::Serialize;
@@ -41,6 +45,7 @@ bitflags! {
         const MUTE_MEMBERS         = 1 << 20;
         const DEAFEN_MEMBERS       = 1 << 21;
         const MOVE_MEMBERS         = 1 << 22;
+        const MODERATE_MEMBERS     = 1 << 23;
     }
 }
 
@@ -56,6 +61,11 @@ impl Permissions {
             | Permissions::SPEAK.bits()
             | Permissions::CREATE_INVITE.bits(),
     );
+
+    /// Stripped from a timed out member, whatever their roles say.
+    pub const TIMEOUT_DENY: Permissions = Permissions::from_bits_truncate(
+        Permissions::SEND_MESSAGE.bits() | Permissions::SPEAK.bits() | Permissions::CONNECT.bits(),
+    );
 }
 
 type id = Id::id;
@@ -63,6 +73,7 @@ type UserId = id;
 type RoleId = id;
 type ChannelId = id;
 type ConnectionId = usize;
+type ThreadId = snowflake_id;
 
 #[derive(Serialize, Clone, Constructor)]
 pub struct Group {
@@ -79,6 +90,9 @@ pub struct Group {
     pub subscribers: HashSet<(UserId, ConnectionId)>,
     #[serde(skip)]
     pub bans: HashSet<UserId>,
+    #[serde(skip)]
+    pub threads: HashMap<ThreadId, Thread>,
+    pub retention: Retention,
 }
 
 #[derive(Serialize, Clone, Constructor, Default)]
@@ -86,6 +100,10 @@ pub struct Member {
     id: UserId,
     name: Option<String>,
     roles: Vec<RoleId>,
+    timeout_until: Option<DateTime<Utc>>,
+    /// Set by a moderator and applied whenever the member joins a voice channel.
+    server_mute: bool,
+    server_deafen: bool,
 }
 
 impl Member {
@@ -93,6 +111,22 @@ impl Member {
         self.id
     }
 
+    pub fn timeout_until(&self) -> Option<DateTime<Utc>> {
+        self.timeout_until
+    }
+
+    pub fn is_timed_out(&self, now: DateTime<Utc>) -> bool {
+        self.timeout_until.is_some_and(|until| until > now)
+    }
+
+    pub fn server_mute(&self) -> bool {
+        self.server_mute
+    }
+
+    pub fn server_deafen(&self) -> bool {
+        self.server_deafen
+    }
+
     pub fn has_role(&self, role_id: RoleId) -> bool {
         self.roles.contains(&role_id)
     }
@@ -108,7 +142,22 @@ pub struct Role {
     pub permissions: Permissions,
 }
 
+impl Role {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    pub fn color(&self) -> &str {
+        &self.color
+    }
+}
+
 #[derive(Serialize, Clone, Constructor)]
+#[allow(clippy::too_many_arguments)]
 pub struct Channel {
     id: ChannelId,
     name: String,
@@ -118,6 +167,23 @@ pub struct Channel {
     pub r#type: ChannelType,
     #[serde(skip)]
     pub permission_overrides: Vec<PermissionOverride>,
+    pub retention: Retention,
+    /// Seconds a member has to wait between messages, 0 when off.
+    pub slow_mode: u32,
+    #[serde(skip)]
+    pub last_sent: HashMap<UserId, DateTime<Utc>>,
+}
+
+/// A thread is anchored on the message it was started from and shares its id.
+#[derive(Serialize, Clone, Constructor)]
+pub struct Thread {
+    pub id: ThreadId,
+    pub channel_id: ChannelId,
+    pub name: String,
+    pub owner: UserId,
+    pub archived: bool,
+    #[serde(skip)]
+    pub subscribers: HashSet<(UserId, ConnectionId)>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -130,6 +196,51 @@ pub enum ChannelType {
     Text,
 }
 
+pub const MAX_SLOW_MODE: u32 = 6 * 60 * 60;
+
+impl Channel {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn title(&self) -> Option<&str> {
+        self.title.as_deref()
+    }
+
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// Records a send of `user_id` unless it is still cooling down, in which case
+    /// the remaining wait is returned.
+    pub fn try_send(&mut self, user_id: UserId, now: DateTime<Utc>) -> Option<Duration> {
+        if self.slow_mode == 0 {
+            return None;
+        }
+
+        let cooldown = Duration::seconds(self.slow_mode as i64);
+
+        if let Some(&last) = self.last_sent.get(&user_id)
+            && now - last < cooldown
+        {
+            return Some(cooldown - (now - last));
+        }
+
+        self.last_sent.retain(|_, &mut last| now - last < cooldown);
+        self.last_sent.insert(user_id, now);
+
+        None
+    }
+
+    pub fn set_slow_mode(&mut self, slow_mode: u32) {
+        self.slow_mode = slow_mode;
+
+        if slow_mode == 0 {
+            self.last_sent.clear();
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, Default)]
 pub struct WatchParty {
     pub video: id,
@@ -165,6 +276,64 @@ impl WatchPartyOpt for Option<WatchParty> {
     }
 }
 
+#[derive(Serialize, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum PermissionSource {
+    Everyone,
+    Role(RoleId),
+    EveryoneOverride,
+    RoleOverride(RoleId),
+    UserOverride,
+    Owner,
+    Administrator,
+    Timeout,
+}
+
+#[derive(Serialize, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Effect {
+    Allow,
+    Deny,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Contribution {
+    pub source: PermissionSource,
+    pub effect: Effect,
+}
+
+#[derive(Serialize, Debug)]
+pub struct BitExplanation {
+    pub name: &'static str,
+    pub value: bool,
+    pub contributions: Vec<Contribution>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct PermissionExplanation {
+    pub permissions: u64,
+    pub bits: Vec<BitExplanation>,
+}
+
+/// Denies of all overrides at one level go first, so an allow at the same level wins.
+fn apply_overrides<'a>(
+    mut perms: Permissions,
+    overrides: impl Iterator<Item = (PermissionSource, &'a PermissionOverride)> + Clone,
+    trace: &mut impl FnMut(PermissionSource, Effect, Permissions),
+) -> Permissions {
+    for (source, ovr) in overrides.clone() {
+        perms &= !ovr.deny;
+        trace(source, Effect::Deny, ovr.deny);
+    }
+
+    for (source, ovr) in overrides {
+        perms |= ovr.allow;
+        trace(source, Effect::Allow, ovr.allow);
+    }
+
+    perms
+}
+
 #[derive(Serialize, Clone, Constructor)]
 pub struct PermissionOverride {
     pub target: OverrideTarget,
@@ -184,64 +353,204 @@ impl Group {
         &self,
         user_id: UserId,
         channel_id: Option<ChannelId>,
+    ) -> Permissions {
+        self.resolve_permissions(user_id, channel_id, |_, _, _| {})
+    }
+
+    /// Every bit of a member's permissions with the steps that set it, in the order
+    /// `compute_permissions` applies them, so the last step touching a bit wins.
+    pub fn explain_permissions(
+        &self,
+        user_id: UserId,
+        channel_id: Option<ChannelId>,
+    ) -> PermissionExplanation {
+        let mut steps = Vec::new();
+
+        let permissions = self.resolve_permissions(user_id, channel_id, |source, effect, bits| {
+            steps.push((source, effect, bits))
+        });
+
+        let bits = Permissions::all()
+            .iter_names()
+            .map(|(name, bit)| BitExplanation {
+                name,
+                value: permissions.contains(bit),
+                contributions: steps
+                    .iter()
+                    .filter(|(_, _, bits)| bits.contains(bit))
+                    .map(|&(source, effect, _)| Contribution { source, effect })
+                    .collect(),
+            })
+            .collect();
+
+        PermissionExplanation {
+            permissions: permissions.bits(),
+            bits,
+        }
+    }
+
+    fn resolve_permissions(
+        &self,
+        user_id: UserId,
+        channel_id: Option<ChannelId>,
+        mut trace: impl FnMut(PermissionSource, Effect, Permissions),
     ) -> Permissions {
         let Some(user) = self.members.get(&user_id) else {
             return Permissions::empty();
         };
 
         let mut perms = self.everyone;
+        trace(PermissionSource::Everyone, Effect::Allow, self.everyone);
+
         for role_id in &user.roles {
             if let Some(role) = self.roles.get(role_id) {
                 perms |= role.permissions;
+                trace(
+                    PermissionSource::Role(*role_id),
+                    Effect::Allow,
+                    role.permissions,
+                );
             }
         }
 
-        if perms.contains(Permissions::ADMINISTRATOR) || self.owner == user_id {
+        if self.owner == user_id {
+            trace(PermissionSource::Owner, Effect::Allow, Permissions::all());
             return Permissions::all();
         }
 
-        if let Some(cid) = channel_id {
-            if let Some(channel) = self.channels.get(&cid) {
-                let mut role_deny = Permissions::empty();
-                let mut role_allow = Permissions::empty();
+        if perms.contains(Permissions::ADMINISTRATOR) {
+            trace(
+                PermissionSource::Administrator,
+                Effect::Allow,
+                Permissions::all(),
+            );
+            return Permissions::all();
+        }
 
-                for ovr in &channel.permission_overrides {
-                    if let OverrideTarget::Role(rid) = ovr.target {
-                        if rid == Id::id(0) || user.roles.contains(&rid) {
-                            role_deny |= ovr.deny;
-                            role_allow |= ovr.allow;
+        if let Some(channel) = channel_id.and_then(|cid| self.channels.get(&cid)) {
+            let role_overrides =
+                channel
+                    .permission_overrides
+                    .iter()
+                    .filter_map(|ovr| match ovr.target {
+                        OverrideTarget::Role(Id::id(0)) => {
+                            Some((PermissionSource::EveryoneOverride, ovr))
                         }
-                    }
-                }
+                        OverrideTarget::Role(rid) if user.roles.contains(&rid) => {
+                            Some((PermissionSource::RoleOverride(rid), ovr))
+                        }
+                        _ => None,
+                    });
 
-                perms &= !role_deny;
-                perms |= role_allow;
+            perms = apply_overrides(perms, role_overrides, &mut trace);
 
-                let mut user_deny = Permissions::empty();
-                let mut user_allow = Permissions::empty();
+            let user_overrides = channel
+                .permission_overrides
+                .iter()
+                .filter(|ovr| ovr.target == OverrideTarget::User(user_id))
+                .map(|ovr| (PermissionSource::UserOverride, ovr));
 
-                for ovr in &channel.permission_overrides {
-                    if let OverrideTarget::User(uid) = ovr.target {
-                        if uid == user_id {
-                            user_deny |= ovr.deny;
-                            user_allow |= ovr.allow;
-                        }
-                    }
-                }
+            perms = apply_overrides(perms, user_overrides, &mut trace);
+        }
 
-                perms &= !user_deny;
-                perms |= user_allow;
-            }
+        if user.is_timed_out(Utc::now()) {
+            perms &= !Permissions::TIMEOUT_DENY;
+            trace(
+                PermissionSource::Timeout,
+                Effect::Deny,
+                Permissions::TIMEOUT_DENY,
+            );
         }
 
         perms
     }
 
+    /// Position of the highest role of a member. Roles rank in the order they are
+    /// listed, so a lower position is higher, and @everyone is below every role.
+    pub fn top_role_position(&self, user_id: UserId) -> usize {
+        self.members
+            .get(&user_id)
+            .into_iter()
+            .flat_map(|member| member.roles.iter())
+            .filter_map(|role_id| self.roles.get(role_id))
+            .map(|role| role.position)
+            .min()
+            .unwrap_or(usize::MAX)
+    }
+
+    /// Whether `actor` is above `target` in the role hierarchy. The owner is above
+    /// everyone and nobody is above the owner.
+    pub fn outranks(&self, actor: UserId, target: UserId) -> bool {
+        if target == self.owner {
+            return false;
+        }
+        if actor == self.owner {
+            return true;
+        }
+
+        self.top_role_position(actor) < self.top_role_position(target)
+    }
+
+    /// Whether `role_id` is below the highest role of `actor`, @everyone always is.
+    pub fn can_manage_role(&self, actor: UserId, role_id: RoleId) -> bool {
+        if actor == self.owner || role_id == Id::id(0) {
+            return true;
+        }
+
+        self.roles
+            .get(&role_id)
+            .is_some_and(|role| role.position > self.top_role_position(actor))
+    }
+
+    pub fn is_timed_out(&self, user_id: UserId) -> bool {
+        self.members
+            .get(&user_id)
+            .is_some_and(|member| member.is_timed_out(Utc::now()))
+    }
+
+    pub fn set_timeout(&mut self, user_id: UserId, until: Option<DateTime<Utc>>) {
+        if let Some(member) = self.members.get_mut(&user_id) {
+            member.timeout_until = until;
+        }
+    }
+
+    pub fn set_server_mute(&mut self, user_id: UserId, mute: bool) {
+        if let Some(member) = self.members.get_mut(&user_id) {
+            member.server_mute = mute;
+        }
+    }
+
+    pub fn set_server_deafen(&mut self, user_id: UserId, deafen: bool) {
+        if let Some(member) = self.members.get_mut(&user_id) {
+            member.server_deafen = deafen;
+        }
+    }
+
+    /// Members reached by the mentions who can read the channel.
+    pub fn mentioned_users(&self, mentions: &Mentions, channel_id: ChannelId) -> Vec<UserId> {
+        self.members
+            .values()
+            .filter(|m| {
+                mentions.everyone
+                    || mentions.users.contains(&m.id)
+                    || m.roles.iter().any(|r| mentions.roles.contains(r))
+            })
+            .map(|m| m.id)
+            .filter(|&uid| {
+                self.compute_permissions(uid, Some(channel_id))
+                    .contains(Permissions::VIEW_MESSAGES)
+            })
+            .collect()
+    }
+
     pub fn subscribe(&mut self, user_id: id, conn_id: ConnectionId) -> bool {
         self.subscribers.insert((user_id, conn_id))
     }
 
     pub fn unsubscribe(&mut self, user_id: id, conn_id: ConnectionId) -> bool {
+        for thread in self.threads.values_mut() {
+            thread.subscribers.remove(&(user_id, conn_id));
+        }
         self.subscribers.remove(&(user_id, conn_id))
     }
 
@@ -423,6 +732,9 @@ impl Group {
                 ChannelType::Text
             },
             permission_overrides: Vec::new(),
+            retention: Retention::default(),
+            slow_mode: 0,
+            last_sent: HashMap::new(),
         };
 
         self.channels.insert(channel_id, channel);
@@ -461,7 +773,9 @@ impl Group {
     }
 
     pub fn delete_channel(&mut self, channel_id: ChannelId) {
-        if self.channels.remove(&channel_id).is_some() {}
+        if self.channels.remove(&channel_id).is_some() {
+            self.threads.retain(|_, t| t.channel_id != channel_id);
+        }
     }
 
     pub fn set_permission_override(
@@ -493,6 +807,43 @@ impl Group {
         }
     }
 
+    // ===== THREAD =====
+
+    pub fn create_thread(
+        &mut self,
+        thread_id: ThreadId,
+        channel_id: ChannelId,
+        name: String,
+        owner: UserId,
+    ) -> &mut Thread {
+        self.threads.entry(thread_id).or_insert(Thread {
+            id: thread_id,
+            channel_id,
+            name,
+            owner,
+            archived: false,
+            subscribers: HashSet::new(),
+        })
+    }
+
+    pub fn set_thread_archived(&mut self, thread_id: ThreadId, archived: bool) {
+        if let Some(thread) = self.threads.get_mut(&thread_id) {
+            thread.archived = archived;
+        }
+    }
+
+    pub fn active_threads(&self) -> HashMap<ChannelId, Vec<Thread>> {
+        self.threads
+            .values()
+            .filter(|t| !t.archived)
+            .fold(HashMap::new(), |mut acc, thread| {
+                acc.entry(thread.channel_id)
+                    .or_insert_with(Vec::new)
+                    .push(thread.clone());
+                acc
+            })
+    }
+
     // ===== ROLE =====
     pub fn create_role(
         &mut self,
@@ -577,6 +928,12 @@ impl Group {
         self.subscribers
             .retain(|(sub_user_id, _)| *sub_user_id != user_id);
 
+        for thread in self.threads.values_mut() {
+            thread
+                .subscribers
+                .retain(|(sub_user_id, _)| *sub_user_id != user_id);
+        }
+
         for channel in self.channels.values_mut() {
             if let ChannelType::Voice {
                 users, watch_party, ..
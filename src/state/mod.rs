@@ -1,5 +1,6 @@
 pub mod app;
 pub mod group;
+pub mod typing;
 pub mod user;
 
 pub use group::Group;
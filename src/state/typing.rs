@@ -0,0 +1,148 @@
+use crate::id::id;
+use crate::message::{Ack, Message};
+use crate::state::group::Permissions;
+use crate::{State, db};
+use dashmap::{DashMap, Entry};
+use nohash_hasher::BuildNoHashHasher;
+use std::time::{Duration, Instant};
+use tokio::time;
+
+const TYPING_TIMEOUT: Duration = Duration::from_secs(8);
+const TYPING_THROTTLE: Duration = Duration::from_secs(1);
+const SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum TypingTarget {
+    Channel { group_id: id, channel_id: id },
+    Direct(id),
+}
+
+/// Who is typing where, each entry expires unless it is started again.
+#[derive(Default)]
+pub struct Typing {
+    expires: DashMap<(id, TypingTarget), Instant>,
+    last_started: DashMap<id, Instant, BuildNoHashHasher<id>>,
+}
+
+impl Typing {
+    /// Returns whether the start should be announced. Refreshing an ongoing
+    /// indicator is silent and starts beyond the per-user throttle are dropped.
+    pub fn start(&self, user_id: id, target: TypingTarget) -> bool {
+        let now = Instant::now();
+
+        if let Some(mut expires) = self.expires.get_mut(&(user_id, target)) {
+            *expires = now + TYPING_TIMEOUT;
+            return false;
+        }
+
+        match self.last_started.entry(user_id) {
+            Entry::Occupied(mut last) => {
+                if now.duration_since(*last.get()) < TYPING_THROTTLE {
+                    return false;
+                }
+                last.insert(now);
+            }
+            Entry::Vacant(last) => {
+                last.insert(now);
+            }
+        }
+
+        self.expires.insert((user_id, target), now + TYPING_TIMEOUT);
+
+        true
+    }
+
+    /// Returns whether the user was typing there.
+    pub fn stop(&self, user_id: id, target: TypingTarget) -> bool {
+        self.expires.remove(&(user_id, target)).is_some()
+    }
+
+    fn take_expired(&self) -> Vec<(id, TypingTarget)> {
+        let now = Instant::now();
+        let mut expired = Vec::new();
+
+        self.expires.retain(|&key, expires| {
+            let keep = *expires > now;
+            if !keep {
+                expired.push(key);
+            }
+            keep
+        });
+
+        self.last_started
+            .retain(|_, last| now.duration_since(*last) < TYPING_THROTTLE);
+
+        expired
+    }
+}
+
+pub fn notify_typing(state: &State, user_id: id, target: TypingTarget, typing: bool) {
+    let data = if typing {
+        Ack::StartedTyping(user_id)
+    } else {
+        Ack::StoppedTyping(user_id)
+    };
+
+    match target {
+        TypingTarget::Channel {
+            group_id,
+            channel_id,
+        } => {
+            let ack = Message {
+                id: state.snowflake.generate(),
+                from: group_id,
+                to: channel_id,
+                data,
+                ..Message::default()
+            };
+
+            if let Some(group) = state.groups.get(&group_id) {
+                group.notify_with_permissions(
+                    ack,
+                    Permissions::VIEW_CHANNEL,
+                    Some(channel_id),
+                    state,
+                );
+            }
+        }
+        TypingTarget::Direct(group_dm_id) if group_dm_id.is_group_dm() => {
+            let ack = Message {
+                id: state.snowflake.generate(),
+                from: user_id,
+                to: group_dm_id,
+                data,
+                ..Message::default()
+            };
+
+            db::group_dm::send_to_members(state, group_dm_id, ack, Some(user_id));
+        }
+        TypingTarget::Direct(peer) => {
+            let ack = Message {
+                id: state.snowflake.generate(),
+                from: user_id,
+                to: peer,
+                data,
+                ..Message::default()
+            };
+
+            if let Some(user) = state.users.get(&peer) {
+                user.send_message(ack);
+            }
+        }
+    }
+}
+
+pub async fn typing_schedular(state: State) {
+    let mut interval = time::interval(SWEEP_INTERVAL);
+
+    loop {
+        tokio::select! {
+            _ = state.shutdown.cancelled() => break,
+            _ = interval.tick() => {}
+        }
+
+        for (user_id, target) in state.typing.take_expired() {
+            notify_typing(&state, user_id, target, false);
+        }
+    }
+}
@@ -1,12 +1,21 @@
 use crate::{
+    db::message::ReadState,
     id::id,
     message::{Event, Message, NotifyCollectionExt, snowflake::snowflake_id},
     msgpack,
 };
-use bytes::Bytes;
-use flume::Sender;
+use bytes::{BufMut, Bytes, BytesMut};
+use flume::{Receiver, Sender, unbounded};
+use parking_lot::Mutex;
 use serde::{Deserialize, Serialize};
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::Arc;
+
+/// Outgoing frames kept per session for resuming, below the queue limit of a connection.
+const MAX_REPLAY_FRAMES: usize = 256;
+
+/// Length of the sequence number before each frame of a `seq_header` connection.
+const SEQ_HEADER_LEN: usize = 8;
 
 pub struct Session {
     pub state: State,
@@ -16,7 +25,92 @@ pub struct Session {
 
 pub struct Connection {
     pub id: usize,
-    pub writer: Sender<Bytes>,
+    pub session_id: snowflake_id,
+    pub writer: Writer,
+}
+
+/// Outgoing side of a connection. The n-th frame of a session has sequence
+/// number n and the latest frames are kept so a reconnecting client can resume.
+/// Clients that asked for it in the handshake get the number as a big-endian
+/// u64 before each msgpack payload, others get plain msgpack frames.
+#[derive(Clone)]
+pub struct Writer(Arc<Mutex<WriterInner>>);
+
+struct WriterInner {
+    tx: Sender<Bytes>,
+    seq: u64,
+    seq_header: bool,
+    generation: u64,
+    replay: VecDeque<Bytes>,
+}
+
+impl Writer {
+    pub fn new(seq_header: bool) -> (Self, Receiver<Bytes>) {
+        let (tx, rx) = unbounded();
+
+        let writer = Writer(Arc::new(Mutex::new(WriterInner {
+            tx,
+            seq: 0,
+            seq_header,
+            generation: 0,
+            replay: VecDeque::with_capacity(MAX_REPLAY_FRAMES),
+        })));
+
+        (writer, rx)
+    }
+
+    pub fn send(&self, bytes: Bytes) {
+        let mut inner = self.0.lock();
+
+        inner.seq += 1;
+
+        let frame = if inner.seq_header {
+            let mut frame = BytesMut::with_capacity(SEQ_HEADER_LEN + bytes.len());
+            frame.put_u64(inner.seq);
+            frame.put_slice(&bytes);
+            frame.freeze()
+        } else {
+            bytes
+        };
+
+        if inner.replay.len() == MAX_REPLAY_FRAMES {
+            inner.replay.pop_front();
+        }
+        inner.replay.push_back(frame.clone());
+
+        let _ = inner.tx.send(frame);
+    }
+
+    /// Bumped every time the session moves to a new stream.
+    pub fn generation(&self) -> u64 {
+        self.0.lock().generation
+    }
+
+    /// Moves the session to a new stream, queueing the frames after `last_seq` on it.
+    /// Returns none when those frames are no longer buffered.
+    pub fn resume(&self, last_seq: u64) -> Option<(u64, usize, Receiver<Bytes>)> {
+        let mut inner = self.0.lock();
+
+        let first_seq = inner.seq + 1 - inner.replay.len() as u64;
+
+        if last_seq > inner.seq || last_seq + 1 < first_seq {
+            return None;
+        }
+
+        let (tx, rx) = unbounded();
+
+        let skip = (last_seq + 1 - first_seq) as usize;
+        let replayed = inner.replay.len() - skip;
+
+        for frame in inner.replay.iter().skip(skip) {
+            let _ = tx.send(frame.clone());
+        }
+
+        inner.tx = tx;
+        inner.generation += 1;
+
+        Some((inner.generation, replayed, rx))
+    }
 }
 
 #[derive(Clone, Serialize, Debug, Default)]
@@ -30,10 +124,21 @@ pub struct State {
     pub friends: HashSet<id>,
     pub friend_requests: Vec<id>,
     pub friend_requests_sent: Vec<id>,
+    pub blocks: HashSet<id>,
+    pub blocked_by: HashSet<id>,
     pub dms: HashSet<id>,
     pub groups: Vec<id>,
     pub activities: Vec<Activity>,
     pub voice: Option<Voice>,
+    /// Read state of each dm, only filled in the snapshot sent with `Ack::Initialized`.
+    pub read_states: HashMap<id, ReadState>,
+}
+
+impl State {
+    /// Whether either side has blocked the other.
+    pub fn is_blocked(&self, other: id) -> bool {
+        self.blocks.contains(&other) || self.blocked_by.contains(&other)
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone, Default, Debug)]
@@ -91,6 +196,11 @@ pub struct Voice {
     pub mute: bool,
     #[serde(default)]
     pub deafen: bool,
+    /// Set by a moderator, the member can't lift it themselves.
+    #[serde(default)]
+    pub server_mute: bool,
+    #[serde(default)]
+    pub server_deafen: bool,
 }
 
 #[derive(Clone, Debug, Serialize)]